@@ -21,12 +21,22 @@ struct User {
 struct AuthStorage {
     users: HashMap<Principal, User>,
     sessions: HashMap<Principal, u64>, // Principal -> Expiry timestamp
+    admin: Option<Principal>,
+    governance_canister: Option<Principal>, // Allowed to grant roles once a proposal passes
 }
 
 thread_local! {
     static STATE: std::cell::RefCell<AuthStorage> = std::cell::RefCell::new(AuthStorage::default());
 }
 
+#[init]
+fn init() {
+    let caller = ic_cdk::caller();
+    STATE.with(|state| {
+        state.borrow_mut().admin = Some(caller);
+    });
+}
+
 #[query]
 fn is_authenticated() -> bool {
     let caller = ic_cdk::caller();
@@ -48,16 +58,13 @@ fn login(username: String) -> bool {
         let mut state = state.borrow_mut();
         
         // Create user if not exists
-        if !state.users.contains_key(&caller) {
-            let user = User {
-                principal: caller,
-                username: username.clone(),
-                email: format!("{}@example.com", username),
-                created_at: time(),
-                roles: vec!["user".to_string()],
-            };
-            state.users.insert(caller, user);
-        }
+        state.users.entry(caller).or_insert_with(|| User {
+            principal: caller,
+            username: username.clone(),
+            email: format!("{}@example.com", username),
+            created_at: time(),
+            roles: vec!["user".to_string()],
+        });
         
         // Set session expiry (30 days from now)
         let expiry = time() + 30 * 24 * 60 * 60 * 1_000_000_000;
@@ -98,6 +105,55 @@ fn get_user_info() -> User {
     })
 }
 
+#[update]
+fn grant_role(principal: Principal, role: String) -> User {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.governance_canister != Some(caller) {
+            ic_cdk::trap("Only the governance canister can grant roles");
+        }
+        
+        let user = match state.users.get_mut(&principal) {
+            Some(user) => user,
+            None => ic_cdk::trap("User not found"),
+        };
+        if !user.roles.contains(&role) {
+            user.roles.push(role);
+        }
+        user.clone()
+    })
+}
+
+// Every registered member, one principal per user account. Used by
+// governance for one-member-one-vote proposals.
+#[query]
+fn get_member_principals() -> Vec<Principal> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        if state.governance_canister != Some(caller) && state.admin != Some(caller) {
+            ic_cdk::trap("Not authorized to list members");
+        }
+        state.users.keys().cloned().collect()
+    })
+}
+
+#[update]
+fn set_governance_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the governance canister");
+        }
+        state.governance_canister = Some(canister_id);
+    });
+}
+
 // Required for candid interface generation
 candid::export_service!();
 #[query(name = "__get_candid_interface_tmp_hack")]
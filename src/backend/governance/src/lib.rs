@@ -1,31 +1,37 @@
 
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Principal, Reserved};
+use ic_cdk::api::call::RejectionCode;
 use ic_cdk::api::time;
 use ic_cdk::export::{
     candid,
     serde::Serialize,
 };
 use ic_cdk_macros::*;
+use sha2::{Digest, Sha256};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-#[derive(CandidType, Clone, Deserialize, Serialize)]
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq)]
 pub enum ProposalStatus {
-    Active,
-    Passed,
+    Draft,      // only visible to the creator, still editable
+    Discussion, // public, open for comments and amendments
+    Active,     // voting
+    Timelocked, // passed, waiting before it can take effect
+    Passed,     // executable
     Rejected,
     Executed,
     Canceled,
+    Vetoed, // stopped by a guardian during the timelock
 }
 
-#[derive(CandidType, Clone, Deserialize, Serialize)]
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq)]
 pub enum VoteType {
     Yes,
     No,
     Abstain,
 }
 
-#[derive(CandidType, Clone, Deserialize, Serialize)]
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum ProposalType {
     FeatureRequest,
     BudgetAllocation,
@@ -35,6 +41,148 @@ pub enum ProposalType {
     Other,
 }
 
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum ApprovalThreshold {
+    SimpleMajority, // more than half of yes + no
+    SuperMajority,  // at least two thirds of yes + no
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq)]
+pub enum PowerModel {
+    TokenWeighted,
+    Quadratic,        // square root of each registered member's token power
+    OneMemberOneVote, // every registered member in the auth canister counts once
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct VotingRule {
+    proposal_type: ProposalType,
+    quorum_percent: f64, // yes + no power needed, as a share of the snapshot supply
+    threshold: ApprovalThreshold,
+    min_voting_days: u64,
+    max_voting_days: u64,
+    discussion_days: u64,
+    timelock_days: u64,
+    power_model: PowerModel,
+}
+
+// The rules proposals are created, submitted and voted under
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct GovernanceParameters {
+    proposal_threshold: u64, // tokens, liquid or staked, needed to create a proposal
+    proposal_deposit: u64,
+    max_open_proposals: u64, // per member, in discussion or voting
+    reveal_period_days: u64, // for secret ballots
+    voting_rules: Vec<VotingRule>, // one per proposal type
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct GovernanceConfig {
+    version: u64,
+    parameters: GovernanceParameters,
+    updated_at: u64,
+    updated_by: Option<String>, // proposal that set it, None for the initial configuration
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq)]
+pub enum DepositStatus {
+    Unpaid, // draft, not yet submitted
+    Locked,
+    Refunded,
+    Forfeited,
+}
+
+// Mirrors the loans canister's loan types
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum LoanType {
+    Personal,
+    Business,
+    Education,
+    Housing,
+    Agriculture,
+    Medical,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanRate {
+    loan_type: LoanType,
+    interest_rate: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct TrancheSpec {
+    amount: f64,
+    milestone: String,
+}
+
+// What a proposal does once it passes and is executed
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum ProposalAction {
+    SetLoanInterestRates { rates: Vec<LoanRate> },
+    SetWalletInterestRate { monthly_percent: f64 },
+    MintTokens { recipient: Principal, amount: u64 },
+    GrantRole { principal: Principal, role: String },
+    SetGuardian { principal: Principal, enabled: bool },
+    SetVotingRule { rule: VotingRule },
+    // Rules for proposal types left out keep their current values
+    SetGovernanceConfig { parameters: GovernanceParameters },
+    // Reserves the whole budget and pays the first tranche
+    AllocateBudget { recipient: Principal, purpose: String, tranches: Vec<TrancheSpec> },
+    // Pays a later tranche once its milestone has been reached
+    ReleaseTranche { allocation_id: String, tranche: u64 },
+    // Funds an epoch's participation rewards: wallet credit is reserved in
+    // the treasury, tokens are minted as members claim them
+    FundRewardPool { epoch: u64, kind: RewardKind, amount: u64 },
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum TrancheStatus {
+    Pending,
+    Processing, // payout call in flight
+    Released { released_at: u64 },
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Tranche {
+    amount: f64,
+    milestone: String,
+    milestone_report: Option<String>,
+    status: TrancheStatus,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct BudgetAllocation {
+    id: String,
+    proposal_id: String,
+    recipient: Principal,
+    purpose: String,
+    tranches: Vec<Tranche>,
+    created_at: u64,
+}
+
+// How the treasury canister pays a member: budget payouts and rewards are
+// credited to the wallet differently
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum PayoutKind {
+    Budget,
+    Reward,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum ExecutionResult {
+    Succeeded,
+    Failed { reason: String },
+}
+
+// Canisters that proposal actions call into
+#[derive(CandidType, Clone, Default, Deserialize, Serialize)]
+pub struct ExternalCanisters {
+    auth: Option<Principal>,
+    wallet: Option<Principal>,
+    loans: Option<Principal>,
+    treasury: Option<Principal>, // holds the funds budgets and wallet rewards are paid from
+}
+
 #[derive(CandidType, Clone, Deserialize, Serialize)]
 pub struct Proposal {
     id: String,
@@ -48,9 +196,95 @@ pub struct Proposal {
     yes_votes: u64,
     no_votes: u64,
     abstain_votes: u64,
-    min_votes_required: u64,
+    min_votes_required: u64, // quorum in voting power, fixed from the unboosted eligible supply at submission
+    quorum_percent: f64,
+    approval_threshold: ApprovalThreshold,
     execution_timestamp: Option<u64>,
     created_at: u64,
+    snapshot_time: u64,
+    snapshot_height: u64, // token ledger height the voting power was read at
+    snapshot_total_power: u64,
+    action: Option<ProposalAction>,
+    execution_result: Option<ExecutionResult>,
+    deposit: u64,
+    deposit_status: DepositStatus,
+    version: u32, // bumped on each amendment
+    voting_period_days: u64,
+    timelock_days: u64,
+    submitted_at: Option<u64>,
+    executable_at: Option<u64>, // end of the timelock once passed
+    veto: Option<Veto>,
+    multi_option: Option<MultiOption>, // None for yes/no proposals
+    option_result: Option<OptionResult>,
+    power_model: PowerModel,
+    secret_ballot: bool, // votes are committed as hashes and revealed after voting
+    reveal_end: Option<u64>, // end of the reveal phase for secret ballots
+    config_version: u64, // governance configuration in force at submission (creation for drafts)
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Veto {
+    guardian: Principal,
+    reason: String,
+    vetoed_at: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum ProposalSort {
+    CreatedAt,
+    VotingEnd,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProposalQuery {
+    status: Option<ProposalStatus>,
+    proposal_type: Option<ProposalType>,
+    creator: Option<Principal>,
+    search: Option<String>, // keywords that must all appear in the title or description
+    sort: ProposalSort,
+    descending: bool,
+    cursor: Option<String>, // next_cursor of the previous page
+    limit: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProposalPage {
+    proposals: Vec<Proposal>,
+    next_cursor: Option<String>, // None on the last page
+    total: u64, // proposals matching the filters across all pages
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProposalRevision {
+    version: u32,
+    description: String,
+    note: String, // what changed and why
+    revised_at: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq)]
+pub enum Reaction {
+    Agree,
+    Disagree,
+    Insightful,
+    Question,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct CommentReaction {
+    user: Principal,
+    reaction: Reaction,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Comment {
+    id: u64,
+    proposal_id: String,
+    author: Principal,
+    parent_id: Option<u64>, // the comment this replies to
+    body: String,
+    created_at: u64,
+    reactions: Vec<CommentReaction>, // at most one per user
 }
 
 #[derive(CandidType, Clone, Deserialize, Serialize)]
@@ -58,272 +292,4438 @@ pub struct UserVote {
     proposal_id: String,
     user: Principal,
     vote_type: VoteType,
-    voting_power: u64,
+    voting_power: u64, // own and delegated power the vote currently carries
+    timestamp: u64,
+}
+
+// One entry per cast, changed or retracted vote
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct VoteChange {
+    proposal_id: String,
+    user: Principal,
+    previous: Option<VoteType>,
+    current: Option<VoteType>, // None when the vote was retracted
+    previous_choices: Option<Vec<u32>>, // multi-option ballots
+    current_choices: Option<Vec<u32>>,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq)]
+pub enum OptionVotingMethod {
+    Plurality,    // one choice, most power wins
+    Approval,     // any number of choices, most power wins
+    RankedChoice, // ordered choices, instant runoff
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct MultiOption {
+    method: OptionVotingMethod,
+    options: Vec<String>,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct OptionBallot {
+    proposal_id: String,
+    user: Principal,
+    choices: Vec<u32>, // option indexes, in order of preference for ranked choice
     timestamp: u64,
 }
 
+// What a secret ballot commits to: a yes/no vote or option choices
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum SecretBallot {
+    Vote(VoteType),
+    Options(Vec<u32>),
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct VoteCommitment {
+    proposal_id: String,
+    user: Principal,
+    commitment: Vec<u8>, // sha256 of the ballot, see commitment_hash
+    committed_at: u64,
+    revealed: bool,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct OptionCount {
+    option: u32,
+    power: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct TallyRound {
+    round: u32,
+    counts: Vec<OptionCount>,
+    exhausted: u64, // power on ballots with no remaining choice
+    eliminated: Option<u32>,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct OptionResult {
+    participating_power: u64,
+    rounds: Vec<TallyRound>,
+    winner: Option<u32>, // None on a tie
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Delegation {
+    delegator: Principal,
+    delegate: Principal,
+    proposal_type: Option<ProposalType>, // None delegates every proposal type
+    created_at: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum TokenTxKind {
+    Mint,
+    Transfer,
+    Stake,
+    Unstake, // cool-down started
+    StakeWithdrawal,
+    ProposalDeposit,
+    DepositRefund,
+    DepositForfeit, // burned
+    Reward,         // minted for governance participation
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct TokenTxRecord {
+    height: u64,
+    kind: TokenTxKind,
+    from: Option<Principal>,
+    to: Option<Principal>,
+    amount: u64,
+    stake_id: Option<u64>,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct TokenAllocation {
+    recipient: Principal,
+    amount: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Stake {
+    id: u64,
+    owner: Principal,
+    amount: u64,
+    staked_at: u64,
+    lock_until: u64,
+    boost_percent: u64, // extra voting power until lock_until
+    unstake_requested_at: Option<u64>,
+    withdrawable_at: Option<u64>, // end of the cool-down, once unstaking started
+}
+
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub enum RewardKind {
+    #[default]
+    GovernanceTokens,
+    WalletCredit, // paid from the treasury into members' wallets
+}
+
+// An epoch's reward pool, as approved by a FundRewardPool proposal
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct RewardPool {
+    epoch: u64,
+    kind: RewardKind,
+    amount: u64, // tokens, or whole wallet currency units
+    proposal_id: String,
+    claimed: u64,    // minted or paid out so far, never more than the amount
+    unreleased: u64, // wallet credit left undistributed at close and still reserved in the treasury
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct RewardEpoch {
+    epoch: u64,
+    ends_at: u64,
+    proposals: u64, // proposals decided during the epoch
+    voters: u64,
+    kind: RewardKind,
+    pool: u64,
+    distributed: u64, // less than the pool after rounding
+    closed_at: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum RewardStatus {
+    Claimable,
+    Processing, // wallet payout in flight
+    Claimed { claimed_at: u64 },
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Reward {
+    epoch: u64,
+    member: Principal,
+    kind: RewardKind,
+    amount: u64,
+    proposals_voted: u64,
+    participation_percent: f64, // share of the epoch's decided proposals voted on
+    status: RewardStatus,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct RewardClaim {
+    tokens: u64,
+    wallet_credit: u64,
+    error: Option<String>, // why a wallet payout failed; those rewards stay claimable
+}
+
+// Voting power frozen when a proposal is created, with each holder's
+// delegate for the proposal's type resolved at the same moment
+#[derive(Default)]
+struct VotingSnapshot {
+    powers: HashMap<Principal, u64>,
+    delegates: HashMap<Principal, Principal>,
+}
+
+#[derive(Default)]
+struct VoterParticipation {
+    proposals_voted: u64,
+    power: u64, // own snapshot power summed over the proposals voted on
+}
+
+// Votes on the proposals decided during an epoch that has not been closed
+#[derive(Default)]
+struct EpochParticipation {
+    proposals: u64,
+    voters: HashMap<Principal, VoterParticipation>,
+}
+
 #[derive(Default)]
 struct GovernanceStorage {
     proposals: HashMap<String, Proposal>,
     votes: HashMap<String, Vec<UserVote>>, // proposal_id -> votes
     user_votes: HashMap<Principal, HashMap<String, UserVote>>, // user -> proposal_id -> vote
-    token_balances: HashMap<Principal, u64>, // Simple voting token balance
+    vote_history: HashMap<String, Vec<VoteChange>>, // proposal_id -> changes in order
+    option_ballots: HashMap<String, HashMap<Principal, OptionBallot>>, // proposal_id -> voter -> ballot
+    commitments: HashMap<String, HashMap<Principal, VoteCommitment>>, // proposal_id -> voter -> secret ballot hash
+    token_balances: HashMap<Principal, u64>, // Liquid (unstaked) token balance
+    token_ledger: Vec<TokenTxRecord>,
+    stakes: HashMap<u64, Stake>,
+    delegations: HashMap<Principal, Vec<Delegation>>, // delegator -> at most one delegation per scope
+    snapshots: HashMap<String, VotingSnapshot>, // proposal_id -> voting power at creation
+    ledger_height: u64, // number of token ledger entries
+    admin: Option<Principal>,
+    guardians: HashSet<Principal>, // may veto passed proposals during their timelock
+    canisters: ExternalCanisters,
+    executing: HashSet<String>, // proposals with an execution call in flight
+    configs: Vec<GovernanceConfig>, // every configuration version, the one in force last
+    allocations: HashMap<String, BudgetAllocation>,
+    revisions: HashMap<String, Vec<ProposalRevision>>, // proposal_id -> revisions, oldest first
+    comments: HashMap<String, Vec<Comment>>, // proposal_id -> thread in posting order
+    reward_pools: BTreeMap<u64, RewardPool>, // epoch -> pool funded by a proposal
+    epoch_participation: HashMap<u64, EpochParticipation>,
+    reward_epochs: BTreeMap<u64, RewardEpoch>, // closed epochs
+    rewards: HashMap<Principal, Vec<Reward>>, // member -> rewards in the order epochs were closed
     next_proposal_id: u64,
+    next_stake_id: u64,
+    next_allocation_id: u64,
+    next_comment_id: u64,
 }
 
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const DEFAULT_PROPOSAL_THRESHOLD: u64 = 100;
+const DEFAULT_PROPOSAL_DEPOSIT: u64 = 50;
+const DEFAULT_MAX_OPEN_PROPOSALS: u64 = 3;
+const DEFAULT_REVEAL_PERIOD_DAYS: u64 = 2;
+const MAX_COMMENT_LENGTH: usize = 2_000;
+const MAX_OPTIONS: usize = 10;
+const MAX_PAGE_SIZE: u64 = 100;
+const UNSTAKE_COOLDOWN_DAYS: u64 = 7;
+const MAX_LOCK_DAYS: u64 = 4 * 365;
+const REWARD_EPOCH_DAYS: u64 = 30;
+
+// Voting power boost by minimum lock period in days
+const LOCK_BOOSTS: [(u64, u64); 5] = [(0, 0), (30, 10), (90, 25), (180, 50), (365, 100)];
+
+const ALL_PROPOSAL_TYPES: [ProposalType; 6] = [
+    ProposalType::FeatureRequest,
+    ProposalType::BudgetAllocation,
+    ProposalType::PolicyChange,
+    ProposalType::MembershipRule,
+    ProposalType::TokenIssuance,
+    ProposalType::Other,
+];
+
 thread_local! {
     static STATE: RefCell<GovernanceStorage> = RefCell::new(GovernanceStorage::default());
 }
 
-#[update]
-fn create_proposal(title: String, description: String, proposal_type: ProposalType, voting_period_days: u64) -> Proposal {
+#[init]
+fn init() {
     let caller = ic_cdk::caller();
-    
-    // Check if caller has sufficient tokens to create a proposal (e.g., 100 tokens)
     STATE.with(|state| {
-        let state = state.borrow();
-        let balance = state.token_balances.get(&caller).cloned().unwrap_or(0);
-        
-        if balance < 100 {
-            ic_cdk::trap("Insufficient tokens to create proposal. Need at least 100 tokens.");
-        }
+        let mut state = state.borrow_mut();
+        state.admin = Some(caller);
+        state.guardians.insert(caller);
+        state.configs.push(GovernanceConfig {
+            version: 1,
+            parameters: default_parameters(),
+            updated_at: time(),
+            updated_by: None,
+        });
     });
+}
+
+fn default_voting_rule(proposal_type: &ProposalType) -> VotingRule {
+    let (quorum_percent, threshold, min_voting_days, max_voting_days, discussion_days, timelock_days) = match proposal_type {
+        ProposalType::FeatureRequest => (10.0, ApprovalThreshold::SimpleMajority, 3, 14, 1, 1),
+        ProposalType::BudgetAllocation => (20.0, ApprovalThreshold::SimpleMajority, 5, 21, 2, 2),
+        ProposalType::PolicyChange => (20.0, ApprovalThreshold::SuperMajority, 7, 30, 3, 3),
+        ProposalType::MembershipRule => (20.0, ApprovalThreshold::SuperMajority, 7, 30, 3, 2),
+        ProposalType::TokenIssuance => (30.0, ApprovalThreshold::SuperMajority, 7, 30, 3, 3),
+        ProposalType::Other => (10.0, ApprovalThreshold::SimpleMajority, 3, 14, 1, 1),
+    };
     
-    // Calculate voting period in nanoseconds
-    let now = time();
-    let voting_end = now + (voting_period_days * 24 * 60 * 60 * 1_000_000_000);
+    // Membership decisions are made by members as equals, not by token weight
+    let power_model = match proposal_type {
+        ProposalType::MembershipRule => PowerModel::OneMemberOneVote,
+        _ => PowerModel::TokenWeighted,
+    };
     
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        let proposal_id = format!("PROP-{}", state.next_proposal_id);
-        state.next_proposal_id += 1;
-        
-        let proposal = Proposal {
-            id: proposal_id.clone(),
-            creator: caller,
-            title,
-            description,
-            proposal_type,
-            voting_start: now,
-            voting_end,
-            status: ProposalStatus::Active,
-            yes_votes: 0,
-            no_votes: 0,
-            abstain_votes: 0,
-            min_votes_required: 1000, // Example: require 1000 total votes for a valid proposal
-            execution_timestamp: None,
-            created_at: now,
-        };
-        
-        state.proposals.insert(proposal_id, proposal.clone());
-        proposal
-    })
+    VotingRule {
+        proposal_type: proposal_type.clone(),
+        quorum_percent,
+        threshold,
+        min_voting_days,
+        max_voting_days,
+        discussion_days,
+        timelock_days,
+        power_model,
+    }
 }
 
-#[query]
-fn get_proposals() -> Vec<Proposal> {
-    STATE.with(|state| {
-        let state = state.borrow();
-        state.proposals.values().cloned().collect()
-    })
+fn default_parameters() -> GovernanceParameters {
+    GovernanceParameters {
+        proposal_threshold: DEFAULT_PROPOSAL_THRESHOLD,
+        proposal_deposit: DEFAULT_PROPOSAL_DEPOSIT,
+        max_open_proposals: DEFAULT_MAX_OPEN_PROPOSALS,
+        reveal_period_days: DEFAULT_REVEAL_PERIOD_DAYS,
+        voting_rules: ALL_PROPOSAL_TYPES.iter().map(default_voting_rule).collect(),
+    }
 }
 
-#[query]
-fn get_proposal(proposal_id: String) -> Option<Proposal> {
-    STATE.with(|state| {
-        let state = state.borrow();
-        state.proposals.get(&proposal_id).cloned()
-    })
+fn current_config(state: &GovernanceStorage) -> &GovernanceConfig {
+    match state.configs.last() {
+        Some(config) => config,
+        None => ic_cdk::trap("Governance configuration is not initialized"),
+    }
 }
 
-#[update]
-fn vote(proposal_id: String, vote_type: VoteType) -> UserVote {
-    let caller = ic_cdk::caller();
-    
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // Check if proposal exists and is active
-        let proposal = match state.proposals.get_mut(&proposal_id) {
-            Some(p) => p,
-            None => ic_cdk::trap("Proposal not found"),
-        };
-        
-        if matches!(proposal.status, ProposalStatus::Active) == false {
-            ic_cdk::trap("Proposal is not active");
-        }
-        
-        let now = time();
-        if now > proposal.voting_end {
-            ic_cdk::trap("Voting period has ended");
-        }
-        
-        // Get user's voting power (token balance)
-        let voting_power = state.token_balances.get(&caller).cloned().unwrap_or(0);
-        if voting_power == 0 {
-            ic_cdk::trap("No voting power (zero tokens)");
-        }
-        
-        // Check if user has already voted
-        if let Some(user_proposals) = state.user_votes.get(&caller) {
-            if user_proposals.contains_key(&proposal_id) {
-                ic_cdk::trap("Already voted on this proposal");
-            }
-        }
-        
-        // Record the vote
-        let user_vote = UserVote {
-            proposal_id: proposal_id.clone(),
-            user: caller,
-            vote_type: vote_type.clone(),
-            voting_power,
-            timestamp: now,
-        };
-        
-        // Update proposal vote counts
-        match vote_type {
-            VoteType::Yes => {
-                proposal.yes_votes += voting_power;
-            },
-            VoteType::No => {
-                proposal.no_votes += voting_power;
-            },
-            VoteType::Abstain => {
-                proposal.abstain_votes += voting_power;
-            },
-        };
-        
-        // Record vote in proposal's vote history
-        state.votes.entry(proposal_id.clone()).or_insert_with(Vec::new).push(user_vote.clone());
-        
-        // Record vote in user's vote history
-        state.user_votes.entry(caller).or_insert_with(HashMap::new).insert(proposal_id, user_vote.clone());
-        
-        // Check if voting has reached conclusion conditions
-        let total_votes = proposal.yes_votes + proposal.no_votes + proposal.abstain_votes;
-        if total_votes >= proposal.min_votes_required {
-            if proposal.yes_votes > proposal.no_votes {
-                proposal.status = ProposalStatus::Passed;
-            } else {
-                proposal.status = ProposalStatus::Rejected;
-            }
-        }
-        
-        user_vote
-    })
+fn voting_rule_in(parameters: &GovernanceParameters, proposal_type: &ProposalType) -> VotingRule {
+    parameters
+        .voting_rules
+        .iter()
+        .find(|r| r.proposal_type == *proposal_type)
+        .cloned()
+        .unwrap_or_else(|| default_voting_rule(proposal_type))
 }
 
-#[query]
-fn get_user_votes() -> Vec<UserVote> {
-    let caller = ic_cdk::caller();
-    
-    STATE.with(|state| {
-        let state = state.borrow();
-        match state.user_votes.get(&caller) {
-            Some(votes) => votes.values().cloned().collect(),
-            None => Vec::new(),
-        }
-    })
+fn voting_rule_for(state: &GovernanceStorage, proposal_type: &ProposalType) -> VotingRule {
+    voting_rule_in(&current_config(state).parameters, proposal_type)
 }
 
-#[query]
-fn get_proposal_votes(proposal_id: String) -> Vec<UserVote> {
-    STATE.with(|state| {
-        let state = state.borrow();
-        match state.votes.get(&proposal_id) {
-            Some(votes) => votes.clone(),
-            None => Vec::new(),
+// Records a new configuration version set by an executed proposal. Voting
+// rules for proposal types not listed keep their current values.
+fn apply_parameters(state: &mut GovernanceStorage, mut parameters: GovernanceParameters, proposal_id: &str) {
+    let current = &current_config(state).parameters;
+    let kept: Vec<VotingRule> = current
+        .voting_rules
+        .iter()
+        .filter(|rule| !parameters.voting_rules.iter().any(|r| r.proposal_type == rule.proposal_type))
+        .cloned()
+        .collect();
+    parameters.voting_rules.extend(kept);
+    
+    let version = state.configs.len() as u64 + 1;
+    state.configs.push(GovernanceConfig {
+        version,
+        parameters,
+        updated_at: time(),
+        updated_by: Some(proposal_id.to_string()),
+    });
+}
+
+fn meets_threshold(threshold: &ApprovalThreshold, yes: u64, no: u64) -> bool {
+    let (yes, decided) = (yes as u128, yes as u128 + no as u128);
+    match threshold {
+        ApprovalThreshold::SimpleMajority => yes * 2 > decided,
+        ApprovalThreshold::SuperMajority => decided > 0 && yes * 3 >= decided * 2,
+    }
+}
+
+fn outcome(proposal: &Proposal, yes: u64, no: u64) -> ProposalStatus {
+    // Abstentions are recorded but count toward neither quorum nor approval
+    if yes + no >= proposal.min_votes_required && meets_threshold(&proposal.approval_threshold, yes, no) {
+        ProposalStatus::Passed
+    } else {
+        ProposalStatus::Rejected
+    }
+}
+
+// Applies the time-driven lifecycle steps: discussion ends at voting_start
+// and the timelock at executable_at. Tallying at voting_end is explicit.
+fn advance_phase(proposal: &mut Proposal, now: u64) {
+    if matches!(proposal.status, ProposalStatus::Discussion) && now >= proposal.voting_start {
+        proposal.status = ProposalStatus::Active;
+    }
+    if matches!(proposal.status, ProposalStatus::Timelocked) && proposal.executable_at.is_some_and(|at| now >= at) {
+        proposal.status = ProposalStatus::Passed;
+    }
+}
+
+// Advances a stored proposal and returns it, trapping if it does not exist
+fn sync_proposal<'a>(state: &'a mut GovernanceStorage, proposal_id: &str) -> &'a mut Proposal {
+    let proposal = match state.proposals.get_mut(proposal_id) {
+        Some(p) => p,
+        None => ic_cdk::trap("Proposal not found"),
+    };
+    advance_phase(proposal, time());
+    proposal
+}
+
+// Records the end of voting: a passing proposal enters its timelock
+fn conclude(state: &mut GovernanceStorage, proposal_id: &str, status: ProposalStatus) {
+    let now = time();
+    let proposal = state.proposals.get_mut(proposal_id).unwrap();
+    proposal.status = match status {
+        ProposalStatus::Passed => {
+            proposal.executable_at = Some(now + proposal.timelock_days * NANOS_PER_DAY);
+            ProposalStatus::Timelocked
+        },
+        other => other,
+    };
+    advance_phase(proposal, now);
+    settle_deposit(state, proposal_id);
+    record_participation(state, proposal_id);
+}
+
+fn reward_epoch(timestamp: u64) -> u64 {
+    timestamp / (REWARD_EPOCH_DAYS * NANOS_PER_DAY)
+}
+
+// Counts the members who voted on a decided proposal toward the current
+// reward epoch, weighted by their own snapshot power
+fn record_participation(state: &mut GovernanceStorage, proposal_id: &str) {
+    let voters: Vec<Principal> = match state.proposals[proposal_id].multi_option {
+        Some(_) => state.option_ballots.get(proposal_id).into_iter().flatten().map(|(voter, _)| *voter).collect(),
+        None => direct_votes(state, proposal_id).into_keys().collect(),
+    };
+    let snapshot = &state.snapshots[proposal_id];
+    
+    let participation = state.epoch_participation.entry(reward_epoch(time())).or_default();
+    participation.proposals += 1;
+    for voter in voters {
+        let entry = participation.voters.entry(voter).or_default();
+        entry.proposals_voted += 1;
+        entry.power += snapshot.powers.get(&voter).cloned().unwrap_or(0);
+    }
+}
+
+// Decides a proposal before voting_end only when no way of casting the
+// undecided power could change the result. Power is firm once its holder has
+// voted directly; tokens a delegate voted with can still be reclaimed. Votes
+// can only be changed while a proposal is Active, so deciding early also
+// freezes the firm votes.
+fn early_outcome(proposal: &Proposal, snapshot: &VotingSnapshot, voted: &HashMap<Principal, VoteType>) -> Option<ProposalStatus> {
+    let (mut yes, mut no, mut firm) = (0, 0, 0);
+    for (voter, vote_type) in voted {
+        let balance = snapshot.powers.get(voter).cloned().unwrap_or(0);
+        firm += balance;
+        match vote_type {
+            VoteType::Yes => yes += balance,
+            VoteType::No => no += balance,
+            VoteType::Abstain => {},
         }
-    })
+    }
+    let undecided = proposal.snapshot_total_power.saturating_sub(firm);
+    
+    let worst = outcome(proposal, yes, no + undecided);
+    let best = outcome(proposal, yes + undecided, no);
+    match (worst, best) {
+        (ProposalStatus::Passed, _) => Some(ProposalStatus::Passed),
+        (_, ProposalStatus::Rejected) => Some(ProposalStatus::Rejected),
+        _ => None,
+    }
 }
 
-#[query]
-fn get_token_balance() -> u64 {
-    let caller = ic_cdk::caller();
+fn record_token_tx(
+    state: &mut GovernanceStorage,
+    kind: TokenTxKind,
+    from: Option<Principal>,
+    to: Option<Principal>,
+    amount: u64,
+    stake_id: Option<u64>,
+) -> TokenTxRecord {
+    let record = TokenTxRecord {
+        height: state.ledger_height,
+        kind,
+        from,
+        to,
+        amount,
+        stake_id,
+        timestamp: time(),
+    };
+    state.ledger_height += 1;
+    state.token_ledger.push(record.clone());
+    record
+}
+
+// Returns the creator's deposit once the proposal is decided or canceled,
+// unless it was rejected overwhelmingly (two thirds or more against) or
+// never reached quorum, in which case the deposit is burned. Canceling is
+// only free before voting opens; a proposal canceled while Active is judged
+// on its tally at that moment. For a secret ballot that tally is the ballots
+// revealed so far, and every committed ballot counts toward quorum.
+fn settle_deposit(state: &mut GovernanceStorage, proposal_id: &str) {
+    let proposal = &state.proposals[proposal_id];
+    let undecided = matches!(
+        proposal.status,
+        ProposalStatus::Draft | ProposalStatus::Discussion | ProposalStatus::Active
+    );
+    if proposal.deposit_status != DepositStatus::Locked || undecided {
+        return;
+    }
+    
+    let (yes, no) = (proposal.yes_votes as u128, proposal.no_votes as u128);
+    let overwhelmingly_rejected = no > 0 && no * 3 >= (yes + no) * 2;
+    let canceled = matches!(proposal.status, ProposalStatus::Canceled);
+    let participation = match &proposal.option_result {
+        Some(result) => result.participating_power,
+        None if canceled && proposal.secret_ballot => committed_power(state, proposal_id),
+        None => proposal.yes_votes + proposal.no_votes,
+    };
+    let reached_quorum = participation >= proposal.min_votes_required;
+    let canceled_before_voting = canceled && time() < proposal.voting_start;
+    let refund = canceled_before_voting || (reached_quorum && !overwhelmingly_rejected);
+    let (creator, deposit) = (proposal.creator, proposal.deposit);
+    
+    let proposal = state.proposals.get_mut(proposal_id).unwrap();
+    if refund {
+        proposal.deposit_status = DepositStatus::Refunded;
+        *state.token_balances.entry(creator).or_insert(0) += deposit;
+        record_token_tx(state, TokenTxKind::DepositRefund, None, Some(creator), deposit, None);
+    } else {
+        proposal.deposit_status = DepositStatus::Forfeited;
+        record_token_tx(state, TokenTxKind::DepositForfeit, None, None, deposit, None);
+    }
+}
+
+// Snapshot power behind the ballots committed on a secret-ballot proposal,
+// revealed or not, including power delegated to the committers
+fn committed_power(state: &GovernanceStorage, proposal_id: &str) -> u64 {
+    let (snapshot, commitments) = match (state.snapshots.get(proposal_id), state.commitments.get(proposal_id)) {
+        (Some(snapshot), Some(commitments)) => (snapshot, commitments),
+        _ => return 0,
+    };
+    snapshot
+        .powers
+        .iter()
+        .filter(|(holder, _)| effective_voter(snapshot, holder, commitments).is_some())
+        .map(|(_, power)| *power)
+        .sum()
+}
+
+fn mint(state: &mut GovernanceStorage, recipient: Principal, amount: u64) {
+    let balance = state.token_balances.entry(recipient).or_insert(0);
+    *balance += amount;
+    record_token_tx(state, TokenTxKind::Mint, None, Some(recipient), amount, None);
+}
+
+fn lock_boost(lock_days: u64) -> u64 {
+    LOCK_BOOSTS
+        .iter()
+        .rev()
+        .find(|(min_days, _)| lock_days >= *min_days)
+        .map(|(_, boost)| *boost)
+        .unwrap_or(0)
+}
+
+fn staked_tokens(state: &GovernanceStorage, owner: &Principal) -> u64 {
+    state.stakes.values().filter(|s| s.owner == *owner).map(|s| s.amount).sum()
+}
+
+// Liquid tokens count one-for-one; stakes count with their lock boost until
+// the lock expires and at face value after it, until unstaking starts, after
+// which they carry no power during the cool-down
+fn voting_powers(state: &GovernanceStorage, now: u64) -> HashMap<Principal, u64> {
+    let mut powers: HashMap<Principal, u64> = HashMap::new();
+    for (holder, balance) in state.token_balances.iter().filter(|(_, balance)| **balance > 0) {
+        *powers.entry(*holder).or_insert(0) += balance;
+    }
+    for stake in state.stakes.values().filter(|s| s.unstake_requested_at.is_none()) {
+        let boost_percent = if now < stake.lock_until { stake.boost_percent } else { 0 };
+        *powers.entry(stake.owner).or_insert(0) += stake.amount * (100 + boost_percent) / 100;
+    }
+    powers
+}
+
+// Liquid tokens and stakes not being unstaked, all at face value
+fn unboosted_powers(state: &GovernanceStorage) -> HashMap<Principal, u64> {
+    let mut powers: HashMap<Principal, u64> = HashMap::new();
+    for (holder, balance) in state.token_balances.iter().filter(|(_, balance)| **balance > 0) {
+        *powers.entry(*holder).or_insert(0) += balance;
+    }
+    for stake in state.stakes.values().filter(|s| s.unstake_requested_at.is_none()) {
+        *powers.entry(stake.owner).or_insert(0) += stake.amount;
+    }
+    powers
+}
+
+// Each action belongs to one proposal type, so members know what kind of
+// decision they are voting on
+fn validate_action(proposal_type: &ProposalType, action: &ProposalAction) {
+    let expected = match action {
+        ProposalAction::SetLoanInterestRates { rates } => {
+            if rates.is_empty() || rates.iter().any(|r| !(r.interest_rate >= 0.0 && r.interest_rate.is_finite())) {
+                ic_cdk::trap("Invalid loan interest rates");
+            }
+            ProposalType::PolicyChange
+        },
+        ProposalAction::SetWalletInterestRate { monthly_percent } => {
+            if !(*monthly_percent >= 0.0 && monthly_percent.is_finite()) {
+                ic_cdk::trap("Invalid wallet interest rate");
+            }
+            ProposalType::PolicyChange
+        },
+        ProposalAction::MintTokens { amount, .. } => {
+            if *amount == 0 {
+                ic_cdk::trap("Mint amount must be positive");
+            }
+            ProposalType::TokenIssuance
+        },
+        ProposalAction::GrantRole { role, .. } => {
+            if role.trim().is_empty() {
+                ic_cdk::trap("Role must not be empty");
+            }
+            ProposalType::MembershipRule
+        },
+        ProposalAction::AllocateBudget { purpose, tranches, .. } => {
+            if purpose.trim().is_empty() || tranches.is_empty() {
+                ic_cdk::trap("Budget allocations need a purpose and at least one tranche");
+            }
+            if tranches.iter().any(|t| !(t.amount > 0.0 && t.amount.is_finite())) {
+                ic_cdk::trap("Tranche amounts must be positive");
+            }
+            ProposalType::BudgetAllocation
+        },
+        ProposalAction::ReleaseTranche { .. } => ProposalType::BudgetAllocation,
+        ProposalAction::SetGuardian { .. } => ProposalType::MembershipRule,
+        // Wallet credit is spent from the treasury and tokens are newly
+        // issued, so each goes through the proposal type for that decision
+        ProposalAction::FundRewardPool { kind, amount, .. } => {
+            if *amount == 0 {
+                ic_cdk::trap("Reward pool must be positive");
+            }
+            match kind {
+                RewardKind::WalletCredit => ProposalType::BudgetAllocation,
+                RewardKind::GovernanceTokens => ProposalType::TokenIssuance,
+            }
+        },
+        ProposalAction::SetVotingRule { rule } => {
+            validate_voting_rule(rule);
+            ProposalType::PolicyChange
+        },
+        ProposalAction::SetGovernanceConfig { parameters } => {
+            parameters.voting_rules.iter().for_each(validate_voting_rule);
+            let types: HashSet<&ProposalType> = parameters.voting_rules.iter().map(|r| &r.proposal_type).collect();
+            if types.len() != parameters.voting_rules.len() {
+                ic_cdk::trap("Only one voting rule per proposal type");
+            }
+            if parameters.max_open_proposals == 0 || parameters.reveal_period_days == 0 {
+                ic_cdk::trap("Open proposal limit and reveal period must be positive");
+            }
+            ProposalType::PolicyChange
+        },
+    };
+    
+    if *proposal_type != expected {
+        ic_cdk::trap("Proposal action does not match the proposal type");
+    }
+}
+
+fn validate_voting_rule(rule: &VotingRule) {
+    if !(rule.quorum_percent > 0.0 && rule.quorum_percent <= 100.0) {
+        ic_cdk::trap("Quorum must be between 0 and 100 percent");
+    }
+    if rule.min_voting_days == 0 || rule.min_voting_days > rule.max_voting_days {
+        ic_cdk::trap("Invalid voting period limits");
+    }
+}
+
+fn external_canister(select: fn(&ExternalCanisters) -> Option<Principal>, name: &str) -> Result<Principal, String> {
+    STATE.with(|state| select(&state.borrow().canisters)).ok_or(format!("The {} canister is not configured", name))
+}
+
+fn call_error((code, message): (RejectionCode, String)) -> String {
+    format!("Call rejected ({:?}): {}", code, message)
+}
+
+// Pays from the treasury canister into a member's wallet, out of the funds
+// reserved under `reference` when there is one
+async fn treasury_payout(
+    recipient: Principal,
+    amount: f64,
+    kind: PayoutKind,
+    reference: Option<String>,
+    description: String,
+) -> Result<(), String> {
+    let treasury = external_canister(|c| c.treasury, "treasury")?;
+    let (result,) = ic_cdk::call::<_, (Result<Reserved, String>,)>(
+        treasury,
+        "pay_out",
+        (recipient, amount, kind, reference, description),
+    )
+    .await
+    .map_err(call_error)?;
+    result.map(|_| ())
+}
+
+// Reserves the full budget in the treasury up front so later tranches cannot
+// be starved by other spending. The allocation is only kept once the funds
+// are reserved.
+async fn create_allocation(
+    proposal_id: &str,
+    recipient: Principal,
+    purpose: String,
+    tranches: Vec<TrancheSpec>,
+) -> Result<String, String> {
+    let treasury = external_canister(|c| c.treasury, "treasury")?;
+    let total: f64 = tranches.iter().map(|t| t.amount).sum();
+    
+    let allocation_id = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let allocation = BudgetAllocation {
+            id: format!("BUD-{}", state.next_allocation_id),
+            proposal_id: proposal_id.to_string(),
+            recipient,
+            purpose,
+            tranches: tranches
+                .into_iter()
+                .map(|t| Tranche {
+                    amount: t.amount,
+                    milestone: t.milestone,
+                    milestone_report: None,
+                    status: TrancheStatus::Pending,
+                })
+                .collect(),
+            created_at: time(),
+        };
+        state.next_allocation_id += 1;
+        
+        let id = allocation.id.clone();
+        state.allocations.insert(id.clone(), allocation);
+        id
+    });
+    
+    let reserved = ic_cdk::call::<_, (Result<Reserved, String>,)>(treasury, "reserve", (allocation_id.clone(), total))
+        .await
+        .map_err(call_error)
+        .and_then(|(result,)| result);
+    if let Err(reason) = reserved {
+        STATE.with(|state| state.borrow_mut().allocations.remove(&allocation_id));
+        return Err(reason);
+    }
+    Ok(allocation_id)
+}
+
+// Pays one tranche into the recipient's wallet out of the allocation's
+// reservation. Tranches are paid in order.
+async fn pay_tranche(allocation_id: &str, tranche: usize) -> Result<(), String> {
+    let (recipient, amount, description) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let allocation = state
+            .allocations
+            .get_mut(allocation_id)
+            .ok_or_else(|| "Budget allocation not found".to_string())?;
+        
+        if tranche >= allocation.tranches.len() {
+            return Err("Tranche not found".to_string());
+        }
+        if allocation.tranches[..tranche].iter().any(|t| !matches!(t.status, TrancheStatus::Released { .. })) {
+            return Err("Earlier tranches have not been released".to_string());
+        }
+        let entry = &mut allocation.tranches[tranche];
+        if !matches!(entry.status, TrancheStatus::Pending) {
+            return Err("Tranche is already released or being released".to_string());
+        }
+        entry.status = TrancheStatus::Processing;
+        
+        let description = format!("{} tranche {}: {}", allocation.id, tranche + 1, allocation.purpose);
+        Ok((allocation.recipient, entry.amount, description))
+    })?;
+    
+    let reference = Some(allocation_id.to_string());
+    let result = treasury_payout(recipient, amount, PayoutKind::Budget, reference, description).await;
     
     STATE.with(|state| {
-        let state = state.borrow();
-        state.token_balances.get(&caller).cloned().unwrap_or(0)
-    })
+        let mut state = state.borrow_mut();
+        let entry = &mut state.allocations.get_mut(allocation_id).unwrap().tranches[tranche];
+        entry.status = match result {
+            Ok(()) => TrancheStatus::Released { released_at: time() },
+            Err(_) => TrancheStatus::Pending,
+        };
+    });
+    
+    result
 }
 
-#[query]
-fn get_voting_power() -> u64 {
-    get_token_balance() // In this simple implementation, voting power equals token balance
+fn reward_reservation(epoch: u64) -> String {
+    format!("REWARDS-{}", epoch)
+}
+
+async fn fund_reward_pool(proposal_id: &str, epoch: u64, kind: RewardKind, amount: u64) -> Result<(), String> {
+    let check = |state: &GovernanceStorage| {
+        if state.reward_epochs.contains_key(&epoch) {
+            return Err("Epoch already closed".to_string());
+        }
+        if state.reward_pools.contains_key(&epoch) {
+            return Err(format!("Epoch {} already has a reward pool", epoch));
+        }
+        Ok(())
+    };
+    STATE.with(|state| check(&state.borrow()))?;
+    
+    if kind == RewardKind::WalletCredit {
+        let treasury = external_canister(|c| c.treasury, "treasury")?;
+        let reference = reward_reservation(epoch);
+        ic_cdk::call::<_, (Result<Reserved, String>,)>(treasury, "reserve", (reference.clone(), amount as f64))
+            .await
+            .map_err(call_error)
+            .and_then(|(result,)| result)?;
+        
+        // The epoch may have been closed while the funds were being reserved
+        if let Err(reason) = STATE.with(|state| check(&state.borrow())) {
+            ic_cdk::call::<_, (f64,)>(treasury, "release_reservation", (reference, amount as f64))
+                .await
+                .map_err(call_error)?;
+            return Err(reason);
+        }
+    }
+    
+    STATE.with(|state| {
+        let pool = RewardPool {
+            epoch,
+            kind,
+            amount,
+            proposal_id: proposal_id.to_string(),
+            claimed: 0,
+            unreleased: 0,
+        };
+        state.borrow_mut().reward_pools.insert(epoch, pool);
+    });
+    Ok(())
+}
+
+async fn run_action(proposal_id: &str, action: ProposalAction) -> Result<(), String> {
+    match action {
+        ProposalAction::SetLoanInterestRates { rates } => {
+            let loans = external_canister(|c| c.loans, "loans")?;
+            ic_cdk::call::<_, ()>(loans, "set_interest_rates", (rates,)).await.map_err(call_error)
+        },
+        ProposalAction::SetWalletInterestRate { monthly_percent } => {
+            let wallet = external_canister(|c| c.wallet, "wallet")?;
+            ic_cdk::call::<_, ()>(wallet, "set_interest_rate", (monthly_percent,)).await.map_err(call_error)
+        },
+        ProposalAction::MintTokens { recipient, amount } => {
+            STATE.with(|state| mint(&mut state.borrow_mut(), recipient, amount));
+            Ok(())
+        },
+        ProposalAction::GrantRole { principal, role } => {
+            let auth = external_canister(|c| c.auth, "auth")?;
+            ic_cdk::call::<_, (Reserved,)>(auth, "grant_role", (principal, role))
+                .await
+                .map(|_| ())
+                .map_err(call_error)
+        },
+        ProposalAction::SetGuardian { principal, enabled } => {
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                if enabled {
+                    state.guardians.insert(principal);
+                } else {
+                    state.guardians.remove(&principal);
+                }
+            });
+            Ok(())
+        },
+        ProposalAction::SetVotingRule { rule } => {
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let mut parameters = current_config(&state).parameters.clone();
+                parameters.voting_rules = vec![rule];
+                apply_parameters(&mut state, parameters, proposal_id);
+            });
+            Ok(())
+        },
+        ProposalAction::SetGovernanceConfig { parameters } => {
+            STATE.with(|state| apply_parameters(&mut state.borrow_mut(), parameters, proposal_id));
+            Ok(())
+        },
+        ProposalAction::AllocateBudget { recipient, purpose, tranches } => {
+            // The allocation and its reservation outlive a failed first
+            // payout, so a retried execution only retries the payout
+            let existing = STATE.with(|state| {
+                let state = state.borrow();
+                state.allocations.values().find(|a| a.proposal_id == proposal_id).map(|a| a.id.clone())
+            });
+            let allocation_id = match existing {
+                Some(id) => id,
+                None => create_allocation(proposal_id, recipient, purpose, tranches).await?,
+            };
+            pay_tranche(&allocation_id, 0).await
+        },
+        ProposalAction::ReleaseTranche { allocation_id, tranche } => pay_tranche(&allocation_id, tranche as usize).await,
+        ProposalAction::FundRewardPool { epoch, kind, amount } => fund_reward_pool(proposal_id, epoch, kind, amount).await,
+    }
+}
+
+// The delegate a member's power goes to for a proposal type: a delegation
+// for that type takes precedence over one covering every type.
+fn delegate_for(state: &GovernanceStorage, delegator: &Principal, proposal_type: &ProposalType) -> Option<Principal> {
+    let delegations = state.delegations.get(delegator)?;
+    delegations
+        .iter()
+        .find(|d| d.proposal_type.as_ref() == Some(proposal_type))
+        .or_else(|| delegations.iter().find(|d| d.proposal_type.is_none()))
+        .map(|d| d.delegate)
+}
+
+// Applies a power model to token holdings
+fn model_powers(tokens: HashMap<Principal, u64>, power_model: &PowerModel, members: &[Principal]) -> HashMap<Principal, u64> {
+    match power_model {
+        PowerModel::TokenWeighted => tokens,
+        PowerModel::Quadratic => {
+            // Only members count, so tokens split across outside addresses
+            // cannot multiply their holder's power
+            let members: HashSet<&Principal> = members.iter().collect();
+            tokens
+                .into_iter()
+                .filter(|(holder, _)| members.contains(holder))
+                .map(|(holder, power)| (holder, power.isqrt()))
+                .collect()
+        },
+        PowerModel::OneMemberOneVote => members.iter().map(|member| (*member, 1)).collect(),
+    }
+}
+
+// The power quorum is measured against. Lock boosts are left out so staking
+// cannot raise the quorum beyond what the eligible holders could turn out.
+fn eligible_supply(state: &GovernanceStorage, power_model: &PowerModel, members: &[Principal]) -> u64 {
+    model_powers(unboosted_powers(state), power_model, members).values().sum()
+}
+
+fn take_snapshot(
+    state: &GovernanceStorage,
+    proposal_type: &ProposalType,
+    power_model: &PowerModel,
+    members: &[Principal],
+    now: u64,
+) -> VotingSnapshot {
+    let powers = model_powers(voting_powers(state, now), power_model, members);
+    let delegates = state
+        .delegations
+        .keys()
+        .filter_map(|delegator| delegate_for(state, delegator, proposal_type).map(|d| (*delegator, d)))
+        .collect();
+    
+    VotingSnapshot { powers, delegates }
+}
+
+// Follows the snapshot's delegation chain from `holder` to the first member
+// who voted on the proposal. Delegations are kept acyclic, but the walk is
+// bounded by the number of delegators regardless.
+fn effective_voter<T>(snapshot: &VotingSnapshot, holder: &Principal, voted: &HashMap<Principal, T>) -> Option<Principal> {
+    let mut current = *holder;
+    for _ in 0..=snapshot.delegates.len() {
+        if voted.contains_key(&current) {
+            return Some(current);
+        }
+        current = *snapshot.delegates.get(&current)?;
+    }
+    None
+}
+
+fn direct_votes(state: &GovernanceStorage, proposal_id: &str) -> HashMap<Principal, VoteType> {
+    state
+        .votes
+        .get(proposal_id)
+        .into_iter()
+        .flatten()
+        .map(|v| (v.user, v.vote_type.clone()))
+        .collect()
+}
+
+// Recomputes a proposal's tallies from its snapshot: every holder's tokens
+// count for their own vote, or else for the vote of the nearest delegate in
+// their chain who voted.
+fn tally_votes(snapshot: &VotingSnapshot, voted: &HashMap<Principal, VoteType>) -> (u64, u64, u64) {
+    let (mut yes, mut no, mut abstain) = (0, 0, 0);
+    
+    for (holder, balance) in &snapshot.powers {
+        let voter = match effective_voter(snapshot, holder, voted) {
+            Some(voter) => voter,
+            None => continue,
+        };
+        match voted[&voter] {
+            VoteType::Yes => yes += balance,
+            VoteType::No => no += balance,
+            VoteType::Abstain => abstain += balance,
+        }
+    }
+    
+    (yes, no, abstain)
+}
+
+// Whether `holder`'s power reaches `member`, directly or through a chain of
+// delegations for the given proposal type
+fn delegates_to(state: &GovernanceStorage, holder: &Principal, member: &Principal, proposal_type: &ProposalType) -> bool {
+    let mut current = *holder;
+    for _ in 0..=state.delegations.len() {
+        if current == *member {
+            return true;
+        }
+        current = match delegate_for(state, &current, proposal_type) {
+            Some(next) => next,
+            None => return false,
+        };
+    }
+    false
+}
+
+// A member's own power plus that delegated to them, directly or through a
+// chain, for the given proposal type
+fn delegated_voting_power(state: &GovernanceStorage, member: &Principal, proposal_type: &ProposalType) -> u64 {
+    voting_powers(state, time())
+        .iter()
+        .filter(|(holder, _)| delegates_to(state, holder, member, proposal_type))
+        .map(|(_, balance)| *balance)
+        .sum()
+}
+
+fn validate_multi_option(multi_option: &MultiOption) {
+    let count = multi_option.options.len();
+    if !(2..=MAX_OPTIONS).contains(&count) {
+        ic_cdk::trap("Multi-option proposals need between 2 and 10 options");
+    }
+    if multi_option.options.iter().any(|o| o.trim().is_empty()) {
+        ic_cdk::trap("Options must not be empty");
+    }
+}
+
+fn validate_choices(multi_option: &MultiOption, choices: &[u32]) {
+    let count = multi_option.options.len() as u32;
+    if choices.is_empty() || choices.iter().any(|c| *c >= count) {
+        ic_cdk::trap("Invalid option choice");
+    }
+    let distinct: HashSet<&u32> = choices.iter().collect();
+    if distinct.len() != choices.len() {
+        ic_cdk::trap("Each option can only be chosen once");
+    }
+    if multi_option.method == OptionVotingMethod::Plurality && choices.len() != 1 {
+        ic_cdk::trap("Plurality ballots choose exactly one option");
+    }
+}
+
+fn counts_from(power: &[u64]) -> Vec<OptionCount> {
+    power
+        .iter()
+        .enumerate()
+        .map(|(option, power)| OptionCount {
+            option: option as u32,
+            power: *power,
+        })
+        .collect()
+}
+
+// The single option with the most power, or None when the top is tied
+fn leader(counts: &[OptionCount]) -> Option<u32> {
+    let top = counts.iter().map(|c| c.power).max()?;
+    let mut leaders = counts.iter().filter(|c| c.power == top);
+    let first = leaders.next()?;
+    match leaders.next() {
+        Some(_) => None,
+        None if top > 0 => Some(first.option),
+        None => None,
+    }
+}
+
+// Instant runoff: each round counts every ballot for its highest-ranked
+// option still standing, and eliminates the weakest option until one holds a
+// majority of the remaining power. Ties for last place eliminate the
+// later-listed option; a tie between all remaining options has no winner.
+fn instant_runoff(option_count: usize, ballots: &[(u64, Vec<u32>)]) -> (Vec<TallyRound>, Option<u32>) {
+    let mut standing: Vec<bool> = vec![true; option_count];
+    let mut rounds = Vec::new();
+    
+    loop {
+        let mut power = vec![0u64; option_count];
+        let mut exhausted = 0;
+        for (weight, choices) in ballots {
+            match choices.iter().find(|c| standing[**c as usize]) {
+                Some(choice) => power[*choice as usize] += weight,
+                None => exhausted += weight,
+            }
+        }
+        
+        let counts: Vec<OptionCount> = counts_from(&power).into_iter().filter(|c| standing[c.option as usize]).collect();
+        let total: u64 = counts.iter().map(|c| c.power).sum();
+        let top = counts.iter().map(|c| c.power).max().unwrap_or(0);
+        let bottom = counts.iter().map(|c| c.power).min().unwrap_or(0);
+        let round = rounds.len() as u32 + 1;
+        
+        let winner = counts.iter().find(|c| c.power == top).map(|c| c.option);
+        if total == 0 || (top == bottom && counts.len() > 1) {
+            rounds.push(TallyRound { round, counts, exhausted, eliminated: None });
+            return (rounds, None);
+        }
+        if (top as u128) * 2 > total as u128 || counts.len() == 1 {
+            rounds.push(TallyRound { round, counts, exhausted, eliminated: None });
+            return (rounds, winner);
+        }
+        
+        let eliminated = counts.iter().rev().find(|c| c.power == bottom).map(|c| c.option).unwrap();
+        standing[eliminated as usize] = false;
+        rounds.push(TallyRound {
+            round,
+            counts,
+            exhausted,
+            eliminated: Some(eliminated),
+        });
+    }
+}
+
+// Tallies a multi-option proposal from its snapshot. Each ballot carries its
+// voter's own power plus any delegated to them by members who did not vote.
+fn tally_options(state: &GovernanceStorage, proposal_id: &str, multi_option: &MultiOption) -> OptionResult {
+    let snapshot = &state.snapshots[proposal_id];
+    let ballots = state.option_ballots.get(proposal_id).cloned().unwrap_or_default();
+    
+    let mut weights: HashMap<Principal, u64> = HashMap::new();
+    for (holder, power) in &snapshot.powers {
+        if let Some(voter) = effective_voter(snapshot, holder, &ballots) {
+            *weights.entry(voter).or_insert(0) += power;
+        }
+    }
+    let weighted: Vec<(u64, Vec<u32>)> = ballots
+        .values()
+        .map(|b| (weights.get(&b.user).cloned().unwrap_or(0), b.choices.clone()))
+        .collect();
+    let participating_power = weighted.iter().map(|(weight, _)| weight).sum();
+    
+    let option_count = multi_option.options.len();
+    let (rounds, winner) = match multi_option.method {
+        OptionVotingMethod::Plurality | OptionVotingMethod::Approval => {
+            let mut power = vec![0u64; option_count];
+            for (weight, choices) in &weighted {
+                for choice in choices {
+                    power[*choice as usize] += weight;
+                }
+            }
+            let counts = counts_from(&power);
+            let winner = leader(&counts);
+            (vec![TallyRound { round: 1, counts, exhausted: 0, eliminated: None }], winner)
+        },
+        OptionVotingMethod::RankedChoice => instant_runoff(option_count, &weighted),
+    };
+    
+    OptionResult {
+        participating_power,
+        rounds,
+        winner,
+    }
 }
 
+// Creates a draft only the creator can see and edit. Nothing is locked or
+// snapshotted until the draft is submitted.
 #[update]
-fn execute_proposal(proposal_id: String) -> Proposal {
+fn create_proposal(
+    title: String,
+    description: String,
+    proposal_type: ProposalType,
+    voting_period_days: u64,
+    action: Option<ProposalAction>,
+    multi_option: Option<MultiOption>,
+    secret_ballot: bool,
+) -> Proposal {
     let caller = ic_cdk::caller();
     
+    if let Some(action) = &action {
+        validate_action(&proposal_type, action);
+    }
+    if let Some(multi_option) = &multi_option {
+        validate_multi_option(multi_option);
+        if action.is_some() {
+            ic_cdk::trap("Multi-option proposals cannot carry an action");
+        }
+    }
+    
     STATE.with(|state| {
         let mut state = state.borrow_mut();
+        let now = time();
         
-        let proposal = match state.proposals.get_mut(&proposal_id) {
-            Some(p) => p,
-            None => ic_cdk::trap("Proposal not found"),
-        };
+        let config = current_config(&state);
+        let config_version = config.version;
+        let rule = voting_rule_in(&config.parameters, &proposal_type);
+        require_proposal_threshold(&state, &caller);
         
-        // Only the creator can execute a passed proposal
-        if proposal.creator != caller {
-            ic_cdk::trap("Only the proposal creator can execute it");
+        if voting_period_days < rule.min_voting_days || voting_period_days > rule.max_voting_days {
+            ic_cdk::trap(&format!(
+                "Voting period must be between {} and {} days for this proposal type",
+                rule.min_voting_days, rule.max_voting_days
+            ));
         }
         
-        if !matches!(proposal.status, ProposalStatus::Passed) {
-            ic_cdk::trap("Only passed proposals can be executed");
-        }
+        let proposal_id = format!("PROP-{}", state.next_proposal_id);
+        state.next_proposal_id += 1;
         
-        // Execute the proposal (in a real implementation, this would call into other canisters)
-        proposal.status = ProposalStatus::Executed;
-        proposal.execution_timestamp = Some(time());
+        let proposal = Proposal {
+            id: proposal_id.clone(),
+            creator: caller,
+            title,
+            description,
+            proposal_type,
+            voting_start: 0,
+            voting_end: 0,
+            status: ProposalStatus::Draft,
+            yes_votes: 0,
+            no_votes: 0,
+            abstain_votes: 0,
+            min_votes_required: 0,
+            quorum_percent: rule.quorum_percent,
+            approval_threshold: rule.threshold,
+            execution_timestamp: None,
+            created_at: now,
+            snapshot_time: 0,
+            snapshot_height: 0,
+            snapshot_total_power: 0,
+            action,
+            execution_result: None,
+            deposit: 0,
+            deposit_status: DepositStatus::Unpaid,
+            version: 1,
+            voting_period_days,
+            timelock_days: rule.timelock_days,
+            submitted_at: None,
+            executable_at: None,
+            veto: None,
+            multi_option,
+            option_result: None,
+            power_model: rule.power_model,
+            secret_ballot,
+            reveal_end: None,
+            config_version,
+        };
         
-        proposal.clone()
+        state.revisions.insert(proposal_id.clone(), vec![ProposalRevision {
+            version: 1,
+            description: proposal.description.clone(),
+            note: "Original proposal".to_string(),
+            revised_at: now,
+        }]);
+        
+        state.proposals.insert(proposal_id, proposal.clone());
+        proposal
     })
 }
 
+// Check if caller has sufficient tokens, liquid or staked, to create a proposal
+fn require_proposal_threshold(state: &GovernanceStorage, caller: &Principal) {
+    let threshold = current_config(state).parameters.proposal_threshold;
+    let balance = state.token_balances.get(caller).cloned().unwrap_or(0) + staked_tokens(state, caller);
+    if balance < threshold {
+        ic_cdk::trap(&format!("Insufficient tokens to create proposal. Need at least {} tokens.", threshold));
+    }
+}
+
+fn require_submittable(state: &GovernanceStorage, proposal_id: &str, caller: &Principal) -> Proposal {
+    let proposal = match state.proposals.get(proposal_id) {
+        Some(p) => p.clone(),
+        None => ic_cdk::trap("Proposal not found"),
+    };
+    if proposal.creator != *caller {
+        ic_cdk::trap("Only the proposal creator can submit it");
+    }
+    if !matches!(proposal.status, ProposalStatus::Draft) {
+        ic_cdk::trap("Only drafts can be submitted");
+    }
+    // Tokens may have moved, or the threshold changed, since the draft was created
+    require_proposal_threshold(state, caller);
+    
+    let open = state
+        .proposals
+        .values()
+        .filter(|p| p.creator == *caller && matches!(p.status, ProposalStatus::Discussion | ProposalStatus::Active))
+        .count();
+    if open as u64 >= current_config(state).parameters.max_open_proposals {
+        ic_cdk::trap("Too many open proposals");
+    }
+    
+    proposal
+}
+
+async fn fetch_members() -> Result<Vec<Principal>, String> {
+    let auth = external_canister(|c| c.auth, "auth")?;
+    ic_cdk::call::<_, (Vec<Principal>,)>(auth, "get_member_principals", ())
+        .await
+        .map(|(members,)| members)
+        .map_err(call_error)
+}
+
+// Publishes a draft for discussion: locks the deposit, snapshots voting power
+// and fixes the schedule from the voting rules in force
 #[update]
-fn cancel_proposal(proposal_id: String) -> Proposal {
+async fn submit_proposal(proposal_id: String) -> Proposal {
     let caller = ic_cdk::caller();
     
+    // The configuration read here governs the whole submission, even if a
+    // policy change lands while the membership call is in flight
+    let (config, rule) = STATE.with(|state| {
+        let state = state.borrow();
+        let proposal = require_submittable(&state, &proposal_id, &caller);
+        let config = current_config(&state).clone();
+        let rule = voting_rule_in(&config.parameters, &proposal.proposal_type);
+        (config, rule)
+    });
+    let deposit = config.parameters.proposal_deposit;
+    
+    // Member-based power models take their electorate from the auth canister
+    let members = match rule.power_model {
+        PowerModel::OneMemberOneVote | PowerModel::Quadratic => match fetch_members().await {
+            Ok(members) => members,
+            Err(reason) => ic_cdk::trap(&format!("Could not load the membership list: {}", reason)),
+        },
+        _ => Vec::new(),
+    };
+    
     STATE.with(|state| {
         let mut state = state.borrow_mut();
+        let now = time();
         
-        let proposal = match state.proposals.get_mut(&proposal_id) {
-            Some(p) => p,
-            None => ic_cdk::trap("Proposal not found"),
-        };
+        // Re-check: the draft may have changed while the membership call was in flight
+        let proposal = require_submittable(&state, &proposal_id, &caller);
         
-        // Only the creator can cancel a proposal
-        if proposal.creator != caller {
-            ic_cdk::trap("Only the proposal creator can cancel it");
-        }
+        // Freeze voting power so tokens moved after submission cannot vote twice
+        let snapshot = take_snapshot(&state, &proposal.proposal_type, &rule.power_model, &members, now);
+        let snapshot_height = state.ledger_height;
+        let snapshot_total_power: u64 = snapshot.powers.values().sum();
+        let eligible_supply = eligible_supply(&state, &rule.power_model, &members);
+        state.snapshots.insert(proposal_id.clone(), snapshot);
         
-        if !matches!(proposal.status, ProposalStatus::Active) {
-            ic_cdk::trap("Only active proposals can be canceled");
+        // Lock the refundable deposit from the creator's liquid tokens. The
+        // snapshot above still counts it toward their voting power.
+        let balance = state.token_balances.get(&caller).cloned().unwrap_or(0);
+        if balance < deposit {
+            ic_cdk::trap(&format!("A deposit of {} liquid tokens is required", deposit));
         }
+        state.token_balances.insert(caller, balance - deposit);
+        record_token_tx(&mut state, TokenTxKind::ProposalDeposit, Some(caller), None, deposit, None);
         
-        proposal.status = ProposalStatus::Canceled;
+        // Voting opens once the discussion phase is over
+        let voting_start = now + rule.discussion_days * NANOS_PER_DAY;
+        
+        let proposal = state.proposals.get_mut(&proposal_id).unwrap();
+        proposal.status = ProposalStatus::Discussion;
+        proposal.submitted_at = Some(now);
+        proposal.voting_start = voting_start;
+        proposal.voting_end = voting_start + proposal.voting_period_days * NANOS_PER_DAY;
+        if proposal.secret_ballot {
+            proposal.reveal_end = Some(proposal.voting_end + config.parameters.reveal_period_days * NANOS_PER_DAY);
+        }
+        proposal.snapshot_time = now;
+        proposal.snapshot_height = snapshot_height;
+        proposal.snapshot_total_power = snapshot_total_power;
+        proposal.min_votes_required = (eligible_supply as f64 * rule.quorum_percent / 100.0).ceil() as u64;
+        proposal.quorum_percent = rule.quorum_percent;
+        proposal.approval_threshold = rule.threshold;
+        proposal.timelock_days = rule.timelock_days;
+        proposal.power_model = rule.power_model;
+        proposal.deposit = deposit;
+        proposal.config_version = config.version;
+        proposal.deposit_status = DepositStatus::Locked;
+        advance_phase(proposal, now);
         
         proposal.clone()
     })
 }
 
-// For testing: mint some tokens to the caller
-#[update]
-fn mint_test_tokens(amount: u64) {
+// Whether the caller may see a proposal: drafts are private to their creator
+fn is_visible(proposal: &Proposal, caller: &Principal) -> bool {
+    !matches!(proposal.status, ProposalStatus::Draft) || proposal.creator == *caller
+}
+
+// Proposals are returned in their current lifecycle phase even when no
+// update call has advanced the stored copy yet
+#[query]
+fn get_proposals() -> Vec<Proposal> {
     let caller = ic_cdk::caller();
+    let now = time();
     
     STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        let balance = state.token_balances.entry(caller).or_insert(0);
-        *balance += amount;
-    });
+        let state = state.borrow();
+        state
+            .proposals
+            .values()
+            .filter(|p| is_visible(p, &caller))
+            .cloned()
+            .map(|mut p| {
+                advance_phase(&mut p, now);
+                p
+            })
+            .collect()
+    })
 }
 
-// Required for candid interface generation
-candid::export_service!();
-#[query(name = "__get_candid_interface_tmp_hack")]
-fn export_candid() -> String {
-    __export_service()
+#[query]
+fn get_proposal(proposal_id: String) -> Option<Proposal> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.proposals.get(&proposal_id).filter(|p| is_visible(p, &caller)).cloned().map(|mut p| {
+            advance_phase(&mut p, time());
+            p
+        })
+    })
+}
+
+// Position of a proposal in a listing: the sort timestamp, then the number in
+// its id so proposals with equal timestamps keep a stable order
+fn sort_key(proposal: &Proposal, sort: &ProposalSort) -> (u64, u64) {
+    let timestamp = match sort {
+        ProposalSort::CreatedAt => proposal.created_at,
+        ProposalSort::VotingEnd => proposal.voting_end,
+    };
+    let number = proposal.id.trim_start_matches("PROP-").parse().unwrap_or(0);
+    (timestamp, number)
+}
+
+// Cursors are the sort key of the last proposal on the previous page
+fn parse_cursor(cursor: &str) -> (u64, u64) {
+    let parsed = cursor
+        .split_once(':')
+        .and_then(|(timestamp, number)| Some((timestamp.parse().ok()?, number.parse().ok()?)));
+    match parsed {
+        Some(key) => key,
+        None => ic_cdk::trap("Invalid cursor"),
+    }
+}
+
+// Lists visible proposals one page at a time, filtered, searched and sorted
+// as requested
+#[query]
+fn list_proposals(query: ProposalQuery) -> ProposalPage {
+    let caller = ic_cdk::caller();
+    let now = time();
+    
+    let keywords: Vec<String> = query
+        .search
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect();
+    let after = query.cursor.as_deref().map(parse_cursor);
+    let limit = query.limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        let mut matching: Vec<((u64, u64), Proposal)> = state
+            .proposals
+            .values()
+            .filter(|p| is_visible(p, &caller))
+            .cloned()
+            .map(|mut p| {
+                advance_phase(&mut p, now);
+                p
+            })
+            .filter(|p| query.status.as_ref().is_none_or(|status| p.status == *status))
+            .filter(|p| query.proposal_type.as_ref().is_none_or(|t| p.proposal_type == *t))
+            .filter(|p| query.creator.is_none_or(|creator| p.creator == creator))
+            .filter(|p| {
+                let text = format!("{} {}", p.title, p.description).to_lowercase();
+                keywords.iter().all(|word| text.contains(word.as_str()))
+            })
+            .map(|p| (sort_key(&p, &query.sort), p))
+            .collect();
+        matching.sort_by_key(|(key, _)| *key);
+        if query.descending {
+            matching.reverse();
+        }
+        let total = matching.len() as u64;
+        
+        let start = match after {
+            Some(cursor) => matching
+                .iter()
+                .position(|(key, _)| if query.descending { *key < cursor } else { *key > cursor })
+                .unwrap_or(matching.len()),
+            None => 0,
+        };
+        let page: Vec<((u64, u64), Proposal)> = matching.into_iter().skip(start).take(limit + 1).collect();
+        
+        let next_cursor = if page.len() > limit {
+            let (timestamp, number) = page[limit - 1].0;
+            Some(format!("{}:{}", timestamp, number))
+        } else {
+            None
+        };
+        
+        ProposalPage {
+            proposals: page.into_iter().take(limit).map(|(_, p)| p).collect(),
+            next_cursor,
+            total,
+        }
+    })
+}
+
+fn require_open_for_voting(state: &mut GovernanceStorage, proposal_id: &str) {
+    let proposal = sync_proposal(state, proposal_id);
+    match proposal.status {
+        ProposalStatus::Active => {},
+        ProposalStatus::Draft | ProposalStatus::Discussion => ic_cdk::trap("Proposal is still under discussion"),
+        _ => ic_cdk::trap("Proposal is not active"),
+    }
+    if time() > proposal.voting_end {
+        ic_cdk::trap("Voting period has ended");
+    }
+}
+
+fn require_public_ballot(proposal: &Proposal) {
+    if proposal.secret_ballot {
+        ic_cdk::trap("This proposal uses a secret ballot; use commit_vote and reveal_vote");
+    }
+}
+
+// Recomputes a proposal's tallies and the power each of its votes carries,
+// keeping both vote indexes in step, then closes voting early if the outcome
+// is settled
+fn refresh_votes(state: &mut GovernanceStorage, proposal_id: &str) {
+    let voted = direct_votes(state, proposal_id);
+    let snapshot = &state.snapshots[proposal_id];
+    
+    // A direct vote takes the voter's tokens back from any delegate who voted
+    let (yes_votes, no_votes, abstain_votes) = tally_votes(snapshot, &voted);
+    let mut powers: HashMap<Principal, u64> = HashMap::new();
+    for (holder, balance) in &snapshot.powers {
+        if let Some(voter) = effective_voter(snapshot, holder, &voted) {
+            *powers.entry(voter).or_insert(0) += balance;
+        }
+    }
+    // Secret ballots are only tallied once the reveal phase is over
+    let proposal = &state.proposals[proposal_id];
+    let decided = if proposal.secret_ballot { None } else { early_outcome(proposal, snapshot, &voted) };
+    
+    for vote in state.votes.get_mut(proposal_id).into_iter().flatten() {
+        vote.voting_power = powers.get(&vote.user).cloned().unwrap_or(0);
+        if let Some(user_vote) = state.user_votes.get_mut(&vote.user).and_then(|votes| votes.get_mut(proposal_id)) {
+            user_vote.voting_power = vote.voting_power;
+        }
+    }
+    
+    // Update proposal vote counts
+    let proposal = state.proposals.get_mut(proposal_id).unwrap();
+    proposal.yes_votes = yes_votes;
+    proposal.no_votes = no_votes;
+    proposal.abstain_votes = abstain_votes;
+    
+    // Close voting early once the remaining power can no longer change the outcome
+    if let Some(status) = decided {
+        conclude(state, proposal_id, status);
+    }
+}
+
+// Casts the caller's vote, or changes it if they already voted
+#[update]
+fn vote(proposal_id: String, vote_type: VoteType) -> UserVote {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        
+        require_open_for_voting(state, &proposal_id);
+        require_public_ballot(&state.proposals[&proposal_id]);
+        if state.proposals[&proposal_id].multi_option.is_some() {
+            ic_cdk::trap("This proposal has options; use vote_options");
+        }
+        
+        let previous = state
+            .user_votes
+            .get(&caller)
+            .and_then(|votes| votes.get(&proposal_id))
+            .map(|v| v.vote_type.clone());
+        if previous.as_ref() == Some(&vote_type) {
+            ic_cdk::trap("Already voted this way on this proposal");
+        }
+        
+        // Voting power comes from the proposal's snapshot: the caller's own
+        // tokens plus any delegated to them that no closer delegate voted with
+        let mut voted = direct_votes(state, &proposal_id);
+        voted.insert(caller, vote_type.clone());
+        let snapshot = &state.snapshots[&proposal_id];
+        let voting_power: u64 = snapshot
+            .powers
+            .iter()
+            .filter(|(holder, _)| effective_voter(snapshot, holder, &voted) == Some(caller))
+            .map(|(_, balance)| *balance)
+            .sum();
+        if voting_power == 0 {
+            ic_cdk::trap("No voting power (zero tokens)");
+        }
+        
+        let now = time();
+        let user_vote = UserVote {
+            proposal_id: proposal_id.clone(),
+            user: caller,
+            vote_type: vote_type.clone(),
+            voting_power,
+            timestamp: now,
+        };
+        
+        // Record vote in proposal's vote history, replacing any earlier vote
+        let votes = state.votes.entry(proposal_id.clone()).or_insert_with(Vec::new);
+        votes.retain(|v| v.user != caller);
+        votes.push(user_vote.clone());
+        
+        // Record vote in user's vote history
+        state.user_votes.entry(caller).or_insert_with(HashMap::new).insert(proposal_id.clone(), user_vote);
+        
+        state.vote_history.entry(proposal_id.clone()).or_insert_with(Vec::new).push(VoteChange {
+            proposal_id: proposal_id.clone(),
+            user: caller,
+            previous,
+            current: Some(vote_type),
+            previous_choices: None,
+            current_choices: None,
+            timestamp: now,
+        });
+        
+        refresh_votes(state, &proposal_id);
+        
+        state.user_votes[&caller][&proposal_id].clone()
+    })
+}
+
+// Withdraws the caller's vote while the proposal is still Active. Any power
+// they delegated flows back to their delegate's vote.
+#[update]
+fn retract_vote(proposal_id: String) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        
+        require_open_for_voting(state, &proposal_id);
+        require_public_ballot(&state.proposals[&proposal_id]);
+        
+        if state.proposals[&proposal_id].multi_option.is_some() {
+            let previous = match state.option_ballots.get_mut(&proposal_id).and_then(|ballots| ballots.remove(&caller)) {
+                Some(ballot) => ballot,
+                None => ic_cdk::trap("No vote to retract"),
+            };
+            state.vote_history.entry(proposal_id.clone()).or_insert_with(Vec::new).push(VoteChange {
+                proposal_id: proposal_id.clone(),
+                user: caller,
+                previous: None,
+                current: None,
+                previous_choices: Some(previous.choices),
+                current_choices: None,
+                timestamp: time(),
+            });
+            return;
+        }
+        
+        let previous = match state.user_votes.get_mut(&caller).and_then(|votes| votes.remove(&proposal_id)) {
+            Some(vote) => vote,
+            None => ic_cdk::trap("No vote to retract"),
+        };
+        if let Some(votes) = state.votes.get_mut(&proposal_id) {
+            votes.retain(|v| v.user != caller);
+        }
+        
+        state.vote_history.entry(proposal_id.clone()).or_insert_with(Vec::new).push(VoteChange {
+            proposal_id: proposal_id.clone(),
+            user: caller,
+            previous: Some(previous.vote_type),
+            current: None,
+            previous_choices: None,
+            current_choices: None,
+            timestamp: time(),
+        });
+        
+        refresh_votes(state, &proposal_id);
+    });
+}
+
+// Casts or replaces the caller's ballot on a multi-option proposal. Results
+// are only computed once voting ends.
+#[update]
+fn vote_options(proposal_id: String, choices: Vec<u32>) -> OptionBallot {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        
+        require_open_for_voting(state, &proposal_id);
+        require_public_ballot(&state.proposals[&proposal_id]);
+        let multi_option = match &state.proposals[&proposal_id].multi_option {
+            Some(m) => m.clone(),
+            None => ic_cdk::trap("This is a yes/no proposal; use vote"),
+        };
+        validate_choices(&multi_option, &choices);
+        
+        let snapshot = &state.snapshots[&proposal_id];
+        let mut voters: HashMap<Principal, ()> = state
+            .option_ballots
+            .get(&proposal_id)
+            .into_iter()
+            .flatten()
+            .map(|(voter, _)| (*voter, ()))
+            .collect();
+        voters.insert(caller, ());
+        let has_power = snapshot
+            .powers
+            .keys()
+            .any(|holder| effective_voter(snapshot, holder, &voters) == Some(caller));
+        if !has_power {
+            ic_cdk::trap("No voting power (zero tokens)");
+        }
+        
+        let now = time();
+        let ballot = OptionBallot {
+            proposal_id: proposal_id.clone(),
+            user: caller,
+            choices: choices.clone(),
+            timestamp: now,
+        };
+        let previous = state
+            .option_ballots
+            .entry(proposal_id.clone())
+            .or_insert_with(HashMap::new)
+            .insert(caller, ballot.clone());
+        
+        state.vote_history.entry(proposal_id.clone()).or_insert_with(Vec::new).push(VoteChange {
+            proposal_id,
+            user: caller,
+            previous: None,
+            current: None,
+            previous_choices: previous.map(|b| b.choices),
+            current_choices: Some(choices),
+            timestamp: now,
+        });
+        
+        ballot
+    })
+}
+
+// sha256 of "<proposal_id>|<voter>|<ballot>|<salt>", where the ballot is
+// Yes, No or Abstain, or the chosen option indexes joined by commas
+fn commitment_hash(proposal_id: &str, voter: &Principal, ballot: &SecretBallot, salt: &str) -> Vec<u8> {
+    let ballot = match ballot {
+        SecretBallot::Vote(VoteType::Yes) => "Yes".to_string(),
+        SecretBallot::Vote(VoteType::No) => "No".to_string(),
+        SecretBallot::Vote(VoteType::Abstain) => "Abstain".to_string(),
+        SecretBallot::Options(choices) => choices.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(","),
+    };
+    Sha256::digest(format!("{}|{}|{}|{}", proposal_id, voter.to_text(), ballot, salt)).to_vec()
+}
+
+// Secret ballots stay hidden until the proposal has been tallied
+fn ballots_hidden(state: &GovernanceStorage, proposal_id: &str) -> bool {
+    state
+        .proposals
+        .get(proposal_id)
+        .is_some_and(|p| p.secret_ballot && matches!(p.status, ProposalStatus::Active))
+}
+
+// Records the hash of the caller's ballot on a secret-ballot proposal while
+// voting is open. Committing again replaces the earlier commitment.
+#[update]
+fn commit_vote(proposal_id: String, commitment: Vec<u8>) -> VoteCommitment {
+    let caller = ic_cdk::caller();
+    
+    if commitment.len() != 32 {
+        ic_cdk::trap("Commitment must be a 32-byte sha256 hash");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        
+        require_open_for_voting(state, &proposal_id);
+        if !state.proposals[&proposal_id].secret_ballot {
+            ic_cdk::trap("This proposal does not use a secret ballot");
+        }
+        
+        // Own tokens or tokens delegated to the caller in the snapshot
+        let snapshot = &state.snapshots[&proposal_id];
+        let committer: HashMap<Principal, ()> = HashMap::from([(caller, ())]);
+        let has_power = snapshot
+            .powers
+            .keys()
+            .any(|holder| effective_voter(snapshot, holder, &committer) == Some(caller));
+        if !has_power {
+            ic_cdk::trap("No voting power (zero tokens)");
+        }
+        
+        let record = VoteCommitment {
+            proposal_id: proposal_id.clone(),
+            user: caller,
+            commitment,
+            committed_at: time(),
+            revealed: false,
+        };
+        state.commitments.entry(proposal_id).or_insert_with(HashMap::new).insert(caller, record.clone());
+        record
+    })
+}
+
+// Opens the caller's commitment during the reveal phase. The ballot only
+// counts if it hashes to what was committed; commitments never revealed are
+// left out of the tally.
+#[update]
+fn reveal_vote(proposal_id: String, ballot: SecretBallot, salt: String) -> VoteCommitment {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        let now = time();
+        
+        let proposal = sync_proposal(state, &proposal_id);
+        let reveal_end = match proposal.reveal_end {
+            Some(end) => end,
+            None => ic_cdk::trap("This proposal does not use a secret ballot"),
+        };
+        if !matches!(proposal.status, ProposalStatus::Active) {
+            ic_cdk::trap("Proposal is not active");
+        }
+        if now <= proposal.voting_end {
+            ic_cdk::trap("Votes can only be revealed once voting has ended");
+        }
+        if now > reveal_end {
+            ic_cdk::trap("Reveal phase has ended");
+        }
+        let multi_option = proposal.multi_option.clone();
+        
+        let commitment = match state.commitments.get(&proposal_id).and_then(|c| c.get(&caller)) {
+            Some(c) => c,
+            None => ic_cdk::trap("No committed vote to reveal"),
+        };
+        if commitment.revealed {
+            ic_cdk::trap("Vote already revealed");
+        }
+        if commitment_hash(&proposal_id, &caller, &ballot, &salt) != commitment.commitment {
+            ic_cdk::trap("Ballot and salt do not match the commitment");
+        }
+        
+        // Voting power is assigned when the proposal is tallied at finalization
+        let (current, current_choices) = match (ballot, multi_option) {
+            (SecretBallot::Vote(vote_type), None) => {
+                let user_vote = UserVote {
+                    proposal_id: proposal_id.clone(),
+                    user: caller,
+                    vote_type: vote_type.clone(),
+                    voting_power: 0,
+                    timestamp: now,
+                };
+                state.votes.entry(proposal_id.clone()).or_insert_with(Vec::new).push(user_vote.clone());
+                state.user_votes.entry(caller).or_insert_with(HashMap::new).insert(proposal_id.clone(), user_vote);
+                (Some(vote_type), None)
+            },
+            (SecretBallot::Options(choices), Some(multi_option)) => {
+                validate_choices(&multi_option, &choices);
+                state.option_ballots.entry(proposal_id.clone()).or_insert_with(HashMap::new).insert(caller, OptionBallot {
+                    proposal_id: proposal_id.clone(),
+                    user: caller,
+                    choices: choices.clone(),
+                    timestamp: now,
+                });
+                (None, Some(choices))
+            },
+            (SecretBallot::Vote(_), Some(_)) => ic_cdk::trap("This proposal has options; reveal the chosen options"),
+            (SecretBallot::Options(_), None) => ic_cdk::trap("This is a yes/no proposal; reveal a vote"),
+        };
+        
+        state.vote_history.entry(proposal_id.clone()).or_insert_with(Vec::new).push(VoteChange {
+            proposal_id: proposal_id.clone(),
+            user: caller,
+            previous: None,
+            current,
+            previous_choices: None,
+            current_choices,
+            timestamp: now,
+        });
+        
+        let commitment = state.commitments.get_mut(&proposal_id).and_then(|c| c.get_mut(&caller)).unwrap();
+        commitment.revealed = true;
+        commitment.clone()
+    })
+}
+
+#[query]
+fn get_my_commitment(proposal_id: String) -> Option<VoteCommitment> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.commitments.get(&proposal_id).and_then(|c| c.get(&caller)).cloned()
+    })
+}
+
+#[query]
+fn get_vote_history(proposal_id: String) -> Vec<VoteChange> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        if ballots_hidden(&state, &proposal_id) {
+            return Vec::new();
+        }
+        state.vote_history.get(&proposal_id).cloned().unwrap_or_default()
+    })
+}
+
+// Final tally once the voting period is over. Anyone can trigger it.
+#[update]
+fn finalize_proposal(proposal_id: String) -> Proposal {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let proposal = sync_proposal(&mut state, &proposal_id);
+        if !matches!(proposal.status, ProposalStatus::Active) {
+            ic_cdk::trap("Proposal is not active");
+        }
+        if time() <= proposal.voting_end {
+            ic_cdk::trap("Voting period has not ended");
+        }
+        if proposal.reveal_end.is_some_and(|end| time() <= end) {
+            ic_cdk::trap("Reveal phase has not ended");
+        }
+        let secret_ballot = proposal.secret_ballot;
+        
+        let status = match proposal.multi_option.clone() {
+            // A multi-option proposal passes with a clear winner and quorum
+            Some(multi_option) => {
+                let result = tally_options(&state, &proposal_id, &multi_option);
+                let proposal = state.proposals.get_mut(&proposal_id).unwrap();
+                let status = if result.winner.is_some() && result.participating_power >= proposal.min_votes_required {
+                    ProposalStatus::Passed
+                } else {
+                    ProposalStatus::Rejected
+                };
+                proposal.option_result = Some(result);
+                status
+            },
+            None => {
+                // Secret ballots are tallied here, from the revealed votes only
+                if secret_ballot {
+                    refresh_votes(&mut state, &proposal_id);
+                }
+                let proposal = &state.proposals[&proposal_id];
+                outcome(proposal, proposal.yes_votes, proposal.no_votes)
+            },
+        };
+        conclude(&mut state, &proposal_id, status);
+        
+        state.proposals[&proposal_id].clone()
+    })
+}
+
+#[query]
+fn get_voting_rules() -> Vec<VotingRule> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        ALL_PROPOSAL_TYPES.iter().map(|t| voting_rule_for(&state, t)).collect()
+    })
+}
+
+#[query]
+fn get_governance_config() -> GovernanceConfig {
+    STATE.with(|state| current_config(&state.borrow()).clone())
+}
+
+// Looks up an earlier configuration, e.g. the one a proposal was submitted under
+#[query]
+fn get_governance_config_version(version: u64) -> Option<GovernanceConfig> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        version.checked_sub(1).and_then(|i| state.configs.get(i as usize)).cloned()
+    })
+}
+
+// Delegate the caller's voting power to another member, for every proposal
+// type or only for one. Replaces any existing delegation with the same scope.
+// Proposals already open keep the delegations in their snapshot.
+#[update]
+fn delegate_voting_power(delegate: Principal, proposal_type: Option<ProposalType>) -> Delegation {
+    let caller = ic_cdk::caller();
+    
+    if delegate == caller {
+        ic_cdk::trap("Cannot delegate voting power to yourself");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        // The proposal types this delegation will decide: a global delegation
+        // is overridden wherever the caller has a type-specific one
+        let affected: Vec<ProposalType> = match &proposal_type {
+            Some(t) => vec![t.clone()],
+            None => ALL_PROPOSAL_TYPES
+                .iter()
+                .filter(|t| {
+                    !state
+                        .delegations
+                        .get(&caller)
+                        .is_some_and(|ds| ds.iter().any(|d| d.proposal_type.as_ref() == Some(*t)))
+                })
+                .cloned()
+                .collect(),
+        };
+        
+        // Reject delegations whose chain would lead back to the caller
+        for t in &affected {
+            if delegates_to(&state, &delegate, &caller, t) {
+                ic_cdk::trap("Delegation would create a cycle");
+            }
+        }
+        
+        let delegation = Delegation {
+            delegator: caller,
+            delegate,
+            proposal_type,
+            created_at: time(),
+        };
+        
+        let delegations = state.delegations.entry(caller).or_insert_with(Vec::new);
+        delegations.retain(|d| d.proposal_type != delegation.proposal_type);
+        delegations.push(delegation.clone());
+        
+        delegation
+    })
+}
+
+// Revoke the caller's delegation for the given scope. Applies to proposals
+// created from now on.
+#[update]
+fn revoke_delegation(proposal_type: Option<ProposalType>) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let delegations = match state.delegations.get_mut(&caller) {
+            Some(ds) => ds,
+            None => ic_cdk::trap("No delegation to revoke"),
+        };
+        let before = delegations.len();
+        delegations.retain(|d| d.proposal_type != proposal_type);
+        if delegations.len() == before {
+            ic_cdk::trap("No delegation to revoke");
+        }
+        if delegations.is_empty() {
+            state.delegations.remove(&caller);
+        }
+    });
+}
+
+#[query]
+fn get_delegations() -> Vec<Delegation> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.delegations.get(&caller).cloned().unwrap_or_default()
+    })
+}
+
+// Delegations other members have made to the caller
+#[query]
+fn get_delegators() -> Vec<Delegation> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .delegations
+            .values()
+            .flatten()
+            .filter(|d| d.delegate == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+// The caller's own tokens plus everything delegated to them for a proposal type
+#[query]
+fn get_delegated_voting_power(proposal_type: ProposalType) -> u64 {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        delegated_voting_power(&state, &caller, &proposal_type)
+    })
+}
+
+#[query]
+fn get_user_votes() -> Vec<UserVote> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        match state.user_votes.get(&caller) {
+            Some(votes) => votes.values().cloned().collect(),
+            None => Vec::new(),
+        }
+    })
+}
+
+#[query]
+fn get_proposal_votes(proposal_id: String) -> Vec<UserVote> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        if ballots_hidden(&state, &proposal_id) {
+            return Vec::new();
+        }
+        match state.votes.get(&proposal_id) {
+            Some(votes) => votes.clone(),
+            None => Vec::new(),
+        }
+    })
+}
+
+#[query]
+fn get_token_balance() -> u64 {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.token_balances.get(&caller).cloned().unwrap_or(0)
+    })
+}
+
+// Liquid tokens plus boosted stakes, before delegation
+#[query]
+fn get_voting_power() -> u64 {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        voting_powers(&state, time()).get(&caller).cloned().unwrap_or(0)
+    })
+}
+
+#[query]
+fn get_total_supply() -> u64 {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.token_balances.values().sum::<u64>() + state.stakes.values().map(|s| s.amount).sum::<u64>()
+    })
+}
+
+// One-off initial distribution by the administrator while no tokens exist.
+// Afterwards tokens can only be minted by executed TokenIssuance proposals.
+#[update]
+fn distribute_genesis_tokens(allocations: Vec<TokenAllocation>) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can distribute genesis tokens");
+        }
+        if !state.token_ledger.is_empty() {
+            ic_cdk::trap("Genesis tokens have already been distributed");
+        }
+        
+        for allocation in allocations {
+            mint(&mut state, allocation.recipient, allocation.amount);
+        }
+    });
+}
+
+#[update]
+fn transfer_tokens(to: Principal, amount: u64) -> TokenTxRecord {
+    let caller = ic_cdk::caller();
+    
+    if amount == 0 {
+        ic_cdk::trap("Amount must be positive");
+    }
+    if to == caller {
+        ic_cdk::trap("Cannot transfer tokens to yourself");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let balance = state.token_balances.get(&caller).cloned().unwrap_or(0);
+        if balance < amount {
+            ic_cdk::trap("Insufficient token balance");
+        }
+        state.token_balances.insert(caller, balance - amount);
+        *state.token_balances.entry(to).or_insert(0) += amount;
+        
+        record_token_tx(&mut state, TokenTxKind::Transfer, Some(caller), Some(to), amount, None)
+    })
+}
+
+// Locks tokens for at least `lock_days`; longer locks earn a larger boost
+#[update]
+fn stake_tokens(amount: u64, lock_days: u64) -> Stake {
+    let caller = ic_cdk::caller();
+    
+    if amount == 0 {
+        ic_cdk::trap("Amount must be positive");
+    }
+    if lock_days > MAX_LOCK_DAYS {
+        ic_cdk::trap("Lock period is too long");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let balance = state.token_balances.get(&caller).cloned().unwrap_or(0);
+        if balance < amount {
+            ic_cdk::trap("Insufficient token balance");
+        }
+        state.token_balances.insert(caller, balance - amount);
+        
+        let now = time();
+        let stake = Stake {
+            id: state.next_stake_id,
+            owner: caller,
+            amount,
+            staked_at: now,
+            lock_until: now + lock_days * NANOS_PER_DAY,
+            boost_percent: lock_boost(lock_days),
+            unstake_requested_at: None,
+            withdrawable_at: None,
+        };
+        state.next_stake_id += 1;
+        state.stakes.insert(stake.id, stake.clone());
+        
+        record_token_tx(&mut state, TokenTxKind::Stake, Some(caller), None, amount, Some(stake.id));
+        stake
+    })
+}
+
+// Starts the cool-down on an unlocked stake. The stake stops counting toward
+// voting power immediately.
+#[update]
+fn unstake_tokens(stake_id: u64) -> Stake {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let now = time();
+        
+        let stake = match state.stakes.get_mut(&stake_id) {
+            Some(s) if s.owner == caller => s,
+            _ => ic_cdk::trap("Stake not found"),
+        };
+        if stake.unstake_requested_at.is_some() {
+            ic_cdk::trap("Stake is already unstaking");
+        }
+        if now < stake.lock_until {
+            ic_cdk::trap("Stake is still locked");
+        }
+        
+        stake.unstake_requested_at = Some(now);
+        stake.withdrawable_at = Some(now + UNSTAKE_COOLDOWN_DAYS * NANOS_PER_DAY);
+        let stake = stake.clone();
+        
+        record_token_tx(&mut state, TokenTxKind::Unstake, Some(caller), None, stake.amount, Some(stake_id));
+        stake
+    })
+}
+
+// Returns a stake's tokens to the liquid balance once the cool-down is over
+#[update]
+fn withdraw_stake(stake_id: u64) -> TokenTxRecord {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let stake = match state.stakes.get(&stake_id) {
+            Some(s) if s.owner == caller => s.clone(),
+            _ => ic_cdk::trap("Stake not found"),
+        };
+        match stake.withdrawable_at {
+            Some(at) if time() >= at => {},
+            Some(_) => ic_cdk::trap("Stake is still cooling down"),
+            None => ic_cdk::trap("Unstake the tokens before withdrawing them"),
+        }
+        
+        state.stakes.remove(&stake_id);
+        *state.token_balances.entry(caller).or_insert(0) += stake.amount;
+        
+        record_token_tx(&mut state, TokenTxKind::StakeWithdrawal, None, Some(caller), stake.amount, Some(stake_id))
+    })
+}
+
+#[query]
+fn get_stakes() -> Vec<Stake> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut stakes: Vec<Stake> = state.stakes.values().filter(|s| s.owner == caller).cloned().collect();
+        stakes.sort_by_key(|s| s.id);
+        stakes
+    })
+}
+
+// Ledger entries involving the caller, oldest first
+#[query]
+fn get_token_transactions() -> Vec<TokenTxRecord> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .token_ledger
+            .iter()
+            .filter(|tx| tx.from == Some(caller) || tx.to == Some(caller))
+            .cloned()
+            .collect()
+    })
+}
+
+// Runs a passed proposal's action once its timelock is over. Anyone can
+// trigger it. A failed call is recorded on the proposal, which stays Passed
+// so execution can be retried.
+#[update]
+async fn execute_proposal(proposal_id: String) -> Proposal {
+    let action = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let proposal = sync_proposal(&mut state, &proposal_id);
+        match proposal.status {
+            ProposalStatus::Passed => {},
+            ProposalStatus::Timelocked => ic_cdk::trap("Proposal is still in its timelock"),
+            _ => ic_cdk::trap("Only passed proposals can be executed"),
+        }
+        
+        let action = proposal.action.clone();
+        if !state.executing.insert(proposal_id.clone()) {
+            ic_cdk::trap("Proposal is already being executed");
+        }
+        action
+    });
+    
+    let result = match action {
+        Some(action) => run_action(&proposal_id, action).await,
+        None => Ok(()),
+    };
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.executing.remove(&proposal_id);
+        
+        let proposal = state.proposals.get_mut(&proposal_id).unwrap();
+        proposal.execution_timestamp = Some(time());
+        proposal.execution_result = Some(match result {
+            Ok(()) => {
+                proposal.status = ProposalStatus::Executed;
+                ExecutionResult::Succeeded
+            },
+            Err(reason) => ExecutionResult::Failed { reason },
+        });
+        
+        proposal.clone()
+    })
+}
+
+// Emergency brake: a guardian can stop a passed proposal before it takes effect
+#[update]
+fn veto_proposal(proposal_id: String, reason: String) -> Proposal {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        if !state.guardians.contains(&caller) {
+            ic_cdk::trap("Only guardians can veto proposals");
+        }
+        
+        let proposal = sync_proposal(&mut state, &proposal_id);
+        if !matches!(proposal.status, ProposalStatus::Timelocked) {
+            ic_cdk::trap("Proposals can only be vetoed during their timelock");
+        }
+        
+        proposal.status = ProposalStatus::Vetoed;
+        proposal.veto = Some(Veto {
+            guardian: caller,
+            reason,
+            vetoed_at: time(),
+        });
+        
+        proposal.clone()
+    })
+}
+
+#[query]
+fn get_guardians() -> Vec<Principal> {
+    STATE.with(|state| state.borrow().guardians.iter().cloned().collect())
+}
+
+#[update]
+fn set_external_canisters(canisters: ExternalCanisters) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can configure external canisters");
+        }
+        state.canisters = canisters;
+    });
+}
+
+#[query]
+fn get_external_canisters() -> ExternalCanisters {
+    STATE.with(|state| state.borrow().canisters.clone())
+}
+
+#[update]
+fn cancel_proposal(proposal_id: String) -> Proposal {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let proposal = sync_proposal(&mut state, &proposal_id);
+        
+        // Only the creator can cancel a proposal
+        if proposal.creator != caller {
+            ic_cdk::trap("Only the proposal creator can cancel it");
+        }
+        
+        if !matches!(proposal.status, ProposalStatus::Draft | ProposalStatus::Discussion | ProposalStatus::Active) {
+            ic_cdk::trap("Only proposals that are still open can be canceled");
+        }
+        
+        // Ballots revealed so far are all that is known of a secret ballot
+        let revealed = proposal.secret_ballot && matches!(proposal.status, ProposalStatus::Active);
+        proposal.status = ProposalStatus::Canceled;
+        if revealed {
+            let (yes, no, abstain) = tally_votes(&state.snapshots[&proposal_id], &direct_votes(&state, &proposal_id));
+            let proposal = state.proposals.get_mut(&proposal_id).unwrap();
+            (proposal.yes_votes, proposal.no_votes, proposal.abstain_votes) = (yes, no, abstain);
+        }
+        settle_deposit(&mut state, &proposal_id);
+        
+        state.proposals[&proposal_id].clone()
+    })
+}
+
+// Lets a budget recipient report progress on a milestone before members
+// vote on releasing its tranche
+#[update]
+fn report_milestone(allocation_id: String, tranche: u64, report: String) -> BudgetAllocation {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let allocation = match state.allocations.get_mut(&allocation_id) {
+            Some(a) => a,
+            None => ic_cdk::trap("Budget allocation not found"),
+        };
+        if allocation.recipient != caller {
+            ic_cdk::trap("Only the budget recipient can report milestones");
+        }
+        let entry = match allocation.tranches.get_mut(tranche as usize) {
+            Some(t) => t,
+            None => ic_cdk::trap("Tranche not found"),
+        };
+        if !matches!(entry.status, TrancheStatus::Pending) {
+            ic_cdk::trap("Tranche has already been released");
+        }
+        entry.milestone_report = Some(report);
+        
+        allocation.clone()
+    })
+}
+
+#[query]
+fn get_budget_allocation(allocation_id: String) -> Option<BudgetAllocation> {
+    STATE.with(|state| state.borrow().allocations.get(&allocation_id).cloned())
+}
+
+// Every budget allocation with its tranche payouts, oldest first. Balances
+// and income are reported by the treasury canister.
+#[query]
+fn get_budget_allocations() -> Vec<BudgetAllocation> {
+    STATE.with(|state| {
+        let mut allocations: Vec<BudgetAllocation> = state.borrow().allocations.values().cloned().collect();
+        allocations.sort_by_key(|a| a.created_at);
+        allocations
+    })
+}
+
+// Revises the description while the proposal is a draft or under
+// discussion. Every revision is kept so voters can see how it evolved.
+#[update]
+fn amend_proposal(proposal_id: String, description: String, note: String) -> Proposal {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let now = time();
+        
+        let proposal = sync_proposal(&mut state, &proposal_id);
+        if proposal.creator != caller {
+            ic_cdk::trap("Only the proposal creator can amend it");
+        }
+        if !matches!(proposal.status, ProposalStatus::Draft | ProposalStatus::Discussion) {
+            ic_cdk::trap("Proposals can only be amended during discussion");
+        }
+        
+        proposal.description = description.clone();
+        proposal.version += 1;
+        let proposal = proposal.clone();
+        
+        state.revisions.entry(proposal_id).or_insert_with(Vec::new).push(ProposalRevision {
+            version: proposal.version,
+            description,
+            note,
+            revised_at: now,
+        });
+        
+        proposal
+    })
+}
+
+#[query]
+fn get_proposal_revisions(proposal_id: String) -> Vec<ProposalRevision> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        match state.proposals.get(&proposal_id) {
+            Some(p) if is_visible(p, &caller) => state.revisions.get(&proposal_id).cloned().unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    })
+}
+
+#[update]
+fn add_comment(proposal_id: String, body: String, parent_id: Option<u64>) -> Comment {
+    let caller = ic_cdk::caller();
+    
+    if body.trim().is_empty() || body.chars().count() > MAX_COMMENT_LENGTH {
+        ic_cdk::trap("Comments must be between 1 and 2000 characters");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        match state.proposals.get(&proposal_id) {
+            Some(p) if matches!(p.status, ProposalStatus::Draft) => ic_cdk::trap("Drafts cannot be discussed until submitted"),
+            Some(_) => {},
+            None => ic_cdk::trap("Proposal not found"),
+        }
+        if let Some(parent_id) = parent_id {
+            let exists = state.comments.get(&proposal_id).is_some_and(|thread| thread.iter().any(|c| c.id == parent_id));
+            if !exists {
+                ic_cdk::trap("Parent comment not found on this proposal");
+            }
+        }
+        
+        let comment = Comment {
+            id: state.next_comment_id,
+            proposal_id: proposal_id.clone(),
+            author: caller,
+            parent_id,
+            body,
+            created_at: time(),
+            reactions: Vec::new(),
+        };
+        state.next_comment_id += 1;
+        state.comments.entry(proposal_id).or_insert_with(Vec::new).push(comment.clone());
+        
+        comment
+    })
+}
+
+// Sets the caller's reaction to a comment, or clears it with None
+#[update]
+fn react_to_comment(proposal_id: String, comment_id: u64, reaction: Option<Reaction>) -> Comment {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let comment = match state
+            .comments
+            .get_mut(&proposal_id)
+            .and_then(|thread| thread.iter_mut().find(|c| c.id == comment_id))
+        {
+            Some(c) => c,
+            None => ic_cdk::trap("Comment not found"),
+        };
+        
+        comment.reactions.retain(|r| r.user != caller);
+        if let Some(reaction) = reaction {
+            comment.reactions.push(CommentReaction { user: caller, reaction });
+        }
+        
+        comment.clone()
+    })
+}
+
+#[query]
+fn get_comments(proposal_id: String) -> Vec<Comment> {
+    STATE.with(|state| state.borrow().comments.get(&proposal_id).cloned().unwrap_or_default())
+}
+
+// Reward pools approved so far, most recent epoch first
+#[query]
+fn get_reward_pools() -> Vec<RewardPool> {
+    STATE.with(|state| state.borrow().reward_pools.values().rev().cloned().collect())
+}
+
+// Shares out an ended epoch's reward pool among the members who voted on
+// proposals decided in it. A member's share follows their own snapshot power
+// summed over the proposals they voted on, i.e. their average voting power
+// times their participation rate. Epochs no proposal funded have nothing to
+// share. Anyone can close an epoch once it is over.
+#[update]
+async fn close_reward_epoch(epoch: u64) -> RewardEpoch {
+    let record = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        let now = time();
+        
+        if epoch >= reward_epoch(now) {
+            ic_cdk::trap("Epoch has not ended yet");
+        }
+        if let Some(record) = state.reward_epochs.get(&epoch) {
+            // Closing again retries handing back credit the treasury did not take
+            if state.reward_pools.get(&epoch).is_some_and(|pool| pool.unreleased > 0) {
+                return record.clone();
+            }
+            ic_cdk::trap("Epoch already closed");
+        }
+        
+        let participation = state.epoch_participation.remove(&epoch).unwrap_or_default();
+        let (kind, pool) = state
+            .reward_pools
+            .get(&epoch)
+            .map_or((RewardKind::GovernanceTokens, 0), |pool| (pool.kind.clone(), pool.amount));
+        
+        let total_power: u128 = participation.voters.values().map(|v| v.power as u128).sum();
+        let mut distributed = 0;
+        for (member, voter) in &participation.voters {
+            let amount = (pool as u128 * voter.power as u128).checked_div(total_power).unwrap_or(0) as u64;
+            if amount == 0 {
+                continue;
+            }
+            distributed += amount;
+            state.rewards.entry(*member).or_insert_with(Vec::new).push(Reward {
+                epoch,
+                member: *member,
+                kind: kind.clone(),
+                amount,
+                proposals_voted: voter.proposals_voted,
+                participation_percent: voter.proposals_voted as f64 / participation.proposals as f64 * 100.0,
+                status: RewardStatus::Claimable,
+            });
+        }
+        if let Some(reward_pool) = state.reward_pools.get_mut(&epoch) {
+            if reward_pool.kind == RewardKind::WalletCredit {
+                reward_pool.unreleased = pool - distributed;
+            }
+        }
+        
+        let record = RewardEpoch {
+            epoch,
+            ends_at: (epoch + 1) * REWARD_EPOCH_DAYS * NANOS_PER_DAY,
+            proposals: participation.proposals,
+            voters: participation.voters.len() as u64,
+            kind,
+            pool,
+            distributed,
+            closed_at: now,
+        };
+        state.reward_epochs.insert(epoch, record.clone());
+        record
+    });
+    
+    release_undistributed_credit(epoch).await;
+    record
+}
+
+// Hands the wallet credit a closed epoch did not distribute back to the
+// treasury. It stays recorded as unreleased if the treasury cannot be reached.
+async fn release_undistributed_credit(epoch: u64) {
+    let amount = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.reward_pools.get_mut(&epoch).map_or(0, |pool| std::mem::take(&mut pool.unreleased))
+    });
+    if amount == 0 {
+        return;
+    }
+    
+    let result = match external_canister(|c| c.treasury, "treasury") {
+        Ok(treasury) => ic_cdk::call::<_, (f64,)>(treasury, "release_reservation", (reward_reservation(epoch), amount as f64))
+            .await
+            .map(|_| ())
+            .map_err(call_error),
+        Err(reason) => Err(reason),
+    };
+    if result.is_err() {
+        STATE.with(|state| {
+            if let Some(pool) = state.borrow_mut().reward_pools.get_mut(&epoch) {
+                pool.unreleased += amount;
+            }
+        });
+    }
+}
+
+// Pays out all of the caller's claimable rewards. Tokens are minted straight
+// away; wallet credit is paid by the treasury out of each epoch's
+// reservation, one payment per epoch. No epoch pays out more than its pool.
+#[update]
+async fn claim_rewards() -> RewardClaim {
+    let caller = ic_cdk::caller();
+    
+    let (tokens, credit_by_epoch) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        let now = time();
+        
+        let mut tokens = 0;
+        let mut credit_by_epoch: BTreeMap<u64, u64> = BTreeMap::new();
+        for reward in state.rewards.get_mut(&caller).into_iter().flatten() {
+            if !matches!(reward.status, RewardStatus::Claimable) {
+                continue;
+            }
+            let pool = match state.reward_pools.get_mut(&reward.epoch) {
+                Some(pool) if pool.claimed + reward.amount <= pool.amount => pool,
+                _ => ic_cdk::trap("Reward exceeds the epoch's reward pool"),
+            };
+            pool.claimed += reward.amount;
+            match reward.kind {
+                RewardKind::GovernanceTokens => {
+                    tokens += reward.amount;
+                    reward.status = RewardStatus::Claimed { claimed_at: now };
+                },
+                RewardKind::WalletCredit => {
+                    *credit_by_epoch.entry(reward.epoch).or_insert(0) += reward.amount;
+                    reward.status = RewardStatus::Processing;
+                },
+            }
+        }
+        if tokens == 0 && credit_by_epoch.is_empty() {
+            ic_cdk::trap("No rewards to claim");
+        }
+        
+        if tokens > 0 {
+            *state.token_balances.entry(caller).or_insert(0) += tokens;
+            record_token_tx(state, TokenTxKind::Reward, None, Some(caller), tokens, None);
+        }
+        (tokens, credit_by_epoch)
+    });
+    
+    let mut claim = RewardClaim { tokens, wallet_credit: 0, error: None };
+    for (epoch, amount) in credit_by_epoch {
+        let description = format!("Governance participation reward for epoch {}", epoch);
+        let reference = Some(reward_reservation(epoch));
+        let result = treasury_payout(caller, amount as f64, PayoutKind::Reward, reference, description).await;
+        
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let now = time();
+            
+            // A failed payout leaves the epoch's rewards claimable for another attempt
+            for reward in state.rewards.get_mut(&caller).into_iter().flatten() {
+                if reward.epoch == epoch && matches!(reward.status, RewardStatus::Processing) {
+                    reward.status = match result {
+                        Ok(()) => RewardStatus::Claimed { claimed_at: now },
+                        Err(_) => RewardStatus::Claimable,
+                    };
+                }
+            }
+            if result.is_err() {
+                if let Some(pool) = state.reward_pools.get_mut(&epoch) {
+                    pool.claimed -= amount;
+                }
+            }
+        });
+        
+        match result {
+            Ok(()) => claim.wallet_credit += amount,
+            Err(reason) => {
+                claim.error.get_or_insert(reason);
+            },
+        }
+    }
+    claim
+}
+
+// The caller's rewards, most recent epoch first
+#[query]
+fn get_reward_history() -> Vec<Reward> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut rewards = state.rewards.get(&caller).cloned().unwrap_or_default();
+        rewards.sort_by_key(|r| std::cmp::Reverse(r.epoch));
+        rewards
+    })
+}
+
+// Closed epochs, most recent first
+#[query]
+fn get_reward_epochs() -> Vec<RewardEpoch> {
+    STATE.with(|state| state.borrow().reward_epochs.values().rev().cloned().collect())
+}
+
+// Required for candid interface generation
+candid::export_service!();
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
+
+// Stands in for the system API in unit tests, which run outside a canister:
+// tests set the caller and the clock, and a trap panics with its message
+#[cfg(test)]
+mod ic_cdk {
+    pub use ::ic_cdk::*;
+    use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+    use candid::Principal;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    
+    thread_local! {
+        pub static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+        pub static TIME: Cell<u64> = const { Cell::new(0) };
+        // Inter-canister calls made so far: method and candid-encoded arguments
+        pub static CALLS: RefCell<Vec<(String, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+        // Candid-encoded replies by method, for calls that return data
+        pub static REPLIES: RefCell<HashMap<String, Vec<u8>>> = RefCell::new(HashMap::new());
+        pub static REJECT_CALLS: Cell<bool> = const { Cell::new(false) };
+    }
+    
+    pub fn caller() -> Principal {
+        CALLER.with(|caller| caller.get())
+    }
+    
+    pub fn trap(message: &str) -> ! {
+        panic!("{}", message)
+    }
+    
+    // Records the call and completes it straight away with the reply set for
+    // the method, an empty record by default, unless REJECT_CALLS is set
+    pub async fn call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+        _id: Principal,
+        method: &str,
+        args: T,
+    ) -> api::call::CallResult<R> {
+        let encoded = candid::utils::encode_args(args).expect("arguments encode");
+        CALLS.with(|calls| calls.borrow_mut().push((method.to_string(), encoded)));
+        if REJECT_CALLS.with(|reject| reject.get()) {
+            return Err((api::call::RejectionCode::CanisterReject, "Rejected by test".to_string()));
+        }
+        let reply = REPLIES
+            .with(|replies| replies.borrow().get(method).cloned())
+            .unwrap_or_else(|| candid::utils::encode_args(((),)).expect("reply encodes"));
+        Ok(candid::utils::decode_args(&reply).expect("reply decodes"))
+    }
+    
+    pub mod api {
+        pub use ::ic_cdk::api::*;
+        
+        pub fn time() -> u64 {
+            super::TIME.with(|time| time.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const START: u64 = 1_700_000_000_000_000_000;
+    const CREATOR: u8 = 1;
+    const ALICE: u8 = 10;
+    const BOB: u8 = 11;
+    const CAROL: u8 = 12;
+    const OUTSIDER: u8 = 99;
+    const AUTH: u8 = 200;
+    const TREASURY: u8 = 201;
+    
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+    
+    fn act_as(id: u8) {
+        ic_cdk::CALLER.with(|caller| caller.set(principal(id)));
+    }
+    
+    fn set_time(now: u64) {
+        ic_cdk::TIME.with(|time| time.set(now));
+    }
+    
+    // Mints directly to the ledger, as an executed TokenIssuance would
+    fn issue(member: u8, amount: u64) {
+        STATE.with(|state| mint(&mut state.borrow_mut(), principal(member), amount));
+    }
+    
+    // The creator installs the canister and so is its administrator
+    fn setup() {
+        set_time(START);
+        act_as(CREATOR);
+        init();
+        issue(CREATOR, 200);
+        issue(ALICE, 300);
+        issue(BOB, 200);
+        issue(CAROL, 50);
+    }
+    
+    fn propose(proposal_type: ProposalType) -> String {
+        act_as(CREATOR);
+        let id = create_proposal("Title".to_string(), "Description".to_string(), proposal_type, 7, None, None, false).id;
+        block_on(submit_proposal(id)).id
+    }
+    
+    fn propose_action(proposal_type: ProposalType, action: ProposalAction) -> String {
+        act_as(CREATOR);
+        let id = create_proposal("Title".to_string(), "Description".to_string(), proposal_type, 7, Some(action), None, false).id;
+        block_on(submit_proposal(id)).id
+    }
+    
+    // Drives an update call to completion; the stubbed inter-canister calls
+    // complete on the first poll
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("Call did not complete"),
+        }
+    }
+    
+    // Moves the clock past the longest discussion phase of proposals
+    // submitted at START, which is still inside every 7-day voting window
+    fn start_voting() {
+        set_time(START + 3 * NANOS_PER_DAY);
+    }
+    
+    // Points governance at an auth canister that lists the given members
+    fn connect_auth(members: &[u8]) {
+        act_as(CREATOR);
+        set_external_canisters(ExternalCanisters {
+            auth: Some(principal(AUTH)),
+            wallet: None,
+            loans: None,
+            treasury: None,
+        });
+        let members: Vec<Principal> = members.iter().map(|member| principal(*member)).collect();
+        let reply = candid::utils::encode_args((members,)).expect("members encode");
+        ic_cdk::REPLIES.with(|replies| replies.borrow_mut().insert("get_member_principals".to_string(), reply));
+    }
+    
+    fn tally(proposal_id: &str) -> (u64, u64, u64) {
+        let proposal = get_proposal(proposal_id.to_string()).expect("proposal exists");
+        (proposal.yes_votes, proposal.no_votes, proposal.abstain_votes)
+    }
+    
+    #[test]
+    fn delegates_vote_with_delegated_power_until_the_delegator_votes() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        let proposal_id = propose(ProposalType::PolicyChange);
+        
+        start_voting();
+        act_as(BOB);
+        let ballot = vote(proposal_id.clone(), VoteType::Yes);
+        assert_eq!(ballot.voting_power, 500);
+        assert_eq!(tally(&proposal_id), (500, 0, 0));
+        
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::No);
+        assert_eq!(tally(&proposal_id), (200, 300, 0));
+    }
+    
+    #[test]
+    fn type_specific_delegations_override_global_ones() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        delegate_voting_power(principal(CAROL), Some(ProposalType::BudgetAllocation));
+        
+        act_as(BOB);
+        assert_eq!(get_delegated_voting_power(ProposalType::PolicyChange), 500);
+        assert_eq!(get_delegated_voting_power(ProposalType::BudgetAllocation), 200);
+        act_as(CAROL);
+        assert_eq!(get_delegated_voting_power(ProposalType::BudgetAllocation), 350);
+    }
+    
+    #[test]
+    fn delegation_chains_carry_power_through_to_the_last_delegate() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        act_as(BOB);
+        delegate_voting_power(principal(CAROL), None);
+        let proposal_id = propose(ProposalType::Other);
+        
+        start_voting();
+        act_as(CAROL);
+        vote(proposal_id.clone(), VoteType::Abstain);
+        assert_eq!(tally(&proposal_id), (0, 0, 550));
+    }
+    
+    #[test]
+    fn delegation_changes_apply_to_proposals_created_afterwards() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        let before = propose(ProposalType::Other);
+        act_as(ALICE);
+        revoke_delegation(None);
+        let after = propose(ProposalType::Other);
+        
+        start_voting();
+        act_as(BOB);
+        vote(before.clone(), VoteType::Yes);
+        vote(after.clone(), VoteType::Yes);
+        assert_eq!(tally(&before), (500, 0, 0));
+        assert_eq!(tally(&after), (200, 0, 0));
+        act_as(ALICE);
+        assert!(get_delegations().is_empty());
+    }
+    
+    #[test]
+    fn tokens_minted_after_creation_do_not_vote() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        issue(ALICE, 1_000);
+        
+        start_voting();
+        act_as(ALICE);
+        assert_eq!(vote(proposal_id.clone(), VoteType::Yes).voting_power, 300);
+        let proposal = get_proposal(proposal_id).expect("proposal exists");
+        assert_eq!(proposal.snapshot_total_power, 750);
+        assert_eq!(proposal.snapshot_height, 4);
+    }
+    
+    #[test]
+    #[should_panic(expected = "No voting power (zero tokens)")]
+    fn members_without_snapshot_power_cannot_vote() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        issue(20, 500);
+        
+        start_voting();
+        act_as(20);
+        vote(proposal_id, VoteType::Yes);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Delegation would create a cycle")]
+    fn delegation_cycles_are_rejected() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        act_as(BOB);
+        delegate_voting_power(principal(CAROL), Some(ProposalType::PolicyChange));
+        
+        act_as(CAROL);
+        delegate_voting_power(principal(ALICE), None);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Cannot delegate voting power to yourself")]
+    fn members_cannot_delegate_to_themselves() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(ALICE), None);
+    }
+    
+    #[test]
+    #[should_panic(expected = "No delegation to revoke")]
+    fn revoking_needs_a_delegation_in_that_scope() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        revoke_delegation(Some(ProposalType::Other));
+    }
+    
+    // A proposal with the given action, passed by Alice's tokens alone
+    fn passed_proposal(proposal_type: ProposalType, action: ProposalAction) -> String {
+        issue(ALICE, 1_000);
+        let proposal_id = propose_action(proposal_type, action);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::Yes);
+        // Past the longest timelock
+        set_time(START + 7 * NANOS_PER_DAY);
+        proposal_id
+    }
+    
+    #[test]
+    fn executing_a_token_issuance_mints_the_tokens() {
+        setup();
+        let action = ProposalAction::MintTokens { recipient: principal(CAROL), amount: 500 };
+        let proposal_id = passed_proposal(ProposalType::TokenIssuance, action);
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id));
+        assert!(matches!(proposal.status, ProposalStatus::Executed));
+        assert!(matches!(proposal.execution_result, Some(ExecutionResult::Succeeded)));
+        act_as(CAROL);
+        assert_eq!(get_token_balance(), 550);
+    }
+    
+    #[test]
+    fn failed_executions_are_recorded_and_can_be_retried() {
+        setup();
+        let action = ProposalAction::SetWalletInterestRate { monthly_percent: 0.5 };
+        let proposal_id = passed_proposal(ProposalType::PolicyChange, action);
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id));
+        assert!(matches!(proposal.status, ProposalStatus::Passed));
+        assert!(matches!(
+            proposal.execution_result,
+            Some(ExecutionResult::Failed { reason }) if reason == "The wallet canister is not configured"
+        ));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposal action does not match the proposal type")]
+    fn actions_must_match_the_proposal_type() {
+        setup();
+        propose_action(ProposalType::Other, ProposalAction::MintTokens { recipient: principal(CAROL), amount: 500 });
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the administrator can configure external canisters")]
+    fn only_the_administrator_configures_external_canisters() {
+        setup();
+        
+        act_as(ALICE);
+        set_external_canisters(ExternalCanisters::default());
+    }
+    
+    #[test]
+    fn super_majority_needs_two_thirds_of_yes_and_no() {
+        assert!(meets_threshold(&ApprovalThreshold::SuperMajority, 2, 1));
+        assert!(meets_threshold(&ApprovalThreshold::SuperMajority, 200, 100));
+        assert!(!meets_threshold(&ApprovalThreshold::SuperMajority, 199, 100));
+        assert!(!meets_threshold(&ApprovalThreshold::SuperMajority, 0, 0));
+    }
+    
+    #[test]
+    fn simple_majority_needs_more_than_half() {
+        assert!(meets_threshold(&ApprovalThreshold::SimpleMajority, 51, 50));
+        assert!(!meets_threshold(&ApprovalThreshold::SimpleMajority, 50, 50));
+        assert!(!meets_threshold(&ApprovalThreshold::SimpleMajority, 0, 0));
+    }
+    
+    #[test]
+    fn thresholds_do_not_overflow_on_large_tallies() {
+        assert!(meets_threshold(&ApprovalThreshold::SuperMajority, u64::MAX, u64::MAX / 2));
+        assert!(meets_threshold(&ApprovalThreshold::SimpleMajority, u64::MAX, u64::MAX - 1));
+    }
+    
+    fn status_of(proposal_id: &str) -> ProposalStatus {
+        get_proposal(proposal_id.to_string()).expect("proposal exists").status
+    }
+    
+    #[test]
+    fn voting_closes_early_once_the_remaining_power_cannot_change_the_outcome() {
+        setup();
+        let passing = propose(ProposalType::PolicyChange);
+        let failing = propose(ProposalType::PolicyChange);
+        start_voting();
+        
+        // With 300 of 750 for, the other 450 could still block a two-thirds
+        // majority; with 300 against, the other 450 can no longer reach one
+        act_as(ALICE);
+        vote(passing.clone(), VoteType::Yes);
+        vote(failing.clone(), VoteType::No);
+        assert!(matches!(status_of(&passing), ProposalStatus::Active));
+        assert!(matches!(status_of(&failing), ProposalStatus::Rejected));
+        
+        act_as(BOB);
+        vote(passing.clone(), VoteType::Yes);
+        assert!(matches!(status_of(&passing), ProposalStatus::Timelocked));
+    }
+    
+    #[test]
+    fn delegated_power_does_not_decide_a_proposal_early() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        let proposal_id = propose(ProposalType::PolicyChange);
+        start_voting();
+        
+        // Alice could still vote herself and take her 300 back
+        act_as(BOB);
+        vote(proposal_id.clone(), VoteType::Yes);
+        assert_eq!(tally(&proposal_id), (500, 0, 0));
+        assert!(matches!(status_of(&proposal_id), ProposalStatus::Active));
+    }
+    
+    #[test]
+    fn finalizing_applies_quorum_and_threshold() {
+        setup();
+        let short_of_quorum = propose(ProposalType::Other);
+        let passing = propose(ProposalType::Other);
+        start_voting();
+        
+        // Quorum for Other proposals is 10% of 750
+        act_as(CAROL);
+        vote(short_of_quorum.clone(), VoteType::Yes);
+        act_as(CREATOR);
+        vote(passing.clone(), VoteType::Yes);
+        
+        after_voting();
+        assert!(matches!(finalize_proposal(short_of_quorum).status, ProposalStatus::Rejected));
+        assert!(matches!(finalize_proposal(passing).status, ProposalStatus::Timelocked));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Voting period has not ended")]
+    fn proposals_cannot_be_finalized_while_voting_is_open() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        finalize_proposal(proposal_id);
+    }
+    
+    #[test]
+    fn changing_a_vote_moves_its_power_and_is_recorded() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::Yes);
+        vote(proposal_id.clone(), VoteType::No);
+        assert_eq!(tally(&proposal_id), (0, 300, 0));
+        
+        let history = get_vote_history(proposal_id);
+        assert_eq!(history.len(), 2);
+        assert!(history[1].previous == Some(VoteType::Yes) && history[1].current == Some(VoteType::No));
+    }
+    
+    #[test]
+    fn retracting_a_vote_returns_delegated_power_to_the_delegate() {
+        setup();
+        act_as(ALICE);
+        delegate_voting_power(principal(BOB), None);
+        let proposal_id = propose(ProposalType::Other);
+        
+        start_voting();
+        act_as(BOB);
+        vote(proposal_id.clone(), VoteType::Yes);
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::No);
+        assert_eq!(tally(&proposal_id), (200, 300, 0));
+        
+        retract_vote(proposal_id.clone());
+        assert_eq!(tally(&proposal_id), (500, 0, 0));
+        let votes = get_proposal_votes(proposal_id.clone());
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0].voting_power, 500);
+        assert!(get_vote_history(proposal_id).last().is_some_and(|change| change.current.is_none()));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Already voted this way on this proposal")]
+    fn repeating_the_same_vote_is_rejected() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::Yes);
+        vote(proposal_id, VoteType::Yes);
+    }
+    
+    #[test]
+    #[should_panic(expected = "No vote to retract")]
+    fn retracting_needs_a_vote() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        act_as(ALICE);
+        retract_vote(proposal_id);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposal is not active")]
+    fn votes_are_frozen_once_a_proposal_is_decided_early() {
+        setup();
+        let proposal_id = propose(ProposalType::PolicyChange);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::No);
+        
+        retract_vote(proposal_id);
+    }
+    
+    #[test]
+    fn staking_boosts_voting_power_by_lock_period() {
+        setup();
+        act_as(ALICE);
+        let stake = stake_tokens(200, 90);
+        assert_eq!(stake.boost_percent, 25);
+        assert_eq!(get_token_balance(), 100);
+        assert_eq!(get_voting_power(), 100 + 250);
+        assert_eq!(get_total_supply(), 750);
+        
+        // Once the lock expires the stake counts at face value
+        set_time(START + 90 * NANOS_PER_DAY);
+        assert_eq!(get_voting_power(), 100 + 200);
+    }
+    
+    #[test]
+    fn quorum_is_measured_against_unboosted_supply() {
+        setup();
+        act_as(ALICE);
+        stake_tokens(300, 365);
+        
+        // Alice's stake votes at double weight, but quorum is 10% of the 750 tokens
+        let proposal = get_proposal(propose(ProposalType::Other)).unwrap();
+        assert_eq!(proposal.snapshot_total_power, 750 + 300);
+        assert_eq!(proposal.min_votes_required, 75);
+    }
+    
+    #[test]
+    fn unstaked_tokens_return_after_the_cool_down() {
+        setup();
+        act_as(ALICE);
+        let stake = stake_tokens(200, 30);
+        
+        set_time(START + 30 * NANOS_PER_DAY);
+        unstake_tokens(stake.id);
+        assert_eq!(get_voting_power(), 100);
+        
+        set_time(START + (30 + UNSTAKE_COOLDOWN_DAYS) * NANOS_PER_DAY);
+        withdraw_stake(stake.id);
+        assert_eq!(get_token_balance(), 300);
+        assert!(get_stakes().is_empty());
+        // Mint, stake, unstake and withdrawal
+        assert_eq!(get_token_transactions().len(), 4);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Stake is still locked")]
+    fn locked_stakes_cannot_be_unstaked() {
+        setup();
+        act_as(ALICE);
+        let stake = stake_tokens(200, 30);
+        
+        set_time(START + 29 * NANOS_PER_DAY);
+        unstake_tokens(stake.id);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Stake is still cooling down")]
+    fn stakes_cannot_be_withdrawn_during_the_cool_down() {
+        setup();
+        act_as(ALICE);
+        let stake = stake_tokens(200, 0);
+        unstake_tokens(stake.id);
+        
+        set_time(START + NANOS_PER_DAY);
+        withdraw_stake(stake.id);
+    }
+    
+    #[test]
+    fn transfers_move_liquid_tokens() {
+        setup();
+        act_as(ALICE);
+        transfer_tokens(principal(CAROL), 100);
+        assert_eq!(get_token_balance(), 200);
+        act_as(CAROL);
+        assert_eq!(get_token_balance(), 150);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Insufficient token balance")]
+    fn staked_tokens_cannot_be_transferred() {
+        setup();
+        act_as(ALICE);
+        stake_tokens(200, 30);
+        transfer_tokens(principal(CAROL), 200);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Genesis tokens have already been distributed")]
+    fn genesis_tokens_are_distributed_once() {
+        act_as(CREATOR);
+        init();
+        distribute_genesis_tokens(vec![TokenAllocation { recipient: principal(ALICE), amount: 300 }]);
+        distribute_genesis_tokens(vec![TokenAllocation { recipient: principal(BOB), amount: 300 }]);
+    }
+    
+    fn deposit_status_of(proposal_id: &str) -> DepositStatus {
+        get_proposal(proposal_id.to_string()).expect("proposal exists").deposit_status
+    }
+    
+    fn after_voting() {
+        set_time(START + 15 * NANOS_PER_DAY);
+    }
+    
+    #[test]
+    fn deposits_are_refunded_when_a_proposal_passes_or_is_canceled() {
+        setup();
+        let passing = propose(ProposalType::Other);
+        let canceled = propose(ProposalType::Other);
+        assert_eq!(get_token_balance(), 100);
+        
+        // Canceling is free while the proposal is still under discussion
+        cancel_proposal(canceled.clone());
+        start_voting();
+        act_as(ALICE);
+        vote(passing.clone(), VoteType::Yes);
+        after_voting();
+        finalize_proposal(passing.clone());
+        
+        assert!(deposit_status_of(&passing) == DepositStatus::Refunded);
+        assert!(deposit_status_of(&canceled) == DepositStatus::Refunded);
+        act_as(CREATOR);
+        assert_eq!(get_token_balance(), 200);
+    }
+    
+    #[test]
+    fn deposits_are_forfeited_without_quorum_or_on_overwhelming_rejection() {
+        setup();
+        let ignored = propose(ProposalType::Other);
+        let rejected = propose(ProposalType::Other);
+        
+        start_voting();
+        act_as(ALICE);
+        vote(rejected.clone(), VoteType::No);
+        after_voting();
+        finalize_proposal(ignored.clone());
+        finalize_proposal(rejected.clone());
+        
+        assert!(deposit_status_of(&ignored) == DepositStatus::Forfeited);
+        assert!(deposit_status_of(&rejected) == DepositStatus::Forfeited);
+        act_as(CREATOR);
+        assert_eq!(get_token_balance(), 100);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Too many open proposals")]
+    fn members_can_only_have_three_open_proposals() {
+        setup();
+        act_as(ALICE);
+        for _ in 0..4 {
+            let id = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, false).id;
+            block_on(submit_proposal(id));
+        }
+    }
+    
+    #[test]
+    #[should_panic(expected = "Voting period must be between 3 and 14 days for this proposal type")]
+    fn voting_periods_follow_the_proposal_type() {
+        setup();
+        act_as(CREATOR);
+        create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 30, None, None, false);
+    }
+    
+    #[test]
+    #[should_panic(expected = "A deposit of 50 liquid tokens is required")]
+    fn deposits_come_from_liquid_tokens() {
+        setup();
+        act_as(ALICE);
+        stake_tokens(260, 30);
+        let id = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, false).id;
+        block_on(submit_proposal(id));
+    }
+    
+    fn budget(amounts: &[f64]) -> ProposalAction {
+        ProposalAction::AllocateBudget {
+            recipient: principal(CAROL),
+            purpose: "Community garden".to_string(),
+            tranches: amounts
+                .iter()
+                .map(|amount| TrancheSpec {
+                    amount: *amount,
+                    milestone: "Milestone".to_string(),
+                })
+                .collect(),
+        }
+    }
+    
+    // Points governance at a treasury canister that accepts reservations and
+    // payouts until told otherwise
+    fn connect_treasury() {
+        act_as(CREATOR);
+        set_external_canisters(ExternalCanisters {
+            auth: None,
+            wallet: None,
+            loans: None,
+            treasury: Some(principal(TREASURY)),
+        });
+        treasury_replies("reserve", Ok(()));
+        treasury_replies("pay_out", Ok(()));
+        let remaining = candid::utils::encode_args((0.0_f64,)).expect("reply encodes");
+        ic_cdk::REPLIES.with(|replies| replies.borrow_mut().insert("release_reservation".to_string(), remaining));
+    }
+    
+    fn treasury_replies(method: &str, result: Result<(), &str>) {
+        let reply = candid::utils::encode_args((result.map_err(String::from),)).expect("reply encodes");
+        ic_cdk::REPLIES.with(|replies| replies.borrow_mut().insert(method.to_string(), reply));
+    }
+    
+    fn reject_calls(reject: bool) {
+        ic_cdk::REJECT_CALLS.with(|flag| flag.set(reject));
+    }
+    
+    fn called(method: &str) -> usize {
+        ic_cdk::CALLS.with(|calls| calls.borrow().iter().filter(|(m, _)| m == method).count())
+    }
+    
+    #[test]
+    fn budgets_reserve_their_total_and_pay_the_first_tranche() {
+        setup();
+        connect_treasury();
+        let proposal_id = passed_proposal(ProposalType::BudgetAllocation, budget(&[300.0, 200.0]));
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id));
+        assert!(matches!(proposal.status, ProposalStatus::Executed));
+        let (reference, amount): (String, f64) = ic_cdk::CALLS.with(|calls| {
+            let calls = calls.borrow();
+            let (_, args) = calls.iter().find(|(m, _)| m == "reserve").expect("reserve was called");
+            candid::utils::decode_args(args).expect("reserve arguments")
+        });
+        assert_eq!((reference.as_str(), amount), ("BUD-0", 500.0));
+        let allocation = &get_budget_allocations()[0];
+        assert!(matches!(allocation.tranches[0].status, TrancheStatus::Released { .. }));
+        assert!(matches!(allocation.tranches[1].status, TrancheStatus::Pending));
+    }
+    
+    #[test]
+    fn budgets_beyond_the_available_treasury_fail_to_execute() {
+        setup();
+        connect_treasury();
+        treasury_replies("reserve", Err("Treasury has 100.00 available but the budget needs 500.00"));
+        let proposal_id = passed_proposal(ProposalType::BudgetAllocation, budget(&[300.0, 200.0]));
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id));
+        assert!(matches!(
+            proposal.execution_result,
+            Some(ExecutionResult::Failed { reason }) if reason == "Treasury has 100.00 available but the budget needs 500.00"
+        ));
+        assert!(get_budget_allocations().is_empty());
+        assert_eq!(called("pay_out"), 0);
+    }
+    
+    #[test]
+    fn failed_first_payouts_keep_the_reservation_for_a_retry() {
+        setup();
+        connect_treasury();
+        treasury_replies("pay_out", Err("Call rejected (CanisterReject): wallet is stopped"));
+        let proposal_id = passed_proposal(ProposalType::BudgetAllocation, budget(&[300.0, 200.0]));
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id.clone()));
+        assert!(matches!(proposal.status, ProposalStatus::Passed));
+        assert!(matches!(get_budget_allocations()[0].tranches[0].status, TrancheStatus::Pending));
+        
+        treasury_replies("pay_out", Ok(()));
+        let proposal = block_on(execute_proposal(proposal_id));
+        assert!(matches!(proposal.status, ProposalStatus::Executed));
+        assert_eq!(called("reserve"), 1);
+        assert_eq!(get_budget_allocations().len(), 1);
+    }
+    
+    #[test]
+    fn budgets_need_a_treasury_canister() {
+        setup();
+        let proposal_id = passed_proposal(ProposalType::BudgetAllocation, budget(&[300.0]));
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id));
+        assert!(matches!(
+            proposal.execution_result,
+            Some(ExecutionResult::Failed { reason }) if reason == "The treasury canister is not configured"
+        ));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Tranche amounts must be positive")]
+    fn budget_tranches_must_be_positive() {
+        setup();
+        propose_action(ProposalType::BudgetAllocation, budget(&[300.0, 0.0]));
+    }
+    
+    #[test]
+    fn amendments_during_discussion_keep_every_revision() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        let proposal = amend_proposal(proposal_id.clone(), "Clearer description".to_string(), "Clarified scope".to_string());
+        assert_eq!(proposal.version, 2);
+        
+        let revisions = get_proposal_revisions(proposal_id);
+        let versions: Vec<u32> = revisions.iter().map(|r| r.version).collect();
+        assert_eq!(versions, vec![1, 2]);
+        assert_eq!(revisions[0].description, "Description");
+        assert_eq!(revisions[1].note, "Clarified scope");
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposals can only be amended during discussion")]
+    fn proposals_cannot_be_amended_once_voting_opens() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        amend_proposal(proposal_id, "Late change".to_string(), "Too late".to_string());
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposal is still under discussion")]
+    fn voting_waits_for_the_discussion_phase() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        act_as(ALICE);
+        vote(proposal_id, VoteType::Yes);
+    }
+    
+    #[test]
+    fn comments_form_threads_with_one_reaction_per_member() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        act_as(ALICE);
+        let question = add_comment(proposal_id.clone(), "Who maintains it?".to_string(), None);
+        act_as(CREATOR);
+        add_comment(proposal_id.clone(), "The garden committee".to_string(), Some(question.id));
+        
+        act_as(BOB);
+        react_to_comment(proposal_id.clone(), question.id, Some(Reaction::Question));
+        let comment = react_to_comment(proposal_id.clone(), question.id, Some(Reaction::Agree));
+        assert_eq!(comment.reactions.len(), 1);
+        assert!(comment.reactions[0].reaction == Reaction::Agree);
+        assert!(react_to_comment(proposal_id.clone(), question.id, None).reactions.is_empty());
+        
+        let thread = get_comments(proposal_id);
+        assert_eq!(thread.len(), 2);
+        assert_eq!(thread[1].parent_id, Some(question.id));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Parent comment not found on this proposal")]
+    fn replies_need_a_parent_on_the_same_proposal() {
+        setup();
+        let first = propose(ProposalType::Other);
+        let second = propose(ProposalType::Other);
+        act_as(ALICE);
+        let comment = add_comment(first, "On the first proposal".to_string(), None);
+        add_comment(second, "Reply".to_string(), Some(comment.id));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Comments must be between 1 and 2000 characters")]
+    fn comments_cannot_be_blank() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        add_comment(proposal_id, "   ".to_string(), None);
+    }
+    
+    // A proposal that passed early at START + 3 days and is still timelocked
+    fn timelocked_proposal() -> String {
+        issue(ALICE, 1_000);
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::Yes);
+        proposal_id
+    }
+    
+    #[test]
+    fn drafts_stay_private_until_submitted() {
+        setup();
+        act_as(CREATOR);
+        let draft = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, false);
+        amend_proposal(draft.id.clone(), "Revised".to_string(), "Wording".to_string());
+        assert!(matches!(draft.status, ProposalStatus::Draft));
+        assert_eq!(get_proposal_revisions(draft.id.clone()).len(), 2);
+        
+        act_as(ALICE);
+        assert!(get_proposal(draft.id.clone()).is_none());
+        assert!(get_proposals().is_empty());
+        assert!(get_proposal_revisions(draft.id.clone()).is_empty());
+        
+        act_as(CREATOR);
+        let submitted = block_on(submit_proposal(draft.id.clone()));
+        assert!(matches!(submitted.status, ProposalStatus::Discussion));
+        assert_eq!(submitted.voting_start, START + NANOS_PER_DAY);
+        act_as(ALICE);
+        assert!(get_proposal(draft.id).is_some());
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the proposal creator can submit it")]
+    fn only_the_creator_submits_a_draft() {
+        setup();
+        act_as(CREATOR);
+        let draft = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, false);
+        act_as(ALICE);
+        block_on(submit_proposal(draft.id));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only drafts can be submitted")]
+    fn proposals_are_submitted_once() {
+        setup();
+        let proposal_id = propose(ProposalType::Other);
+        block_on(submit_proposal(proposal_id));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Drafts cannot be discussed until submitted")]
+    fn drafts_cannot_be_commented_on() {
+        setup();
+        act_as(CREATOR);
+        let draft = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, false);
+        add_comment(draft.id, "Early thoughts".to_string(), None);
+    }
+    
+    #[test]
+    fn passed_proposals_wait_out_their_timelock() {
+        setup();
+        let proposal_id = timelocked_proposal();
+        let proposal = get_proposal(proposal_id.clone()).expect("proposal exists");
+        assert!(matches!(proposal.status, ProposalStatus::Timelocked));
+        assert_eq!(proposal.executable_at, Some(START + 4 * NANOS_PER_DAY));
+        
+        set_time(START + 4 * NANOS_PER_DAY);
+        assert!(matches!(status_of(&proposal_id), ProposalStatus::Passed));
+        assert!(matches!(block_on(execute_proposal(proposal_id)).status, ProposalStatus::Executed));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposal is still in its timelock")]
+    fn timelocked_proposals_cannot_be_executed() {
+        setup();
+        let proposal_id = timelocked_proposal();
+        block_on(execute_proposal(proposal_id));
+    }
+    
+    #[test]
+    fn guardians_can_veto_during_the_timelock() {
+        setup();
+        let proposal_id = timelocked_proposal();
+        
+        act_as(CREATOR);
+        let vetoed = veto_proposal(proposal_id.clone(), "Conflicts with the bylaws".to_string());
+        assert!(matches!(vetoed.status, ProposalStatus::Vetoed));
+        assert!(vetoed.veto.is_some_and(|veto| veto.guardian == principal(CREATOR)));
+        
+        set_time(START + 10 * NANOS_PER_DAY);
+        assert!(matches!(status_of(&proposal_id), ProposalStatus::Vetoed));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only guardians can veto proposals")]
+    fn members_cannot_veto() {
+        setup();
+        let proposal_id = timelocked_proposal();
+        act_as(BOB);
+        veto_proposal(proposal_id, "Disagree".to_string());
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposals can only be vetoed during their timelock")]
+    fn vetoes_come_before_execution() {
+        setup();
+        let proposal_id = timelocked_proposal();
+        set_time(START + 4 * NANOS_PER_DAY);
+        
+        act_as(CREATOR);
+        veto_proposal(proposal_id, "Too late".to_string());
+    }
+    
+    fn propose_options(method: OptionVotingMethod, options: &[&str]) -> String {
+        act_as(CREATOR);
+        let multi_option = MultiOption {
+            method,
+            options: options.iter().map(|o| o.to_string()).collect(),
+        };
+        let id = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, Some(multi_option), false).id;
+        block_on(submit_proposal(id)).id
+    }
+    
+    fn power_of(round: &TallyRound, option: u32) -> u64 {
+        round.counts.iter().find(|c| c.option == option).map(|c| c.power).unwrap_or(0)
+    }
+    
+    #[test]
+    fn instant_runoff_stops_at_a_first_round_majority() {
+        let (rounds, winner) = instant_runoff(3, &[(6, vec![0]), (3, vec![1]), (2, vec![2])]);
+        assert_eq!(winner, Some(0));
+        assert_eq!(rounds.len(), 1);
+        assert_eq!(rounds[0].eliminated, None);
+    }
+    
+    #[test]
+    fn instant_runoff_transfers_eliminated_ballots() {
+        let ballots = [(4, vec![0, 1]), (3, vec![1, 0]), (2, vec![2, 1])];
+        
+        let (rounds, winner) = instant_runoff(3, &ballots);
+        assert_eq!(winner, Some(1));
+        assert_eq!(rounds.len(), 2);
+        assert_eq!(rounds[0].eliminated, Some(2));
+        assert_eq!(power_of(&rounds[1], 0), 4);
+        assert_eq!(power_of(&rounds[1], 1), 5);
+        assert_eq!(rounds[1].counts.len(), 2);
+    }
+    
+    #[test]
+    fn instant_runoff_eliminates_the_later_option_on_a_tie_for_last() {
+        let ballots = [(4, vec![0]), (3, vec![1, 2]), (3, vec![2, 1])];
+        
+        let (rounds, winner) = instant_runoff(3, &ballots);
+        assert_eq!(rounds[0].eliminated, Some(2));
+        assert_eq!(winner, Some(1));
+    }
+    
+    #[test]
+    fn instant_runoff_counts_exhausted_ballots() {
+        let ballots = [(4, vec![0]), (3, vec![1]), (2, vec![2])];
+        
+        let (rounds, winner) = instant_runoff(3, &ballots);
+        assert_eq!(winner, Some(0));
+        assert_eq!(rounds[1].exhausted, 2);
+    }
+    
+    #[test]
+    fn instant_runoff_has_no_winner_on_a_full_tie() {
+        let (rounds, winner) = instant_runoff(2, &[(3, vec![0]), (3, vec![1])]);
+        assert_eq!(winner, None);
+        assert_eq!(rounds.len(), 1);
+        
+        let (_, winner) = instant_runoff(2, &[]);
+        assert_eq!(winner, None);
+    }
+    
+    #[test]
+    fn ranked_choice_proposals_pass_with_the_runoff_winner() {
+        setup();
+        let proposal_id = propose_options(OptionVotingMethod::RankedChoice, &["Solar", "Wind", "Storage"]);
+        start_voting();
+        
+        // Solar leads 300 to 250 to 200, then the creator's 200 transfer from
+        // Storage to Wind, which wins 450 to 300
+        act_as(ALICE);
+        vote_options(proposal_id.clone(), vec![0]);
+        act_as(BOB);
+        vote_options(proposal_id.clone(), vec![1, 0]);
+        act_as(CAROL);
+        vote_options(proposal_id.clone(), vec![1]);
+        act_as(CREATOR);
+        vote_options(proposal_id.clone(), vec![2, 1]);
+        
+        after_voting();
+        let proposal = finalize_proposal(proposal_id);
+        let result = proposal.option_result.expect("options were tallied");
+        assert_eq!(result.participating_power, 750);
+        assert_eq!(result.winner, Some(1));
+        assert_eq!(result.rounds[0].eliminated, Some(2));
+        assert!(!matches!(proposal.status, ProposalStatus::Rejected));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Plurality ballots choose exactly one option")]
+    fn plurality_ballots_pick_one_option() {
+        setup();
+        let proposal_id = propose_options(OptionVotingMethod::Plurality, &["Solar", "Wind"]);
+        start_voting();
+        act_as(ALICE);
+        vote_options(proposal_id, vec![0, 1]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Invalid option choice")]
+    fn ballots_only_name_existing_options() {
+        setup();
+        let proposal_id = propose_options(OptionVotingMethod::Approval, &["Solar", "Wind"]);
+        start_voting();
+        act_as(ALICE);
+        vote_options(proposal_id, vec![2]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "This proposal has options; use vote_options")]
+    fn option_proposals_reject_yes_no_votes() {
+        setup();
+        let proposal_id = propose_options(OptionVotingMethod::Approval, &["Solar", "Wind"]);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id, VoteType::Yes);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Multi-option proposals need between 2 and 10 options")]
+    fn option_proposals_need_two_options() {
+        setup();
+        propose_options(OptionVotingMethod::Plurality, &["Solar"]);
+    }
+    
+    #[test]
+    fn membership_rules_default_to_one_member_one_vote() {
+        setup();
+        let rules = get_voting_rules();
+        for rule in rules {
+            let expected = match rule.proposal_type {
+                ProposalType::MembershipRule => PowerModel::OneMemberOneVote,
+                _ => PowerModel::TokenWeighted,
+            };
+            assert!(rule.power_model == expected);
+        }
+    }
+    
+    #[test]
+    fn quadratic_rules_vote_with_the_square_root_of_token_power() {
+        setup();
+        connect_auth(&[CREATOR, ALICE, BOB, CAROL]);
+        let mut rule = default_voting_rule(&ProposalType::Other);
+        rule.power_model = PowerModel::Quadratic;
+        let change = passed_proposal(ProposalType::PolicyChange, ProposalAction::SetVotingRule { rule });
+        block_on(execute_proposal(change));
+        issue(OUTSIDER, 400);
+        
+        let proposal_id = propose(ProposalType::Other);
+        let proposal = get_proposal(proposal_id.clone()).unwrap();
+        assert!(proposal.power_model == PowerModel::Quadratic);
+        
+        // Tokens held outside the membership carry no power
+        let snapshot_powers = STATE.with(|state| state.borrow().snapshots[&proposal_id].powers.clone());
+        assert!(!snapshot_powers.contains_key(&principal(OUTSIDER)));
+        assert_eq!(proposal.snapshot_total_power, snapshot_powers.values().sum::<u64>());
+        set_time(START + 10 * NANOS_PER_DAY);
+        act_as(ALICE);
+        assert_eq!(vote(proposal_id, VoteType::Yes).voting_power, 36);
+    }
+    
+    #[test]
+    fn one_member_one_vote_snapshots_count_each_member_once() {
+        setup();
+        let members = [principal(ALICE), principal(BOB), principal(20)];
+        let snapshot = STATE.with(|state| {
+            take_snapshot(&state.borrow(), &ProposalType::MembershipRule, &PowerModel::OneMemberOneVote, &members, START)
+        });
+        assert_eq!(snapshot.powers.len(), 3);
+        assert!(snapshot.powers.values().all(|power| *power == 1));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Could not load the membership list: The auth canister is not configured")]
+    fn one_member_one_vote_needs_the_membership_list() {
+        setup();
+        propose(ProposalType::MembershipRule);
+    }
+    
+    fn propose_secret() -> String {
+        act_as(CREATOR);
+        let id = create_proposal("Title".to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, true).id;
+        block_on(submit_proposal(id)).id
+    }
+    
+    fn commit(proposal_id: &str, member: u8, ballot: &SecretBallot, salt: &str) {
+        act_as(member);
+        commit_vote(proposal_id.to_string(), commitment_hash(proposal_id, &principal(member), ballot, salt));
+    }
+    
+    // Inside the reveal phase of a secret ballot submitted at START
+    fn start_reveal() {
+        set_time(START + 9 * NANOS_PER_DAY);
+    }
+    
+    #[test]
+    fn secret_ballots_are_tallied_from_revealed_votes() {
+        setup();
+        let proposal_id = propose_secret();
+        start_voting();
+        let yes = SecretBallot::Vote(VoteType::Yes);
+        let no = SecretBallot::Vote(VoteType::No);
+        commit(&proposal_id, ALICE, &yes, "alice-salt");
+        commit(&proposal_id, BOB, &no, "bob-salt");
+        commit(&proposal_id, CAROL, &no, "carol-salt");
+        
+        // Nothing is visible or decided while ballots are hidden
+        assert!(get_proposal_votes(proposal_id.clone()).is_empty());
+        assert_eq!(tally(&proposal_id), (0, 0, 0));
+        
+        start_reveal();
+        act_as(ALICE);
+        reveal_vote(proposal_id.clone(), yes, "alice-salt".to_string());
+        act_as(BOB);
+        assert!(reveal_vote(proposal_id.clone(), no, "bob-salt".to_string()).revealed);
+        
+        // Carol never reveals, so her power is left out
+        after_voting();
+        let proposal = finalize_proposal(proposal_id.clone());
+        assert_eq!((proposal.yes_votes, proposal.no_votes), (300, 200));
+        assert!(matches!(proposal.status, ProposalStatus::Timelocked));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Ballot and salt do not match the commitment")]
+    fn reveals_must_match_the_commitment() {
+        setup();
+        let proposal_id = propose_secret();
+        start_voting();
+        commit(&proposal_id, ALICE, &SecretBallot::Vote(VoteType::Yes), "alice-salt");
+        
+        start_reveal();
+        reveal_vote(proposal_id, SecretBallot::Vote(VoteType::No), "alice-salt".to_string());
+    }
+    
+    #[test]
+    #[should_panic(expected = "Votes can only be revealed once voting has ended")]
+    fn reveals_wait_for_voting_to_end() {
+        setup();
+        let proposal_id = propose_secret();
+        start_voting();
+        commit(&proposal_id, ALICE, &SecretBallot::Vote(VoteType::Yes), "alice-salt");
+        reveal_vote(proposal_id, SecretBallot::Vote(VoteType::Yes), "alice-salt".to_string());
+    }
+    
+    #[test]
+    #[should_panic(expected = "This proposal uses a secret ballot; use commit_vote and reveal_vote")]
+    fn secret_ballots_reject_open_votes() {
+        setup();
+        let proposal_id = propose_secret();
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id, VoteType::Yes);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Reveal phase has not ended")]
+    fn secret_ballots_finalize_after_the_reveal_phase() {
+        setup();
+        let proposal_id = propose_secret();
+        start_reveal();
+        finalize_proposal(proposal_id);
+    }
+    
+    // Records the pool a passed FundRewardPool proposal approves
+    fn fund_rewards(epoch: u64, kind: RewardKind, amount: u64) {
+        let pool = RewardPool {
+            epoch,
+            kind,
+            amount,
+            proposal_id: "PROP-FUND".to_string(),
+            claimed: 0,
+            unreleased: 0,
+        };
+        STATE.with(|state| state.borrow_mut().reward_pools.insert(epoch, pool));
+    }
+    
+    // Alice and Bob pass a proposal at START + 3 days, then the epoch ends
+    fn epoch_with_votes() -> u64 {
+        let proposal_id = propose(ProposalType::Other);
+        start_voting();
+        act_as(ALICE);
+        vote(proposal_id.clone(), VoteType::Yes);
+        act_as(BOB);
+        vote(proposal_id, VoteType::Yes);
+        
+        let epoch = reward_epoch(time());
+        set_time((epoch + 1) * REWARD_EPOCH_DAYS * NANOS_PER_DAY);
+        epoch
+    }
+    
+    #[test]
+    fn epoch_rewards_follow_voting_power_and_participation() {
+        setup();
+        let epoch = epoch_with_votes();
+        fund_rewards(epoch, RewardKind::GovernanceTokens, 1_000);
+        
+        let closed = block_on(close_reward_epoch(epoch));
+        assert_eq!((closed.proposals, closed.voters), (1, 2));
+        assert_eq!((closed.pool, closed.distributed), (1_000, 1_000));
+        
+        act_as(ALICE);
+        let reward = &get_reward_history()[0];
+        assert_eq!(reward.amount, 600);
+        assert_eq!(reward.participation_percent, 100.0);
+        let claim = block_on(claim_rewards());
+        assert_eq!((claim.tokens, claim.wallet_credit), (600, 0));
+        assert_eq!(get_token_balance(), 900);
+        assert!(matches!(get_reward_history()[0].status, RewardStatus::Claimed { .. }));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Epoch has not ended yet")]
+    fn epochs_close_once_they_are_over() {
+        setup();
+        block_on(close_reward_epoch(reward_epoch(START)));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Epoch already closed")]
+    fn epochs_close_once() {
+        setup();
+        let epoch = epoch_with_votes();
+        block_on(close_reward_epoch(epoch));
+        block_on(close_reward_epoch(epoch));
+    }
+    
+    #[test]
+    #[should_panic(expected = "No rewards to claim")]
+    fn rewards_are_claimed_once() {
+        setup();
+        let epoch = epoch_with_votes();
+        fund_rewards(epoch, RewardKind::GovernanceTokens, 1_000);
+        block_on(close_reward_epoch(epoch));
+        act_as(BOB);
+        block_on(claim_rewards());
+        block_on(claim_rewards());
+    }
+    
+    #[test]
+    fn wallet_credit_rewards_are_paid_by_the_treasury() {
+        setup();
+        connect_treasury();
+        let epoch = epoch_with_votes();
+        fund_rewards(epoch, RewardKind::WalletCredit, 1_000);
+        block_on(close_reward_epoch(epoch));
+        
+        act_as(ALICE);
+        treasury_replies("pay_out", Err("Call rejected (CanisterReject): wallet is stopped"));
+        let claim = block_on(claim_rewards());
+        assert_eq!(claim.error.as_deref(), Some("Call rejected (CanisterReject): wallet is stopped"));
+        assert!(matches!(get_reward_history()[0].status, RewardStatus::Claimable));
+        assert_eq!(get_reward_pools()[0].claimed, 0);
+        
+        treasury_replies("pay_out", Ok(()));
+        let claim = block_on(claim_rewards());
+        assert_eq!((claim.tokens, claim.wallet_credit), (0, 600));
+        assert_eq!(called("pay_out"), 2);
+        let (_, _, _, reference, _): (Principal, f64, PayoutKind, Option<String>, String) = ic_cdk::CALLS.with(|calls| {
+            let calls = calls.borrow();
+            candid::utils::decode_args(&calls.last().expect("payout call").1).expect("payout arguments")
+        });
+        assert_eq!(reference, Some(format!("REWARDS-{}", epoch)));
+    }
+    
+    #[test]
+    fn reward_pools_are_funded_by_passed_proposals() {
+        setup();
+        let action = ProposalAction::FundRewardPool {
+            epoch: 700,
+            kind: RewardKind::GovernanceTokens,
+            amount: 500,
+        };
+        let proposal_id = passed_proposal(ProposalType::TokenIssuance, action);
+        
+        act_as(CREATOR);
+        let proposal = block_on(execute_proposal(proposal_id.clone()));
+        assert!(matches!(proposal.status, ProposalStatus::Executed));
+        let pool = &get_reward_pools()[0];
+        assert_eq!((pool.epoch, pool.amount), (700, 500));
+        assert_eq!(pool.proposal_id, proposal_id);
+    }
+    
+    #[test]
+    fn wallet_credit_pools_are_reserved_in_the_treasury() {
+        setup();
+        connect_treasury();
+        let action = ProposalAction::FundRewardPool {
+            epoch: 700,
+            kind: RewardKind::WalletCredit,
+            amount: 500,
+        };
+        let proposal_id = passed_proposal(ProposalType::BudgetAllocation, action);
+        
+        act_as(CREATOR);
+        block_on(execute_proposal(proposal_id));
+        let (reference, amount): (String, f64) = ic_cdk::CALLS.with(|calls| {
+            let calls = calls.borrow();
+            let (_, args) = calls.iter().find(|(m, _)| m == "reserve").expect("reserve was called");
+            candid::utils::decode_args(args).expect("reserve arguments")
+        });
+        assert_eq!((reference.as_str(), amount), ("REWARDS-700", 500.0));
+        assert_eq!(get_reward_pools().len(), 1);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Proposal action does not match the proposal type")]
+    fn wallet_credit_pools_need_a_budget_proposal() {
+        setup();
+        let action = ProposalAction::FundRewardPool {
+            epoch: 700,
+            kind: RewardKind::WalletCredit,
+            amount: 500,
+        };
+        propose_action(ProposalType::TokenIssuance, action);
+    }
+    
+    #[test]
+    fn unfunded_epochs_have_nothing_to_share() {
+        setup();
+        let epoch = epoch_with_votes();
+        
+        let closed = block_on(close_reward_epoch(epoch));
+        assert_eq!((closed.pool, closed.distributed), (0, 0));
+        act_as(ALICE);
+        assert!(get_reward_history().is_empty());
+    }
+    
+    #[test]
+    #[should_panic(expected = "Reward exceeds the epoch's reward pool")]
+    fn claims_are_capped_at_the_pool() {
+        setup();
+        let epoch = epoch_with_votes();
+        fund_rewards(epoch, RewardKind::GovernanceTokens, 1_000);
+        block_on(close_reward_epoch(epoch));
+        STATE.with(|state| state.borrow_mut().reward_pools.get_mut(&epoch).unwrap().claimed = 500);
+        
+        act_as(ALICE);
+        block_on(claim_rewards());
+    }
+    
+    #[test]
+    fn undistributed_credit_goes_back_to_the_treasury() {
+        setup();
+        connect_treasury();
+        let epoch = epoch_with_votes();
+        fund_rewards(epoch, RewardKind::WalletCredit, 1_001);
+        
+        reject_calls(true);
+        let closed = block_on(close_reward_epoch(epoch));
+        assert_eq!(closed.distributed, 1_000);
+        assert_eq!(get_reward_pools()[0].unreleased, 1);
+        
+        reject_calls(false);
+        block_on(close_reward_epoch(epoch));
+        assert_eq!(get_reward_pools()[0].unreleased, 0);
+        assert_eq!(called("release_reservation"), 2);
+    }
+    
+    fn draft_titled(title: &str) -> String {
+        act_as(CREATOR);
+        create_proposal(title.to_string(), "Description".to_string(), ProposalType::Other, 7, None, None, false).id
+    }
+    
+    fn proposal_query(limit: u64, cursor: Option<String>) -> ProposalQuery {
+        ProposalQuery {
+            status: None,
+            proposal_type: None,
+            creator: None,
+            search: None,
+            sort: ProposalSort::CreatedAt,
+            descending: false,
+            cursor,
+            limit,
+        }
+    }
+    
+    #[test]
+    fn proposal_pages_continue_from_the_cursor() {
+        setup();
+        let ids: Vec<String> = (0..5).map(|n| draft_titled(&format!("Draft {}", n))).collect();
+        
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = list_proposals(proposal_query(2, cursor));
+            assert_eq!(page.total, 5);
+            seen.extend(page.proposals.into_iter().map(|p| p.id));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(seen, ids);
+        
+        let mut query = proposal_query(2, None);
+        query.descending = true;
+        let first = list_proposals(query.clone());
+        query.cursor = first.next_cursor;
+        let second = list_proposals(query);
+        assert_eq!(second.proposals[0].id, ids[2]);
+    }
+    
+    #[test]
+    fn proposal_listing_filters_and_searches() {
+        setup();
+        draft_titled("Solar panels for the office");
+        let submitted = draft_titled("Wind turbine lease");
+        block_on(submit_proposal(submitted.clone()));
+        
+        let mut query = proposal_query(10, None);
+        query.search = Some("SOLAR office".to_string());
+        assert_eq!(list_proposals(query.clone()).total, 1);
+        
+        query.search = None;
+        query.status = Some(ProposalStatus::Discussion);
+        let page = list_proposals(query);
+        assert_eq!(page.proposals.len(), 1);
+        assert_eq!(page.proposals[0].id, submitted);
+        
+        // Others only see submitted proposals
+        act_as(ALICE);
+        assert_eq!(list_proposals(proposal_query(10, None)).total, 1);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Invalid cursor")]
+    fn cursors_must_come_from_a_previous_page() {
+        setup();
+        list_proposals(proposal_query(10, Some("page-2".to_string())));
+    }
+    
+    fn parameters_with(deposit: u64, threshold: u64) -> GovernanceParameters {
+        GovernanceParameters {
+            proposal_threshold: threshold,
+            proposal_deposit: deposit,
+            max_open_proposals: DEFAULT_MAX_OPEN_PROPOSALS,
+            reveal_period_days: DEFAULT_REVEAL_PERIOD_DAYS,
+            voting_rules: Vec::new(),
+        }
+    }
+    
+    #[test]
+    fn configuration_changes_are_versioned_and_apply_to_later_submissions() {
+        setup();
+        let earlier = propose(ProposalType::Other);
+        let action = ProposalAction::SetGovernanceConfig { parameters: parameters_with(20, 100) };
+        let change = passed_proposal(ProposalType::PolicyChange, action);
+        block_on(execute_proposal(change.clone()));
+        
+        let config = get_governance_config();
+        assert_eq!(config.version, 2);
+        assert_eq!(config.updated_by, Some(change));
+        assert_eq!(config.parameters.voting_rules.len(), ALL_PROPOSAL_TYPES.len());
+        let initial = get_governance_config_version(1).expect("initial configuration is kept");
+        assert_eq!(initial.parameters.proposal_deposit, DEFAULT_PROPOSAL_DEPOSIT);
+        assert!(get_governance_config_version(0).is_none());
+        
+        // Proposals keep the configuration they were submitted under
+        let later = get_proposal(propose(ProposalType::Other)).unwrap();
+        assert_eq!((later.config_version, later.deposit), (2, 20));
+        let earlier = get_proposal(earlier).unwrap();
+        assert_eq!((earlier.config_version, earlier.deposit), (1, DEFAULT_PROPOSAL_DEPOSIT));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Insufficient tokens to create proposal. Need at least 500 tokens.")]
+    fn the_proposal_threshold_comes_from_the_configuration() {
+        setup();
+        let action = ProposalAction::SetGovernanceConfig { parameters: parameters_with(50, 500) };
+        let change = passed_proposal(ProposalType::PolicyChange, action);
+        block_on(execute_proposal(change));
+        propose(ProposalType::Other);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Insufficient tokens to create proposal. Need at least 100 tokens.")]
+    fn drafts_are_checked_against_the_threshold_when_submitted() {
+        setup();
+        let draft = draft_titled("Solar panels");
+        
+        // The creator no longer holds enough tokens once the draft is ready
+        act_as(CREATOR);
+        transfer_tokens(principal(ALICE), 150);
+        block_on(submit_proposal(draft));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only one voting rule per proposal type")]
+    fn configurations_hold_one_rule_per_type() {
+        setup();
+        let mut parameters = parameters_with(50, 100);
+        parameters.voting_rules = vec![default_voting_rule(&ProposalType::Other), default_voting_rule(&ProposalType::Other)];
+        propose_action(ProposalType::PolicyChange, ProposalAction::SetGovernanceConfig { parameters });
+    }
+    
+    #[test]
+    #[should_panic(expected = "Open proposal limit and reveal period must be positive")]
+    fn configurations_keep_a_positive_open_proposal_limit() {
+        setup();
+        let mut parameters = parameters_with(50, 100);
+        parameters.max_open_proposals = 0;
+        propose_action(ProposalType::PolicyChange, ProposalAction::SetGovernanceConfig { parameters });
+    }
+    
+    #[test]
+    fn membership_proposals_give_each_registered_member_one_vote() {
+        setup();
+        connect_auth(&[CREATOR, ALICE, BOB]);
+        let proposal_id = propose(ProposalType::MembershipRule);
+        assert_eq!(get_proposal(proposal_id.clone()).unwrap().snapshot_total_power, 3);
+        
+        start_voting();
+        act_as(ALICE);
+        assert_eq!(vote(proposal_id, VoteType::Yes).voting_power, 1);
+    }
+    
+    #[test]
+    fn proposals_canceled_during_voting_are_judged_on_their_tally() {
+        setup();
+        let unsupported = propose(ProposalType::Other);
+        let supported = propose(ProposalType::Other);
+        
+        start_voting();
+        act_as(BOB);
+        vote(supported.clone(), VoteType::Yes);
+        act_as(CREATOR);
+        cancel_proposal(unsupported.clone());
+        cancel_proposal(supported.clone());
+        
+        assert!(deposit_status_of(&unsupported) == DepositStatus::Forfeited);
+        assert!(deposit_status_of(&supported) == DepositStatus::Refunded);
+        assert_eq!(get_token_balance(), 150);
+    }
+    
+    #[test]
+    fn secret_ballots_canceled_during_voting_count_committed_power_toward_quorum() {
+        setup();
+        let unsupported = propose_secret();
+        let supported = propose_secret();
+        
+        start_voting();
+        commit(&supported, BOB, &SecretBallot::Vote(VoteType::No), "bob-salt");
+        act_as(CREATOR);
+        cancel_proposal(unsupported.clone());
+        cancel_proposal(supported.clone());
+        
+        // Bob's hidden ballot reaches quorum without deciding the proposal
+        assert!(deposit_status_of(&unsupported) == DepositStatus::Forfeited);
+        assert!(deposit_status_of(&supported) == DepositStatus::Refunded);
+    }
+    
+    #[test]
+    fn secret_ballots_canceled_while_revealing_are_judged_on_revealed_votes() {
+        setup();
+        let proposal_id = propose_secret();
+        start_voting();
+        let no = SecretBallot::Vote(VoteType::No);
+        commit(&proposal_id, ALICE, &SecretBallot::Vote(VoteType::Yes), "alice-salt");
+        commit(&proposal_id, BOB, &no, "bob-salt");
+        
+        start_reveal();
+        act_as(BOB);
+        reveal_vote(proposal_id.clone(), no, "bob-salt".to_string());
+        act_as(CREATOR);
+        let proposal = cancel_proposal(proposal_id.clone());
+        
+        assert_eq!((proposal.yes_votes, proposal.no_votes), (0, 200));
+        assert!(deposit_status_of(&proposal_id) == DepositStatus::Forfeited);
+    }
 }
@@ -1,15 +1,28 @@
 
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Principal, Reserved};
+use ic_cdk::api::call::RejectionCode;
 use ic_cdk::api::time;
 use ic_cdk::export::{
     candid,
     serde::Serialize,
 };
 use ic_cdk_macros::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::cell::RefCell;
 
-#[derive(CandidType, Clone, Deserialize, Serialize)]
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const NANOS_PER_MONTH: u64 = 30 * NANOS_PER_DAY; // Repayment months are treated as 30 days
+const MAX_PAGE_SIZE: u64 = 100;
+const MAX_REPORT_PERIODS: u64 = 366;
+const PAR_THRESHOLDS_DAYS: [u64; 3] = [30, 60, 90];
+const AMOUNT_EPSILON: f64 = 1e-6;
+const DEFAULT_AFTER_DAYS: u64 = 90;
+const BASE_ELIGIBILITY: f64 = 3000.0;
+const MIN_GROUP_SIZE: usize = 2;
+const MAX_GROUP_SIZE: usize = 30;
+const DEFAULT_INTEREST_SPREAD_PERCENT: f64 = 10.0;
+
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub enum LoanStatus {
     Pending,
     Approved,
@@ -17,9 +30,10 @@ pub enum LoanStatus {
     Active,
     PaidOff,
     Defaulted,
+    WrittenOff,
 }
 
-#[derive(CandidType, Clone, Deserialize, Serialize)]
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum LoanType {
     Personal,
     Business,
@@ -29,6 +43,45 @@ pub enum LoanType {
     Medical,
 }
 
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum FinancingModel {
+    Conventional,
+    // Interest-free loan carrying a fixed, disclosed administration fee
+    QardHasan { administration_fee: f64 },
+    // Cost-plus sale: a fixed profit markup on the financed amount, disclosed up front
+    Murabaha { profit_percent: f64 },
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanProduct {
+    loan_type: LoanType,
+    financing_model: FinancingModel,
+    interest_rate: f64, // Must be zero for interest-free financing models
+    min_amount: f64,
+    max_amount: f64,
+    allowed_terms: Vec<u8>,
+    max_concurrent_loans: u8,
+    eligibility_cap_percent: f64, // Largest loan as a percentage of the member's eligibility
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanRate {
+    loan_type: LoanType,
+    interest_rate: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum LoanValidationError {
+    InvalidAmount,
+    AmountBelowMinimum { minimum: f64 },
+    AmountAboveMaximum { maximum: f64 },
+    TermNotAllowed { allowed_terms: Vec<u8> },
+    TooManyConcurrentLoans { limit: u8 },
+    ExceedsEligibility { eligible_amount: f64 },
+    InvalidGroup { reason: String },
+    GroupMemberIneligible { member: Principal, error: Box<LoanValidationError> },
+}
+
 #[derive(CandidType, Clone, Deserialize, Serialize)]
 pub struct LoanApplication {
     id: String,
@@ -43,6 +96,54 @@ pub struct LoanApplication {
     collateral_amount: Option<f64>,
     credit_score: Option<u16>,
     monthly_payment: f64,
+    financing_model: FinancingModel,
+    financing_charge: f64, // Disclosed administration fee or profit, never interest
+    group_name: Option<String>, // Set for group loans, which are jointly owed by all members
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct GroupAllocation {
+    member: Principal,
+    amount: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct GroupMemberShare {
+    member: Principal,
+    amount: f64,
+    share: f64, // Fraction of the loan allocated to this member
+    monthly_installment: f64,
+    accepted: bool, // Members are only liable once they accept
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct GroupLoan {
+    loan_id: String,
+    group_name: String,
+    members: Vec<GroupMemberShare>,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct MemberRepaymentStatus {
+    member: Principal,
+    accepted: bool,
+    allocated_amount: f64,
+    monthly_installment: f64,
+    due_to_date: f64,
+    paid_to_date: f64,
+    arrears: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct GroupRepaymentStatus {
+    loan_id: String,
+    group_name: String,
+    status: LoanStatus,
+    due_to_date: f64,
+    paid_to_date: f64,
+    arrears: f64,
+    days_past_due: u64,
+    members: Vec<MemberRepaymentStatus>,
 }
 
 #[derive(CandidType, Clone, Deserialize, Serialize)]
@@ -58,198 +159,2733 @@ pub struct LoanPayment {
     loan_id: String,
     principal: Principal,
     amount: f64,
+    fees_portion: f64,
+    interest_portion: f64,
+    charge_portion: f64,
+    principal_portion: f64,
     timestamp: u64,
     status: PaymentStatus,
 }
 
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub enum InstallmentStatus {
+    Pending,
+    Overdue,
+    Paid,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Installment {
+    number: u8,
+    due_date: u64,
+    grace_period_end: u64,
+    principal_due: f64,
+    interest_due: f64,
+    charge_due: f64, // Share of the financing charge of interest-free models
+    fees_due: f64,   // Late fee plus penalty interest charged after the grace period
+    principal_paid: f64,
+    interest_paid: f64,
+    charge_paid: f64,
+    fees_paid: f64,
+    late_fee_charged: bool,
+    penalty_accrued_until: u64,
+    late_fee_percent: f64, // Late payment terms in force when the installment was scheduled
+    penalty_rate: f64,
+    status: InstallmentStatus,
+}
+
+impl Installment {
+    fn remaining_fees(&self) -> f64 {
+        (self.fees_due - self.fees_paid).max(0.0)
+    }
+
+    fn remaining_interest(&self) -> f64 {
+        (self.interest_due - self.interest_paid).max(0.0)
+    }
+
+    fn remaining_charge(&self) -> f64 {
+        (self.charge_due - self.charge_paid).max(0.0)
+    }
+
+    fn remaining_principal(&self) -> f64 {
+        (self.principal_due - self.principal_paid).max(0.0)
+    }
+
+    fn remaining(&self) -> f64 {
+        self.remaining_fees() + self.remaining_interest() + self.remaining_charge() + self.remaining_principal()
+    }
+}
+
+// How an amount is split across what a loan owes
+#[derive(Default)]
+struct Allocation {
+    fees: f64,
+    interest: f64,
+    charges: f64,
+    principal: f64,
+}
+
+// Interest and fees a restructuring added to a loan's principal that are
+// still unpaid. They were never lent out of the pool, so repaying them is
+// income rather than returned capital.
+#[derive(Default)]
+struct CapitalizedArrears {
+    interest: f64,
+    fees: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LatePaymentPolicy {
+    grace_period_days: u64,
+    late_fee_percent: f64, // One-off fee, as a percentage of the missed installment
+    penalty_rate: f64,     // Annual rate charged on the overdue amount after the grace period
+}
+
+impl Default for LatePaymentPolicy {
+    fn default() -> Self {
+        LatePaymentPolicy {
+            grace_period_days: 7,
+            late_fee_percent: 2.0,
+            penalty_rate: 12.0,
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProvisioningRule {
+    min_days_past_due: u64,
+    reserve_percent: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProvisioningPolicy {
+    rules: Vec<ProvisioningRule>, // Sorted by min_days_past_due
+}
+
+impl Default for ProvisioningPolicy {
+    fn default() -> Self {
+        let rules = [(0, 1.0), (30, 10.0), (60, 25.0), (90, 50.0), (180, 100.0)];
+        ProvisioningPolicy {
+            rules: rules
+                .iter()
+                .map(|(min_days_past_due, reserve_percent)| ProvisioningRule {
+                    min_days_past_due: *min_days_past_due,
+                    reserve_percent: *reserve_percent,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProvisionBucket {
+    min_days_past_due: u64,
+    reserve_percent: f64,
+    loan_count: u64,
+    outstanding_principal: f64,
+    required_reserve: f64,
+}
+
+#[derive(CandidType, Clone, Default, Deserialize, Serialize)]
+pub struct LossAccount {
+    principal_written_off: f64,
+    interest_written_off: f64,
+    charges_written_off: f64,
+    fees_written_off: f64,
+    recoveries: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct ProvisioningReport {
+    as_of: u64,
+    buckets: Vec<ProvisionBucket>,
+    total_required_reserve: f64,
+    loss_account: LossAccount,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct WriteOff {
+    loan_id: String,
+    written_off_at: u64,
+    approved_by: Principal,
+    reason: String,
+    principal: f64,
+    interest: f64,
+    charges: f64,
+    fees: f64,
+    recovered: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum PrepaymentMode {
+    ShortenTerm,
+    ReduceInstallment,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct PayoffQuote {
+    loan_id: String,
+    quoted_at: u64,
+    outstanding_principal: f64,
+    accrued_interest: f64,
+    outstanding_charges: f64,
+    outstanding_fees: f64,
+    interest_waived: f64, // Scheduled interest not yet earned at `quoted_at`
+    total: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct RestructureRequest {
+    extend_term_months: u8,
+    payment_holiday_months: u8,
+    capitalize_arrears: bool,
+    new_interest_rate: Option<f64>,
+    reason: String,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanTerms {
+    term_months: u8,
+    interest_rate: f64,
+    monthly_payment: f64,
+    outstanding_principal: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum LoanEventKind {
+    Applied,
+    GroupMemberAccepted { member: Principal },
+    Approved,
+    Rejected,
+    Prepayment { payment_id: String, amount: f64, mode: PrepaymentMode },
+    EarlyPayoff { payment_id: String, amount: f64, interest_waived: f64 },
+    Restructured { request: RestructureRequest, capitalized_arrears: f64 },
+    Defaulted { days_past_due: u64 },
+    WrittenOff { reason: String, principal: f64, interest: f64, charges: f64, fees: f64 },
+    Recovery { payment_id: String, amount: f64 },
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanEvent {
+    timestamp: u64,
+    actor: Principal,
+    kind: LoanEventKind,
+    previous_terms: Option<LoanTerms>,
+    terms: LoanTerms,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub enum FundingSource {
+    MemberSavings,
+    DedicatedDeposit,
+}
+
+// Income the cooperative keeps rather than sharing with contributors; it is
+// handed over to the treasury canister
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum TreasuryIncomeSource {
+    Fees,           // Administration fees of interest-free loans
+    PenaltyIncome,  // Late fees and penalty interest
+    InterestSpread, // The retained share of interest and murabaha profit
+}
+
+const TREASURY_INCOME_SOURCES: [TreasuryIncomeSource; 3] = [
+    TreasuryIncomeSource::Fees,
+    TreasuryIncomeSource::PenaltyIncome,
+    TreasuryIncomeSource::InterestSpread,
+];
+
+#[derive(CandidType, Clone, Default, Deserialize, Serialize)]
+pub struct LendingPool {
+    total_capital: f64,
+    available_liquidity: f64,
+    deployed_capital: f64, // Principal reserved for disbursed loans and not yet repaid
+    interest_collected: f64,
+    interest_distributed: f64,
+    profit_collected: f64,
+    profit_distributed: f64,
+    fee_income: f64,
+    treasury_income: f64,      // Fees, penalty income and interest spread collected for the treasury
+    treasury_transferred: f64, // Part of it already handed over to the treasury canister
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct PoolContribution {
+    contributor: Principal,
+    savings_capital: f64,
+    deposit_capital: f64,
+    earnings: f64, // Distributed interest not yet claimed
+    earnings_claimed: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum PoolTxType {
+    Contribution,
+    Withdrawal,
+    Disbursement,
+    PrincipalRepayment,
+    Interest,
+    Profit,
+    AdministrationFee,
+    Fee,
+    EarningsClaim,
+    WriteOff,
+    Recovery,
+    TreasuryTransfer,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct PoolTxRecord {
+    id: String,
+    tx_type: PoolTxType,
+    amount: f64,
+    timestamp: u64,
+    contributor: Option<Principal>,
+    source: Option<FundingSource>,
+    loan_id: Option<String>,
+}
+
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum StaffRole {
+    Admin,
+    Auditor,
+    LoanOfficer,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum ReportPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanTypeExposure {
+    loan_type: LoanType,
+    loan_count: u64,
+    outstanding_principal: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct PortfolioAtRisk {
+    min_days_past_due: u64,
+    loan_count: u64,
+    outstanding_principal: f64,
+    ratio: f64, // Share of the total outstanding principal
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct PortfolioSummary {
+    as_of: u64,
+    total_loans: u64,
+    disbursed_loans: u64,
+    outstanding_loans: u64,
+    defaulted_loans: u64,
+    total_disbursed: f64,
+    outstanding_principal: f64,
+    outstanding_by_type: Vec<LoanTypeExposure>,
+    portfolio_at_risk: Vec<PortfolioAtRisk>,
+    default_rate: f64,
+    interest_income: f64,
+    charge_income: f64,
+    fee_income: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct PeriodReport {
+    period_start: u64,
+    period_end: u64,
+    loans_disbursed: u64,
+    amount_disbursed: f64,
+    interest_income: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct LoanPage {
+    loans: Vec<LoanApplication>,
+    total: u64,
+    offset: u64,
+}
+
 #[derive(Default)]
 struct LoansStorage {
     loans: HashMap<String, LoanApplication>,
     payments: HashMap<String, Vec<LoanPayment>>,
+    group_loans: HashMap<String, GroupLoan>,      // loan_id -> member allocations
+    schedules: HashMap<String, Vec<Installment>>, // loan_id -> installments
+    history: HashMap<String, Vec<LoanEvent>>,     // loan_id -> events, oldest first
+    late_payment_policy: LatePaymentPolicy,
+    products: HashMap<LoanType, LoanProduct>, // Overrides of the default product rules
+    staff: HashMap<Principal, StaffRole>,
+    pool: LendingPool,
+    pool_contributions: HashMap<Principal, PoolContribution>,
+    pool_transactions: Vec<PoolTxRecord>,
+    provisioning_policy: ProvisioningPolicy,
+    loss_account: LossAccount,
+    write_offs: HashMap<String, WriteOff>,
+    capitalized_arrears: HashMap<String, CapitalizedArrears>, // loan_id -> arrears in principal
+    disbursing: HashSet<String>, // Loans whose disbursement is waiting on the wallet
+    wallet_canister: Option<Principal>, // Holds the member savings the pool is funded from
+    governance_canister: Option<Principal>, // Allowed to change the interest rates
+    treasury_canister: Option<Principal>, // Receives the income the cooperative keeps
+    treasury_payable: HashMap<TreasuryIncomeSource, f64>, // Income collected but not yet transferred
+    interest_spread_percent: f64, // Share of interest and profit kept for the treasury
     next_loan_id: u64,
     next_payment_id: u64,
+    next_pool_tx_id: u64,
 }
 
 thread_local! {
     static STATE: RefCell<LoansStorage> = RefCell::new(LoansStorage::default());
 }
 
-#[update]
-fn apply_for_loan(amount: f64, term_months: u8, purpose: LoanType) -> LoanApplication {
+#[init]
+fn init() {
+    // The deploying principal becomes the first administrator
     let caller = ic_cdk::caller();
-    
-    // Calculate interest rate based on loan type
-    let interest_rate = match purpose {
-        LoanType::Personal => 10.0,    // 10% interest
-        LoanType::Business => 8.5,     // 8.5% interest
-        LoanType::Education => 5.0,    // 5% interest
-        LoanType::Housing => 7.0,      // 7% interest
-        LoanType::Agriculture => 6.0,  // 6% interest
-        LoanType::Medical => 4.5,      // 4.5% interest
-    };
-    
-    // Simple monthly payment calculation (principal + interest / months)
-    let total_interest = amount * interest_rate * (term_months as f64 / 12.0) / 100.0;
-    let monthly_payment = (amount + total_interest) / (term_months as f64);
-    
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        
-        let loan_id = state.next_loan_id;
-        state.next_loan_id += 1;
-        
-        let loan = LoanApplication {
-            id: format!("LOAN-{}", loan_id),
-            principal: caller,
-            amount,
-            term_months,
-            interest_rate,
-            purpose,
-            application_date: time(),
-            status: LoanStatus::Pending,
-            approval_date: None,
-            collateral_amount: None,
-            credit_score: None,
-            monthly_payment,
-        };
-        
-        state.loans.insert(loan.id.clone(), loan.clone());
-        loan
-    })
+        state.staff.insert(caller, StaffRole::Admin);
+        state.interest_spread_percent = DEFAULT_INTEREST_SPREAD_PERCENT;
+    });
 }
 
-#[query]
-fn get_loans() -> Vec<LoanApplication> {
-    let caller = ic_cdk::caller();
-    
-    STATE.with(|state| {
-        let state = state.borrow();
-        state
-            .loans
-            .values()
-            .filter(|loan| loan.principal == caller)
-            .cloned()
-            .collect()
-    })
+fn require_role(state: &LoansStorage, caller: &Principal, allowed: &[StaffRole]) {
+    match state.staff.get(caller) {
+        Some(role) if allowed.contains(role) => {}
+        _ => ic_cdk::trap("Caller is not authorized for this operation"),
+    }
 }
 
-#[query]
-fn get_loan_details(loan_id: String) -> Option<LoanApplication> {
-    let caller = ic_cdk::caller();
+fn default_product(loan_type: &LoanType) -> LoanProduct {
+    let (interest_rate, min_amount, max_amount, allowed_terms, max_concurrent_loans, eligibility_cap_percent) =
+        match loan_type {
+            LoanType::Personal => (10.0, 100.0, 5_000.0, vec![3, 6, 12, 24], 2, 100.0),
+            LoanType::Business => (8.5, 500.0, 20_000.0, vec![6, 12, 24, 36], 1, 200.0),
+            LoanType::Education => (5.0, 100.0, 10_000.0, vec![12, 24, 36, 48], 1, 150.0),
+            LoanType::Housing => (7.0, 1_000.0, 50_000.0, vec![60, 120, 180, 240], 1, 300.0),
+            LoanType::Agriculture => (6.0, 200.0, 15_000.0, vec![3, 6, 9, 12, 18], 2, 200.0),
+            LoanType::Medical => (4.5, 50.0, 5_000.0, vec![3, 6, 12], 2, 100.0),
+        };
     
-    STATE.with(|state| {
-        let state = state.borrow();
-        state
-            .loans
-            .get(&loan_id)
-            .filter(|loan| loan.principal == caller)
-            .cloned()
-    })
+    LoanProduct {
+        loan_type: loan_type.clone(),
+        financing_model: FinancingModel::Conventional,
+        interest_rate,
+        min_amount,
+        max_amount,
+        allowed_terms,
+        max_concurrent_loans,
+        eligibility_cap_percent,
+    }
 }
 
-#[update]
-fn approve_loan(loan_id: String) -> LoanApplication {
-    // In a real application, we would check if the caller is an admin
-    
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        if let Some(loan) = state.loans.get_mut(&loan_id) {
-            loan.status = LoanStatus::Approved;
-            loan.approval_date = Some(time());
-            loan.clone()
-        } else {
-            ic_cdk::trap("Loan not found");
-        }
-    })
+fn product_for(state: &LoansStorage, loan_type: &LoanType) -> LoanProduct {
+    state
+        .products
+        .get(loan_type)
+        .cloned()
+        .unwrap_or_else(|| default_product(loan_type))
 }
 
-#[update]
-fn reject_loan(loan_id: String) -> LoanApplication {
-    // In a real application, we would check if the caller is an admin
-    
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        if let Some(loan) = state.loans.get_mut(&loan_id) {
-            loan.status = LoanStatus::Rejected;
-            loan.clone()
-        } else {
-            ic_cdk::trap("Loan not found");
-        }
-    })
+fn is_open(loan: &LoanApplication) -> bool {
+    loan.status == LoanStatus::Pending || is_outstanding(loan)
 }
 
-#[update]
-fn make_payment(loan_id: String, amount: f64) -> LoanPayment {
-    let caller = ic_cdk::caller();
+// The member's share of a loan: all of an individual loan, their allocation
+// of a group loan once they have accepted it
+fn borrower_share(state: &LoansStorage, loan: &LoanApplication, member: &Principal) -> Option<f64> {
+    match state.group_loans.get(&loan.id) {
+        Some(group) => group.members.iter().find(|m| m.member == *member && m.accepted).map(|m| m.share),
+        None if loan.principal == *member => Some(1.0),
+        None => None,
+    }
+}
+
+fn is_borrower(state: &LoansStorage, loan: &LoanApplication, member: &Principal) -> bool {
+    borrower_share(state, loan, member).is_some()
+}
+
+// Named on the loan, including group members who have not accepted yet
+fn is_party(state: &LoansStorage, loan: &LoanApplication, member: &Principal) -> bool {
+    loan.principal == *member
+        || state
+            .group_loans
+            .get(&loan.id)
+            .is_some_and(|group| group.members.iter().any(|m| m.member == *member))
+}
+
+fn can_view(state: &LoansStorage, loan: &LoanApplication, caller: &Principal) -> bool {
+    is_party(state, loan, caller) || state.staff.contains_key(caller)
+}
+
+// Base eligibility less the member's share of principal still owed on open loans
+fn member_eligibility(state: &LoansStorage, member: &Principal) -> f64 {
+    let owed: f64 = state
+        .loans
+        .values()
+        .filter(|loan| is_open(loan))
+        .filter_map(|loan| Some(borrower_share(state, loan, member)? * outstanding_principal(state, loan)))
+        .sum();
+    (BASE_ELIGIBILITY - owed).max(0.0)
+}
+
+fn validate_application(
+    state: &LoansStorage,
+    member: &Principal,
+    product: &LoanProduct,
+    amount: f64,
+    term_months: u8,
+) -> Result<(), LoanValidationError> {
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(LoanValidationError::InvalidAmount);
+    }
+    if amount < product.min_amount {
+        return Err(LoanValidationError::AmountBelowMinimum { minimum: product.min_amount });
+    }
+    if amount > product.max_amount {
+        return Err(LoanValidationError::AmountAboveMaximum { maximum: product.max_amount });
+    }
+    if term_months == 0 || !product.allowed_terms.contains(&term_months) {
+        return Err(LoanValidationError::TermNotAllowed {
+            allowed_terms: product.allowed_terms.clone(),
+        });
+    }
     
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        
-        // Check if loan exists and belongs to caller
-        if let Some(_) = state.loans.get(&loan_id).filter(|loan| loan.principal == caller) {
-            let payment_id = state.next_payment_id;
-            state.next_payment_id += 1;
-            
-            let payment = LoanPayment {
-                id: format!("PMT-{}", payment_id),
-                loan_id: loan_id.clone(),
-                principal: caller,
-                amount,
-                timestamp: time(),
-                status: PaymentStatus::Completed, // In a real app, this would be pending until confirmed
-            };
-            
-            // Add payment to the loan's payment history
-            state.payments.entry(loan_id).or_insert_with(Vec::new).push(payment.clone());
-            
-            // TODO: Update loan status based on payments (e.g., if fully paid off)
-            
-            payment
-        } else {
-            ic_cdk::trap("Loan not found or does not belong to caller");
-        }
-    })
+    let concurrent = state
+        .loans
+        .values()
+        .filter(|loan| loan.purpose == product.loan_type && is_open(loan) && is_borrower(state, loan, member))
+        .count();
+    if concurrent >= product.max_concurrent_loans as usize {
+        return Err(LoanValidationError::TooManyConcurrentLoans {
+            limit: product.max_concurrent_loans,
+        });
+    }
+    
+    let eligible_amount = member_eligibility(state, member) * product.eligibility_cap_percent / 100.0;
+    if amount > eligible_amount {
+        return Err(LoanValidationError::ExceedsEligibility { eligible_amount });
+    }
+    
+    Ok(())
 }
 
-#[query]
-fn get_payments(loan_id: String) -> Vec<LoanPayment> {
-    let caller = ic_cdk::caller();
+fn validate_group(
+    state: &LoansStorage,
+    applicant: &Principal,
+    product: &LoanProduct,
+    allocations: &[GroupAllocation],
+    term_months: u8,
+) -> Result<(), LoanValidationError> {
+    let invalid = |reason: &str| LoanValidationError::InvalidGroup { reason: reason.to_string() };
     
-    STATE.with(|state| {
-        let state = state.borrow();
-        
-        // First, check if the loan belongs to the caller
-        if let Some(loan) = state.loans.get(&loan_id) {
-            if loan.principal != caller {
-                return Vec::new(); // Return empty vector if loan doesn't belong to caller
-            }
-        } else {
-            return Vec::new(); // Loan not found
+    if allocations.len() < MIN_GROUP_SIZE || allocations.len() > MAX_GROUP_SIZE {
+        return Err(invalid("Groups need between 2 and 30 members"));
+    }
+    if !allocations.iter().any(|a| a.member == *applicant) {
+        return Err(invalid("The applicant must be a member of the group"));
+    }
+    for (i, allocation) in allocations.iter().enumerate() {
+        if allocations[..i].iter().any(|a| a.member == allocation.member) {
+            return Err(invalid("Each member can only appear once"));
         }
-        
-        // Return payments for the loan
+    }
+    
+    // Each member's allocation must meet the product rules on its own
+    for allocation in allocations {
+        validate_application(state, &allocation.member, product, allocation.amount, term_months).map_err(
+            |error| LoanValidationError::GroupMemberIneligible {
+                member: allocation.member,
+                error: Box::new(error),
+            },
+        )?;
+    }
+    
+    Ok(())
+}
+
+fn loan_number(loan_id: &str) -> u64 {
+    loan_id.trim_start_matches("LOAN-").parse().unwrap_or(u64::MAX)
+}
+
+fn total_interest(amount: f64, interest_rate: f64, term_months: u8) -> f64 {
+    amount * interest_rate * (term_months as f64 / 12.0) / 100.0
+}
+
+fn financing_charge(model: &FinancingModel, amount: f64) -> f64 {
+    match model {
+        FinancingModel::Conventional => 0.0,
+        FinancingModel::QardHasan { administration_fee } => *administration_fee,
+        FinancingModel::Murabaha { profit_percent } => amount * profit_percent / 100.0,
+    }
+}
+
+// Late fees apply to every model, but penalty interest only to conventional loans
+fn accrues_penalty_interest(loan: &LoanApplication) -> bool {
+    matches!(loan.financing_model, FinancingModel::Conventional)
+}
+
+fn is_disbursed(loan: &LoanApplication) -> bool {
+    matches!(
+        loan.status,
+        LoanStatus::Approved
+            | LoanStatus::Active
+            | LoanStatus::PaidOff
+            | LoanStatus::Defaulted
+            | LoanStatus::WrittenOff
+    )
+}
+
+fn is_outstanding(loan: &LoanApplication) -> bool {
+    matches!(
+        loan.status,
+        LoanStatus::Approved | LoanStatus::Active | LoanStatus::Defaulted
+    )
+}
+
+fn completed_payments<'a>(state: &'a LoansStorage, loan_id: &str) -> impl Iterator<Item = &'a LoanPayment> {
+    state
+        .payments
+        .get(loan_id)
+        .into_iter()
+        .flatten()
+        .filter(|payment| matches!(payment.status, PaymentStatus::Completed))
+}
+
+fn outstanding_principal(state: &LoansStorage, loan: &LoanApplication) -> f64 {
+    match state.schedules.get(&loan.id) {
+        Some(schedule) => schedule.iter().map(|i| i.remaining_principal()).sum(),
+        None => loan.amount,
+    }
+}
+
+// Days since the oldest unpaid installment fell due
+fn days_past_due(state: &LoansStorage, loan: &LoanApplication, now: u64) -> u64 {
+    state
+        .schedules
+        .get(&loan.id)
+        .and_then(|schedule| {
+            schedule
+                .iter()
+                .find(|installment| installment.status != InstallmentStatus::Paid)
+        })
+        .map_or(0, |installment| now.saturating_sub(installment.due_date) / NANOS_PER_DAY)
+}
+
+// Equal monthly installments of principal and flat interest, the last one
+// absorbing any rounding difference.
+fn build_schedule(loan: &LoanApplication, disbursed_at: u64, policy: &LatePaymentPolicy) -> Vec<Installment> {
+    let term = loan.term_months as u64;
+    let interest = total_interest(loan.amount, loan.interest_rate, loan.term_months);
+    let principal_per_installment = loan.amount / term as f64;
+    let interest_per_installment = interest / term as f64;
+    let charge_per_installment = loan.financing_charge / term as f64;
+    
+    (1..=term)
+        .map(|number| {
+            let due_date = disbursed_at + number * NANOS_PER_MONTH;
+            let grace_period_end = due_date + policy.grace_period_days * NANOS_PER_DAY;
+            let (principal_due, interest_due, charge_due) = if number == term {
+                (
+                    loan.amount - principal_per_installment * (term - 1) as f64,
+                    interest - interest_per_installment * (term - 1) as f64,
+                    loan.financing_charge - charge_per_installment * (term - 1) as f64,
+                )
+            } else {
+                (principal_per_installment, interest_per_installment, charge_per_installment)
+            };
+            
+            Installment {
+                number: number as u8,
+                due_date,
+                grace_period_end,
+                principal_due,
+                interest_due,
+                charge_due,
+                fees_due: 0.0,
+                principal_paid: 0.0,
+                interest_paid: 0.0,
+                charge_paid: 0.0,
+                fees_paid: 0.0,
+                late_fee_charged: false,
+                penalty_accrued_until: grace_period_end,
+                late_fee_percent: policy.late_fee_percent,
+                penalty_rate: policy.penalty_rate,
+                status: InstallmentStatus::Pending,
+            }
+        })
+        .collect()
+}
+
+// Charges the one-off late fee and, when `penalty_interest` is set, accrues
+// daily penalty interest on every installment still unpaid after its grace period.
+fn assess_late_charges(schedule: &mut [Installment], penalty_interest: bool, now: u64) {
+    for installment in schedule.iter_mut() {
+        if installment.status == InstallmentStatus::Paid || now <= installment.grace_period_end {
+            continue;
+        }
+        installment.status = InstallmentStatus::Overdue;
+        
+        let overdue_amount = installment.remaining_interest()
+            + installment.remaining_charge()
+            + installment.remaining_principal();
+        if !installment.late_fee_charged {
+            installment.fees_due += overdue_amount * installment.late_fee_percent / 100.0;
+            installment.late_fee_charged = true;
+        }
+        
+        let days = now.saturating_sub(installment.penalty_accrued_until) / NANOS_PER_DAY;
+        if penalty_interest && days > 0 {
+            installment.fees_due += overdue_amount * installment.penalty_rate / 100.0 / 365.0 * days as f64;
+            installment.penalty_accrued_until += days * NANOS_PER_DAY;
+        }
+    }
+}
+
+// Applies a payment to installments oldest first, settling fees, then
+// interest or financing charge, then principal on each.
+fn allocate_payment(schedule: &mut [Installment], amount: f64) -> Allocation {
+    let mut remaining = amount;
+    let mut allocation = Allocation::default();
+    
+    for installment in schedule.iter_mut() {
+        if remaining <= AMOUNT_EPSILON {
+            break;
+        }
+        if installment.status == InstallmentStatus::Paid {
+            continue;
+        }
+        
+        let to_fees = remaining.min(installment.remaining_fees());
+        installment.fees_paid += to_fees;
+        remaining -= to_fees;
+        
+        let to_interest = remaining.min(installment.remaining_interest());
+        installment.interest_paid += to_interest;
+        remaining -= to_interest;
+        
+        let to_charge = remaining.min(installment.remaining_charge());
+        installment.charge_paid += to_charge;
+        remaining -= to_charge;
+        
+        let to_principal = remaining.min(installment.remaining_principal());
+        installment.principal_paid += to_principal;
+        remaining -= to_principal;
+        
+        if installment.remaining() <= AMOUNT_EPSILON {
+            installment.status = InstallmentStatus::Paid;
+        }
+        allocation.fees += to_fees;
+        allocation.interest += to_interest;
+        allocation.charges += to_charge;
+        allocation.principal += to_principal;
+    }
+    
+    allocation
+}
+
+// The loan's schedule with late charges assessed as of `now`, for queries
+fn schedule_as_of(state: &LoansStorage, loan: &LoanApplication, now: u64) -> Vec<Installment> {
+    let mut schedule = state.schedules.get(&loan.id).cloned().unwrap_or_default();
+    let penalty_interest = accrues_penalty_interest(loan);
+    assess_late_charges(&mut schedule, penalty_interest, now);
+    schedule
+}
+
+// Interest an installment has earned by `at_time`: all of it once due,
+// pro rata over the month leading up to the due date, nothing before that.
+fn earned_interest(installment: &Installment, at_time: u64) -> f64 {
+    let period_start = installment.due_date.saturating_sub(NANOS_PER_MONTH);
+    if at_time >= installment.due_date {
+        installment.interest_due
+    } else if at_time <= period_start {
+        0.0
+    } else {
+        installment.interest_due * (at_time - period_start) as f64 / NANOS_PER_MONTH as f64
+    }
+}
+
+fn payoff_quote(loan_id: &str, schedule: &[Installment], at_time: u64) -> PayoffQuote {
+    let mut quote = PayoffQuote {
+        loan_id: loan_id.to_string(),
+        quoted_at: at_time,
+        outstanding_principal: 0.0,
+        accrued_interest: 0.0,
+        outstanding_charges: 0.0,
+        outstanding_fees: 0.0,
+        interest_waived: 0.0,
+        total: 0.0,
+    };
+    
+    for installment in schedule.iter().filter(|i| i.status != InstallmentStatus::Paid) {
+        let accrued = (earned_interest(installment, at_time) - installment.interest_paid).max(0.0);
+        quote.outstanding_principal += installment.remaining_principal();
+        quote.outstanding_fees += installment.remaining_fees();
+        quote.accrued_interest += accrued;
+        quote.interest_waived += installment.remaining_interest() - accrued;
+        
+        // A disclosed financing charge is fixed and is not rebated on early settlement
+        quote.outstanding_charges += installment.remaining_charge();
+    }
+    
+    quote.total = quote.outstanding_principal
+        + quote.accrued_interest
+        + quote.outstanding_charges
+        + quote.outstanding_fees;
+    quote
+}
+
+// Settles every installment, waiving interest not yet earned at `now`
+fn settle_schedule(schedule: &mut [Installment], now: u64) -> Allocation {
+    let mut allocation = Allocation::default();
+    
+    for installment in schedule.iter_mut().filter(|i| i.status != InstallmentStatus::Paid) {
+        let interest_owed = earned_interest(installment, now).max(installment.interest_paid);
+        allocation.fees += installment.remaining_fees();
+        allocation.interest += interest_owed - installment.interest_paid;
+        allocation.charges += installment.remaining_charge();
+        allocation.principal += installment.remaining_principal();
+        
+        installment.fees_paid = installment.fees_due;
+        installment.interest_due = interest_owed;
+        installment.interest_paid = interest_owed;
+        installment.charge_paid = installment.charge_due;
+        installment.principal_paid = installment.principal_due;
+        installment.status = InstallmentStatus::Paid;
+    }
+    
+    allocation
+}
+
+// Rebuilds the installments falling due after `now` around the reduced
+// principal, either keeping their number (smaller installments) or keeping
+// the installment size (fewer installments). Interest on the new schedule
+// is charged flat on the reduced principal; any financing charge still owed
+// is spread over the new installments unchanged.
+fn reschedule_after_prepayment(
+    schedule: &mut Vec<Installment>,
+    interest_rate: f64,
+    monthly_payment: f64,
+    mode: &PrepaymentMode,
+    now: u64,
+) {
+    let first_future = match schedule.iter().position(|i| i.due_date > now && i.status != InstallmentStatus::Paid) {
+        Some(index) => index,
+        None => return,
+    };
+    let future = schedule.split_off(first_future);
+    let outstanding: f64 = future.iter().map(|i| i.remaining_principal()).sum();
+    let charges: f64 = future.iter().map(|i| i.remaining_charge()).sum();
+    
+    // Interest and fees already paid towards future installments carry over
+    let credit: f64 = future.iter().map(|i| i.interest_paid + i.fees_paid).sum();
+    let template = &future[0];
+    let grace = template.grace_period_end - template.due_date;
+    
+    let monthly_interest = outstanding * interest_rate / 1200.0;
+    let count = match mode {
+        PrepaymentMode::ReduceInstallment => future.len(),
+        PrepaymentMode::ShortenTerm if monthly_payment > monthly_interest + AMOUNT_EPSILON => {
+            let needed = ((outstanding + charges) / (monthly_payment - monthly_interest) - AMOUNT_EPSILON).ceil();
+            (needed.max(1.0) as usize).min(future.len())
+        }
+        PrepaymentMode::ShortenTerm => future.len(),
+    };
+    
+    let principal_per_installment = outstanding / count as f64;
+    let charge_per_installment = charges / count as f64;
+    let mut rebuilt: Vec<Installment> = (0..count)
+        .map(|i| {
+            let due_date = template.due_date + i as u64 * NANOS_PER_MONTH;
+            let principal_due = if i + 1 == count {
+                outstanding - principal_per_installment * (count - 1) as f64
+            } else {
+                principal_per_installment
+            };
+            
+            Installment {
+                number: template.number + i as u8,
+                due_date,
+                grace_period_end: due_date + grace,
+                principal_due,
+                interest_due: monthly_interest,
+                charge_due: charge_per_installment,
+                fees_due: 0.0,
+                principal_paid: 0.0,
+                interest_paid: 0.0,
+                charge_paid: 0.0,
+                fees_paid: 0.0,
+                late_fee_charged: false,
+                penalty_accrued_until: due_date + grace,
+                late_fee_percent: template.late_fee_percent,
+                penalty_rate: template.penalty_rate,
+                status: InstallmentStatus::Pending,
+            }
+        })
+        .collect();
+    
+    allocate_payment(&mut rebuilt, credit);
+    schedule.extend(rebuilt);
+}
+
+// Replaces every unpaid installment with a new schedule over the remaining
+// and extended term, starting after any payment holiday. Interest is charged
+// flat on the restructured principal for the whole period including the
+// holiday. A financing charge still owed is spread over the new installments
+// without being increased. Returns the interest and fees capitalized into
+// principal.
+fn restructure_schedule(
+    schedule: &mut Vec<Installment>,
+    request: &RestructureRequest,
+    interest_rate: f64,
+    policy: &LatePaymentPolicy,
+    now: u64,
+) -> CapitalizedArrears {
+    let unpaid: Vec<Installment> = schedule
+        .iter()
+        .filter(|i| i.status != InstallmentStatus::Paid)
+        .cloned()
+        .collect();
+    schedule.retain(|i| i.status == InstallmentStatus::Paid);
+    
+    let overdue = unpaid.iter().filter(|i| i.due_date <= now);
+    let upcoming = unpaid.iter().filter(|i| i.due_date > now);
+    let arrears_fees: f64 = overdue.clone().map(|i| i.remaining_fees()).sum();
+    let arrears_interest: f64 = overdue.map(|i| i.remaining_interest()).sum();
+    let credit: f64 = upcoming.clone().map(|i| i.interest_paid + i.fees_paid).sum();
+    let charges: f64 = unpaid.iter().map(|i| i.remaining_charge()).sum();
+    let upcoming_count = upcoming.clone().count();
+    let next_due_date = upcoming.map(|i| i.due_date).min().unwrap_or(now + NANOS_PER_MONTH);
+    
+    let mut principal: f64 = unpaid.iter().map(|i| i.remaining_principal()).sum();
+    let capitalized = if request.capitalize_arrears {
+        CapitalizedArrears {
+            interest: arrears_interest,
+            fees: arrears_fees,
+        }
+    } else {
+        CapitalizedArrears::default()
+    };
+    principal += capitalized.interest + capitalized.fees;
+    
+    let count = upcoming_count as u64 + request.extend_term_months as u64;
+    let holiday = request.payment_holiday_months as u64;
+    let interest = principal * interest_rate / 1200.0 * (count + holiday) as f64;
+    let principal_per_installment = principal / count as f64;
+    let interest_per_installment = interest / count as f64;
+    let charge_per_installment = charges / count as f64;
+    let first_number = schedule.len() as u64 + 1;
+    let grace = policy.grace_period_days * NANOS_PER_DAY;
+    
+    let mut rebuilt: Vec<Installment> = (0..count)
+        .map(|i| {
+            let due_date = next_due_date + (holiday + i) * NANOS_PER_MONTH;
+            let (principal_due, interest_due) = if i + 1 == count {
+                (
+                    principal - principal_per_installment * (count - 1) as f64,
+                    interest - interest_per_installment * (count - 1) as f64,
+                )
+            } else {
+                (principal_per_installment, interest_per_installment)
+            };
+            
+            Installment {
+                number: (first_number + i) as u8,
+                due_date,
+                grace_period_end: due_date + grace,
+                principal_due,
+                interest_due,
+                charge_due: charge_per_installment,
+                fees_due: 0.0,
+                principal_paid: 0.0,
+                interest_paid: 0.0,
+                charge_paid: 0.0,
+                fees_paid: 0.0,
+                late_fee_charged: false,
+                penalty_accrued_until: due_date + grace,
+                late_fee_percent: policy.late_fee_percent,
+                penalty_rate: policy.penalty_rate,
+                status: InstallmentStatus::Pending,
+            }
+        })
+        .collect();
+    
+    // Arrears that are not capitalized remain due with the first new installment
+    if !request.capitalize_arrears {
+        rebuilt[0].fees_due += arrears_fees;
+        rebuilt[0].interest_due += arrears_interest;
+    }
+    allocate_payment(&mut rebuilt, credit);
+    schedule.extend(rebuilt);
+    
+    capitalized
+}
+
+fn record_pool_tx(
+    state: &mut LoansStorage,
+    tx_type: PoolTxType,
+    amount: f64,
+    contributor: Option<Principal>,
+    source: Option<FundingSource>,
+    loan_id: Option<String>,
+) -> PoolTxRecord {
+    let tx = PoolTxRecord {
+        id: format!("PTX-{}", state.next_pool_tx_id),
+        tx_type,
+        amount,
+        timestamp: time(),
+        contributor,
+        source,
+        loan_id,
+    };
+    state.next_pool_tx_id += 1;
+    state.pool_transactions.push(tx.clone());
+    tx
+}
+
+// Reserves pool capital for a loan being disbursed. It is recorded as a
+// disbursement once the borrower's wallet has been credited.
+fn disburse_from_pool(state: &mut LoansStorage, amount: f64) {
+    if state.pool.available_liquidity + AMOUNT_EPSILON < amount {
+        ic_cdk::trap("Insufficient lending pool liquidity to fund this loan");
+    }
+    state.pool.available_liquidity -= amount;
+    state.pool.deployed_capital += amount;
+}
+
+// Puts back capital reserved for a disbursement that did not reach the borrower
+fn cancel_disbursement(state: &mut LoansStorage, amount: f64) {
+    state.pool.available_liquidity += amount;
+    state.pool.deployed_capital = (state.pool.deployed_capital - amount).max(0.0);
+}
+
+// Sets income aside for the treasury until it is transferred
+fn owe_treasury(state: &mut LoansStorage, source: TreasuryIncomeSource, amount: f64) {
+    *state.treasury_payable.entry(source).or_insert(0.0) += amount;
+    state.pool.treasury_income += amount;
+}
+
+// Keeps the treasury's spread of interest or profit and returns the part
+// left for contributors
+fn retain_spread(state: &mut LoansStorage, income: f64) -> f64 {
+    let spread = income * state.interest_spread_percent / 100.0;
+    if spread > 0.0 {
+        owe_treasury(state, TreasuryIncomeSource::InterestSpread, spread);
+    }
+    income - spread
+}
+
+// Shares income among contributors in proportion to the capital they
+// provided. Returns false when there is no capital to share it with.
+fn distribute_to_contributors(state: &mut LoansStorage, income: f64) -> bool {
+    let total_capital = state.pool.total_capital;
+    if total_capital <= 0.0 {
+        return false;
+    }
+    for contribution in state.pool_contributions.values_mut() {
+        let capital = contribution.savings_capital + contribution.deposit_capital;
+        contribution.earnings += income * capital / total_capital;
+    }
+    true
+}
+
+// Principal repaid on a restructured loan partly repays capitalized arrears,
+// in proportion to the share of the outstanding principal they make up.
+// Moves that part back to interest and fees for the pool.
+fn split_capitalized_arrears(state: &mut LoansStorage, loan_id: &str, allocation: &Allocation) -> Allocation {
+    let outstanding_after: f64 = state
+        .schedules
+        .get(loan_id)
+        .map_or(0.0, |schedule| schedule.iter().map(|i| i.remaining_principal()).sum());
+    let outstanding_before = outstanding_after + allocation.principal;
+    
+    let (interest, fees) = match state.capitalized_arrears.get_mut(loan_id) {
+        Some(capitalized) if outstanding_before > 0.0 => {
+            let ratio = (allocation.principal / outstanding_before).min(1.0);
+            let (interest, fees) = (capitalized.interest * ratio, capitalized.fees * ratio);
+            capitalized.interest -= interest;
+            capitalized.fees -= fees;
+            (interest, fees)
+        }
+        _ => (0.0, 0.0),
+    };
+    
+    Allocation {
+        fees: allocation.fees + fees,
+        interest: allocation.interest + interest,
+        charges: allocation.charges,
+        principal: allocation.principal - interest - fees,
+    }
+}
+
+// Returns repaid principal to the pool, sets fees aside for the treasury and
+// shares interest or murabaha profit among contributors after the treasury's
+// spread. Financing charges are recorded as profit or administration fees,
+// never as interest.
+fn settle_payment_to_pool(state: &mut LoansStorage, loan_id: &str, model: &FinancingModel, allocation: &Allocation) {
+    let allocation = &split_capitalized_arrears(state, loan_id, allocation);
+    let loan_id = Some(loan_id.to_string());
+    let principal = allocation.principal;
+    
+    if principal > 0.0 {
+        state.pool.available_liquidity += principal;
+        state.pool.deployed_capital = (state.pool.deployed_capital - principal).max(0.0);
+        record_pool_tx(state, PoolTxType::PrincipalRepayment, principal, None, None, loan_id.clone());
+    }
+    if allocation.fees > 0.0 {
+        state.pool.fee_income += allocation.fees;
+        owe_treasury(state, TreasuryIncomeSource::PenaltyIncome, allocation.fees);
+        record_pool_tx(state, PoolTxType::Fee, allocation.fees, None, None, loan_id.clone());
+    }
+    if allocation.interest > 0.0 {
+        state.pool.interest_collected += allocation.interest;
+        record_pool_tx(state, PoolTxType::Interest, allocation.interest, None, None, loan_id.clone());
+        let shared = retain_spread(state, allocation.interest);
+        if distribute_to_contributors(state, shared) {
+            state.pool.interest_distributed += shared;
+        }
+    }
+    if allocation.charges > 0.0 {
+        match model {
+            FinancingModel::Murabaha { .. } => {
+                state.pool.profit_collected += allocation.charges;
+                record_pool_tx(state, PoolTxType::Profit, allocation.charges, None, None, loan_id);
+                let shared = retain_spread(state, allocation.charges);
+                if distribute_to_contributors(state, shared) {
+                    state.pool.profit_distributed += shared;
+                }
+            }
+            _ => {
+                state.pool.fee_income += allocation.charges;
+                owe_treasury(state, TreasuryIncomeSource::Fees, allocation.charges);
+                record_pool_tx(state, PoolTxType::AdministrationFee, allocation.charges, None, None, loan_id);
+            }
+        }
+    }
+}
+
+// Spreads a capital loss (negative) or recovery (positive) across
+// contributors in proportion to the capital they currently hold.
+fn adjust_pool_capital(state: &mut LoansStorage, delta: f64) {
+    let total_capital = state.pool.total_capital;
+    if total_capital > 0.0 {
+        let factor = ((total_capital + delta) / total_capital).max(0.0);
+        for contribution in state.pool_contributions.values_mut() {
+            contribution.savings_capital *= factor;
+            contribution.deposit_capital *= factor;
+        }
+    }
+    state.pool.total_capital = (total_capital + delta).max(0.0);
+}
+
+// Payments on a written-off loan are recoveries: they go back to the pool
+// instead of the repayment schedule. The written-off principal is recovered
+// first and returned to contributors' capital; anything beyond it is interest,
+// charges or fees, and is settled like any other income.
+fn record_recovery(state: &mut LoansStorage, loan_id: &str, payer: Principal, amount: f64, now: u64) -> LoanPayment {
+    let model = match state.loans.get(loan_id) {
+        Some(loan) => loan.financing_model.clone(),
+        None => ic_cdk::trap("Loan not found"),
+    };
+    let write_off = match state.write_offs.get_mut(loan_id) {
+        Some(write_off) => write_off,
+        None => ic_cdk::trap("Write-off record not found"),
+    };
+    let unrecovered =
+        write_off.principal + write_off.interest + write_off.charges + write_off.fees - write_off.recovered;
+    if amount > unrecovered + AMOUNT_EPSILON {
+        ic_cdk::trap("Payment exceeds the written-off balance");
+    }
+    
+    // Earlier recoveries used up each part in the same order
+    let mut previously_recovered = write_off.recovered;
+    let mut remaining = amount;
+    let mut recover = |written_off: f64| {
+        let outstanding = (written_off - previously_recovered).max(0.0);
+        previously_recovered = (previously_recovered - written_off).max(0.0);
+        let recovered = remaining.min(outstanding);
+        remaining -= recovered;
+        recovered
+    };
+    let allocation = Allocation {
+        principal: recover(write_off.principal),
+        interest: recover(write_off.interest),
+        charges: recover(write_off.charges),
+        fees: recover(write_off.fees),
+    };
+    write_off.recovered += amount;
+    
+    state.loss_account.recoveries += amount;
+    if allocation.principal > 0.0 {
+        state.pool.available_liquidity += allocation.principal;
+        adjust_pool_capital(state, allocation.principal);
+        record_pool_tx(state, PoolTxType::Recovery, allocation.principal, None, None, Some(loan_id.to_string()));
+    }
+    let income = Allocation {
+        fees: allocation.fees,
+        interest: allocation.interest,
+        charges: allocation.charges,
+        principal: 0.0,
+    };
+    settle_payment_to_pool(state, loan_id, &model, &income);
+    
+    let payment = LoanPayment {
+        id: format!("PMT-{}", state.next_payment_id),
+        loan_id: loan_id.to_string(),
+        principal: payer,
+        amount,
+        fees_portion: allocation.fees,
+        interest_portion: allocation.interest,
+        charge_portion: allocation.charges,
+        principal_portion: allocation.principal,
+        timestamp: now,
+        status: PaymentStatus::Completed,
+    };
+    state.next_payment_id += 1;
+    state.payments.entry(loan_id.to_string()).or_default().push(payment.clone());
+    
+    let kind = LoanEventKind::Recovery {
+        payment_id: payment.id.clone(),
+        amount,
+    };
+    record_event(state, loan_id, payer, kind, None);
+    payment
+}
+
+fn current_terms(state: &LoansStorage, loan: &LoanApplication) -> LoanTerms {
+    LoanTerms {
+        term_months: loan.term_months,
+        interest_rate: loan.interest_rate,
+        monthly_payment: loan.monthly_payment,
+        outstanding_principal: outstanding_principal(state, loan),
+    }
+}
+
+fn record_event(
+    state: &mut LoansStorage,
+    loan_id: &str,
+    actor: Principal,
+    kind: LoanEventKind,
+    previous_terms: Option<LoanTerms>,
+) {
+    let terms = match state.loans.get(loan_id) {
+        Some(loan) => current_terms(state, loan),
+        None => return,
+    };
+    
+    state.history.entry(loan_id.to_string()).or_default().push(LoanEvent {
+        timestamp: time(),
+        actor,
+        kind,
+        previous_terms,
+        terms,
+    });
+}
+
+#[update]
+fn apply_for_loan(amount: f64, term_months: u8, purpose: LoanType) -> Result<LoanApplication, LoanValidationError> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        // Interest rate and limits come from the loan type's product rules
+        let product = product_for(&state, &purpose);
+        validate_application(&state, &caller, &product, amount, term_months)?;
+        let interest_rate = product.interest_rate;
+        let financing_charge = financing_charge(&product.financing_model, amount);
+        
+        // Simple monthly payment calculation (principal + interest or charge / months)
+        let total_interest = total_interest(amount, interest_rate, term_months);
+        let monthly_payment = (amount + total_interest + financing_charge) / (term_months as f64);
+        
+        let loan_id = state.next_loan_id;
+        state.next_loan_id += 1;
+        
+        let loan = LoanApplication {
+            id: format!("LOAN-{}", loan_id),
+            principal: caller,
+            amount,
+            term_months,
+            interest_rate,
+            purpose,
+            application_date: time(),
+            status: LoanStatus::Pending,
+            approval_date: None,
+            collateral_amount: None,
+            credit_score: None,
+            monthly_payment,
+            financing_model: product.financing_model,
+            financing_charge,
+            group_name: None,
+        };
+        
+        state.loans.insert(loan.id.clone(), loan.clone());
+        record_event(&mut state, &loan.id, caller, LoanEventKind::Applied, None);
+        Ok(loan)
+    })
+}
+
+#[update]
+fn apply_for_group_loan(
+    group_name: String,
+    allocations: Vec<GroupAllocation>,
+    term_months: u8,
+    purpose: LoanType,
+) -> Result<LoanApplication, LoanValidationError> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        if group_name.trim().is_empty() {
+            return Err(LoanValidationError::InvalidGroup {
+                reason: "Group name cannot be empty".to_string(),
+            });
+        }
+        let product = product_for(&state, &purpose);
+        validate_group(&state, &caller, &product, &allocations, term_months)?;
+        
+        let amount: f64 = allocations.iter().map(|a| a.amount).sum();
+        let interest_rate = product.interest_rate;
+        let financing_charge = financing_charge(&product.financing_model, amount);
+        let total_interest = total_interest(amount, interest_rate, term_months);
+        let monthly_payment = (amount + total_interest + financing_charge) / (term_months as f64);
+        
+        let loan_id = format!("LOAN-{}", state.next_loan_id);
+        state.next_loan_id += 1;
+        
+        // The applicant signs for the group; every member is jointly liable
+        let loan = LoanApplication {
+            id: loan_id.clone(),
+            principal: caller,
+            amount,
+            term_months,
+            interest_rate,
+            purpose,
+            application_date: time(),
+            status: LoanStatus::Pending,
+            approval_date: None,
+            collateral_amount: None,
+            credit_score: None,
+            monthly_payment,
+            financing_model: product.financing_model,
+            financing_charge,
+            group_name: Some(group_name.clone()),
+        };
+        let group = GroupLoan {
+            loan_id: loan_id.clone(),
+            group_name,
+            members: allocations
+                .iter()
+                .map(|a| GroupMemberShare {
+                    member: a.member,
+                    amount: a.amount,
+                    share: a.amount / amount,
+                    monthly_installment: monthly_payment * a.amount / amount,
+                    accepted: a.member == caller,
+                })
+                .collect(),
+        };
+        
+        state.loans.insert(loan_id.clone(), loan.clone());
+        state.group_loans.insert(loan_id.clone(), group);
+        record_event(&mut state, &loan_id, caller, LoanEventKind::Applied, None);
+        Ok(loan)
+    })
+}
+
+// A named group member agrees to be jointly liable. Their allocation is
+// checked against their own limits at this point, since it only counts
+// toward them from now on.
+#[update]
+fn accept_group_loan(loan_id: String) -> Result<LoanApplication, LoanValidationError> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        
+        let loan = match state.loans.get(&loan_id) {
+            Some(loan) => loan.clone(),
+            None => ic_cdk::trap("Loan not found"),
+        };
+        if loan.status != LoanStatus::Pending {
+            ic_cdk::trap("Only pending group loans can be accepted");
+        }
+        let amount = match state.group_loans.get(&loan_id).and_then(|g| g.members.iter().find(|m| m.member == caller)) {
+            Some(share) if share.accepted => ic_cdk::trap("Group loan already accepted"),
+            Some(share) => share.amount,
+            None => ic_cdk::trap("Caller is not a member of this group loan"),
+        };
+        
+        let product = product_for(state, &loan.purpose);
+        validate_application(state, &caller, &product, amount, loan.term_months)?;
+        
+        let group = state.group_loans.get_mut(&loan_id).unwrap();
+        if let Some(share) = group.members.iter_mut().find(|m| m.member == caller) {
+            share.accepted = true;
+        }
+        record_event(state, &loan_id, caller, LoanEventKind::GroupMemberAccepted { member: caller }, None);
+        Ok(loan)
+    })
+}
+
+#[query]
+fn get_group_repayment_status(loan_id: String) -> GroupRepaymentStatus {
+    let caller = ic_cdk::caller();
+    let now = time();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        let loan = match state.loans.get(&loan_id).filter(|loan| can_view(&state, loan, &caller)) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found or does not belong to caller"),
+        };
+        let group = match state.group_loans.get(&loan_id) {
+            Some(group) => group,
+            None => ic_cdk::trap("Not a group loan"),
+        };
+        
+        let schedule = schedule_as_of(&state, loan, now);
+        let due_to_date: f64 = schedule
+            .iter()
+            .filter(|i| i.due_date <= now)
+            .map(|i| i.principal_due + i.interest_due + i.charge_due + i.fees_due)
+            .sum();
+        let mut paid_by_member: HashMap<Principal, f64> = HashMap::new();
+        for payment in completed_payments(&state, &loan_id) {
+            *paid_by_member.entry(payment.principal).or_insert(0.0) += payment.amount;
+        }
+        let paid_to_date: f64 = paid_by_member.values().sum();
+        
+        let members = group
+            .members
+            .iter()
+            .map(|share| {
+                let member_due = due_to_date * share.share;
+                let member_paid = paid_by_member.get(&share.member).cloned().unwrap_or(0.0);
+                MemberRepaymentStatus {
+                    member: share.member,
+                    accepted: share.accepted,
+                    allocated_amount: share.amount,
+                    monthly_installment: share.monthly_installment,
+                    due_to_date: member_due,
+                    paid_to_date: member_paid,
+                    arrears: (member_due - member_paid).max(0.0),
+                }
+            })
+            .collect();
+        
+        GroupRepaymentStatus {
+            loan_id: loan_id.clone(),
+            group_name: group.group_name.clone(),
+            status: loan.status.clone(),
+            due_to_date,
+            paid_to_date,
+            arrears: (due_to_date - paid_to_date).max(0.0),
+            days_past_due: days_past_due(&state, loan, now),
+            members,
+        }
+    })
+}
+
+#[query]
+fn get_loans() -> Vec<LoanApplication> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
         state
-            .payments
+            .loans
+            .values()
+            .filter(|loan| is_party(&state, loan, &caller))
+            .cloned()
+            .collect()
+    })
+}
+
+#[query]
+fn get_loan_details(loan_id: String) -> Option<LoanApplication> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .loans
             .get(&loan_id)
+            .filter(|loan| can_view(&state, loan, &caller))
             .cloned()
-            .unwrap_or_default()
+    })
+}
+
+// Credits the loan amount to the borrower's wallet, or each member's share
+// for a group loan, before the loan counts as approved. The pool capital is
+// reserved first and put back if the wallet call fails, and the loan stays
+// pending so it can be approved again.
+#[update]
+async fn approve_loan(loan_id: String) -> Result<LoanApplication, String> {
+    let caller = ic_cdk::caller();
+    
+    let (amount, recipients) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        require_role(state, &caller, &[StaffRole::Admin, StaffRole::LoanOfficer]);
+        
+        let loan = match state.loans.get(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        if loan.status != LoanStatus::Pending {
+            ic_cdk::trap("Only pending loans can be approved");
+        }
+        if state.disbursing.contains(&loan_id) {
+            ic_cdk::trap("Loan is already being disbursed");
+        }
+        let recipients: Vec<(Principal, f64)> = match state.group_loans.get(&loan_id) {
+            Some(group) if group.members.iter().any(|m| !m.accepted) => {
+                ic_cdk::trap("Every group member must accept the loan before it can be approved");
+            }
+            Some(group) => group.members.iter().map(|m| (m.member, m.amount)).collect(),
+            None => vec![(loan.principal, loan.amount)],
+        };
+        
+        let amount = loan.amount;
+        disburse_from_pool(state, amount);
+        state.disbursing.insert(loan_id.clone());
+        (amount, recipients)
+    });
+    
+    let result = credit_disbursement(&loan_id, &recipients).await;
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        state.disbursing.remove(&loan_id);
+        if let Err(reason) = result {
+            cancel_disbursement(state, amount);
+            return Err(reason);
+        }
+        
+        let loan = match state.loans.get_mut(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        let now = time();
+        loan.status = LoanStatus::Approved;
+        loan.approval_date = Some(now);
+        
+        // Repayment schedule starts from the disbursement date
+        let schedule = build_schedule(loan, now, &state.late_payment_policy);
+        let loan = loan.clone();
+        record_pool_tx(state, PoolTxType::Disbursement, amount, None, None, Some(loan_id.clone()));
+        state.schedules.insert(loan_id.clone(), schedule);
+        record_event(state, &loan_id, caller, LoanEventKind::Approved, None);
+        Ok(loan)
+    })
+}
+
+#[update]
+fn reject_loan(loan_id: String) -> LoanApplication {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::LoanOfficer]);
+        if state.disbursing.contains(&loan_id) {
+            ic_cdk::trap("Loan is already being disbursed");
+        }
+        
+        if let Some(loan) = state.loans.get_mut(&loan_id) {
+            // Approved loans already hold pool capital and a schedule
+            if loan.status != LoanStatus::Pending {
+                ic_cdk::trap("Only pending loans can be rejected");
+            }
+            loan.status = LoanStatus::Rejected;
+            let loan = loan.clone();
+            record_event(&mut state, &loan_id, caller, LoanEventKind::Rejected, None);
+            loan
+        } else {
+            ic_cdk::trap("Loan not found");
+        }
+    })
+}
+
+// Checks a payment can be taken before the borrower's wallet is debited,
+// and again once it has been, in case the loan changed in between
+fn check_payment(state: &LoansStorage, loan_id: &str, payer: &Principal, amount: f64, now: u64) -> Result<(), String> {
+    // Any member can repay a group loan
+    let loan = match state.loans.get(loan_id).filter(|loan| is_borrower(state, loan, payer)) {
+        Some(loan) => loan,
+        None => return Err("Loan not found or does not belong to caller".to_string()),
+    };
+    if loan.status == LoanStatus::WrittenOff {
+        let unrecovered = state.write_offs.get(loan_id).map_or(0.0, |w| {
+            w.principal + w.interest + w.charges + w.fees - w.recovered
+        });
+        if amount > unrecovered + AMOUNT_EPSILON {
+            return Err("Payment exceeds the written-off balance".to_string());
+        }
+        return Ok(());
+    }
+    if !is_outstanding(loan) {
+        return Err("Loan is not in repayment".to_string());
+    }
+    let balance: f64 = schedule_as_of(state, loan, now).iter().map(|i| i.remaining()).sum();
+    if amount > balance + AMOUNT_EPSILON {
+        return Err("Payment exceeds the outstanding balance".to_string());
+    }
+    Ok(())
+}
+
+// Takes a payment out of the payer's wallet. A payment that no longer fits
+// the loan once the debit completes is refunded.
+async fn collect_payment(
+    payer: Principal,
+    loan_id: &str,
+    amount: f64,
+    check: fn(&LoansStorage, &str, &Principal, f64, u64) -> Result<(), String>,
+) -> Result<(), String> {
+    if let Err(reason) = STATE.with(|state| check(&state.borrow(), loan_id, &payer, amount, time())) {
+        ic_cdk::trap(&reason);
+    }
+    
+    debit_wallet(payer, amount, &format!("Repayment of {}", loan_id)).await?;
+    
+    if let Err(reason) = STATE.with(|state| check(&state.borrow(), loan_id, &payer, amount, time())) {
+        credit_wallet(payer, amount, &format!("Refund of repayment of {}", loan_id)).await?;
+        return Err(reason);
+    }
+    Ok(())
+}
+
+fn apply_payment(state: &mut LoansStorage, loan_id: &str, payer: Principal, amount: f64, now: u64) -> LoanPayment {
+    let loan = match state.loans.get_mut(loan_id) {
+        Some(loan) => loan,
+        None => ic_cdk::trap("Loan not found"),
+    };
+    if loan.status == LoanStatus::WrittenOff {
+        return record_recovery(state, loan_id, payer, amount, now);
+    }
+    let financing_model = loan.financing_model.clone();
+    
+    let schedule = state.schedules.entry(loan_id.to_string()).or_default();
+    assess_late_charges(schedule, accrues_penalty_interest(loan), now);
+    let allocation = allocate_payment(schedule, amount);
+    
+    if schedule.iter().all(|i| i.status == InstallmentStatus::Paid) {
+        loan.status = LoanStatus::PaidOff;
+    } else if loan.status == LoanStatus::Approved {
+        loan.status = LoanStatus::Active;
+    }
+    
+    let payment_id = state.next_payment_id;
+    state.next_payment_id += 1;
+    
+    let payment = LoanPayment {
+        id: format!("PMT-{}", payment_id),
+        loan_id: loan_id.to_string(),
+        principal: payer,
+        amount,
+        fees_portion: allocation.fees,
+        interest_portion: allocation.interest,
+        charge_portion: allocation.charges,
+        principal_portion: allocation.principal,
+        timestamp: now,
+        status: PaymentStatus::Completed,
+    };
+    
+    // Add payment to the loan's payment history
+    settle_payment_to_pool(state, loan_id, &financing_model, &allocation);
+    state.payments.entry(loan_id.to_string()).or_default().push(payment.clone());
+    
+    payment
+}
+
+// The payment is debited from the caller's wallet before it is applied to the
+// loan and the pool, so the pool only records repayments that were received
+#[update]
+async fn make_payment(loan_id: String, amount: f64) -> Result<LoanPayment, String> {
+    let caller = ic_cdk::caller();
+    
+    if !amount.is_finite() || amount <= 0.0 {
+        ic_cdk::trap("Payment amount must be positive");
+    }
+    
+    collect_payment(caller, &loan_id, amount, check_payment).await?;
+    
+    Ok(STATE.with(|state| apply_payment(&mut state.borrow_mut(), &loan_id, caller, amount, time())))
+}
+
+#[query]
+fn get_payoff_quote(loan_id: String, at_time: u64) -> PayoffQuote {
+    let caller = ic_cdk::caller();
+    
+    if at_time < time() {
+        ic_cdk::trap("Payoff quotes cannot be issued for a past time");
+    }
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        match state.loans.get(&loan_id) {
+            Some(loan) if can_view(&state, loan, &caller) => {
+                if !is_outstanding(loan) {
+                    ic_cdk::trap("Loan is not in repayment");
+                }
+                let schedule = schedule_as_of(&state, loan, at_time);
+                payoff_quote(&loan_id, &schedule, at_time)
+            }
+            _ => ic_cdk::trap("Loan not found or does not belong to caller"),
+        }
+    })
+}
+
+fn check_prepayment(state: &LoansStorage, loan_id: &str, payer: &Principal, amount: f64, now: u64) -> Result<(), String> {
+    let loan = match state.loans.get(loan_id).filter(|loan| is_borrower(state, loan, payer)) {
+        Some(loan) => loan,
+        None => return Err("Loan not found or does not belong to caller".to_string()),
+    };
+    if !is_outstanding(loan) {
+        return Err("Loan is not in repayment".to_string());
+    }
+    
+    let schedule = schedule_as_of(state, loan, now);
+    let quote = payoff_quote(loan_id, &schedule, now);
+    if amount >= quote.total - AMOUNT_EPSILON {
+        if amount > quote.total + AMOUNT_EPSILON {
+            return Err("Prepayment exceeds the payoff amount".to_string());
+        }
+        return Ok(());
+    }
+    
+    // Everything already due is settled first; only the rest reduces principal
+    let due_count = schedule.iter().take_while(|i| i.due_date <= now).count();
+    let arrears: f64 = schedule[..due_count].iter().map(|i| i.remaining()).sum();
+    if amount <= arrears + AMOUNT_EPSILON {
+        return Err("Amount only covers installments already due; use make_payment instead".to_string());
+    }
+    let future_principal: f64 = schedule[due_count..].iter().map(|i| i.remaining_principal()).sum();
+    if amount - arrears >= future_principal - AMOUNT_EPSILON {
+        return Err("Prepayment would settle the loan; pay the amount from get_payoff_quote instead".to_string());
+    }
+    Ok(())
+}
+
+fn apply_prepayment(
+    state: &mut LoansStorage,
+    loan_id: &str,
+    payer: Principal,
+    amount: f64,
+    mode: PrepaymentMode,
+    now: u64,
+) -> LoanPayment {
+    let loan = match state.loans.get(loan_id) {
+        Some(loan) => loan,
+        None => ic_cdk::trap("Loan not found"),
+    };
+    let previous_terms = current_terms(state, loan);
+    let (interest_rate, monthly_payment) = (loan.interest_rate, loan.monthly_payment);
+    let financing_model = loan.financing_model.clone();
+    
+    let schedule = state.schedules.entry(loan_id.to_string()).or_default();
+    assess_late_charges(schedule, accrues_penalty_interest(loan), now);
+    let quote = payoff_quote(loan_id, schedule, now);
+    
+    let payment_id = format!("PMT-{}", state.next_payment_id);
+    let (allocation, kind) = if amount >= quote.total - AMOUNT_EPSILON {
+        let kind = LoanEventKind::EarlyPayoff {
+            payment_id: payment_id.clone(),
+            amount,
+            interest_waived: quote.interest_waived,
+        };
+        (settle_schedule(schedule, now), kind)
+    } else {
+        let due_count = schedule.iter().take_while(|i| i.due_date <= now).count();
+        let arrears: f64 = schedule[..due_count].iter().map(|i| i.remaining()).sum();
+        let settled = allocate_payment(&mut schedule[..due_count], arrears);
+        let mut remaining = amount - arrears;
+        for installment in schedule[due_count..].iter_mut() {
+            let to_principal = remaining.min(installment.remaining_principal());
+            installment.principal_paid += to_principal;
+            remaining -= to_principal;
+        }
+        reschedule_after_prepayment(schedule, interest_rate, monthly_payment, &mode, now);
+        
+        let kind = LoanEventKind::Prepayment {
+            payment_id: payment_id.clone(),
+            amount,
+            mode,
+        };
+        let allocation = Allocation {
+            principal: amount - settled.fees - settled.interest - settled.charges,
+            ..settled
+        };
+        (allocation, kind)
+    };
+    
+    let paid_off = schedule.iter().all(|i| i.status == InstallmentStatus::Paid);
+    let term_months = schedule.len() as u8;
+    let next_installment = schedule
+        .iter()
+        .find(|i| i.status != InstallmentStatus::Paid)
+        .map(|i| i.principal_due + i.interest_due + i.charge_due);
+    
+    if let Some(loan) = state.loans.get_mut(loan_id) {
+        loan.term_months = term_months;
+        if let Some(installment) = next_installment {
+            loan.monthly_payment = installment;
+        }
+        loan.status = if paid_off { LoanStatus::PaidOff } else { LoanStatus::Active };
+    }
+    
+    state.next_payment_id += 1;
+    let payment = LoanPayment {
+        id: payment_id,
+        loan_id: loan_id.to_string(),
+        principal: payer,
+        amount,
+        fees_portion: allocation.fees,
+        interest_portion: allocation.interest,
+        charge_portion: allocation.charges,
+        principal_portion: allocation.principal,
+        timestamp: now,
+        status: PaymentStatus::Completed,
+    };
+    settle_payment_to_pool(state, loan_id, &financing_model, &allocation);
+    state.payments.entry(loan_id.to_string()).or_default().push(payment.clone());
+    record_event(state, loan_id, payer, kind, Some(previous_terms));
+    
+    payment
+}
+
+#[update]
+async fn make_prepayment(loan_id: String, amount: f64, mode: PrepaymentMode) -> Result<LoanPayment, String> {
+    let caller = ic_cdk::caller();
+    
+    if !amount.is_finite() || amount <= 0.0 {
+        ic_cdk::trap("Payment amount must be positive");
+    }
+    
+    collect_payment(caller, &loan_id, amount, check_prepayment).await?;
+    
+    Ok(STATE.with(|state| apply_prepayment(&mut state.borrow_mut(), &loan_id, caller, amount, mode, time())))
+}
+
+#[update]
+fn restructure_loan(loan_id: String, request: RestructureRequest) -> LoanApplication {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        require_role(state, &caller, &[StaffRole::Admin, StaffRole::LoanOfficer]);
+        let now = time();
+        
+        let loan = match state.loans.get(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        if !is_outstanding(loan) {
+            ic_cdk::trap("Loan is not in repayment");
+        }
+        
+        let interest_rate = request.new_interest_rate.unwrap_or(loan.interest_rate);
+        if !(0.0..=loan.interest_rate).contains(&interest_rate) {
+            ic_cdk::trap("Restructuring can only keep or reduce the interest rate");
+        }
+        let previous_terms = current_terms(state, loan);
+        let penalty_interest = accrues_penalty_interest(loan);
+        
+        let schedule = state.schedules.entry(loan_id.clone()).or_default();
+        assess_late_charges(schedule, penalty_interest, now);
+        
+        let paid_count = schedule.iter().filter(|i| i.status == InstallmentStatus::Paid).count();
+        let upcoming_count = schedule
+            .iter()
+            .filter(|i| i.status != InstallmentStatus::Paid && i.due_date > now)
+            .count();
+        let new_count = upcoming_count + request.extend_term_months as usize;
+        if new_count == 0 {
+            ic_cdk::trap("Restructured loan must have at least one installment left");
+        }
+        if paid_count + new_count > u8::MAX as usize {
+            ic_cdk::trap("Restructured term is too long");
+        }
+        
+        let capitalized = restructure_schedule(schedule, &request, interest_rate, &state.late_payment_policy, now);
+        let capitalized_arrears = capitalized.interest + capitalized.fees;
+        
+        // Capitalized arrears add to what the borrower owes, not to the capital
+        // deployed from the pool
+        let tracked = state.capitalized_arrears.entry(loan_id.clone()).or_default();
+        tracked.interest += capitalized.interest;
+        tracked.fees += capitalized.fees;
+        let term_months = schedule.len() as u8;
+        let monthly_payment = schedule
+            .iter()
+            .find(|i| i.status != InstallmentStatus::Paid)
+            .map_or(0.0, |i| i.principal_due + i.interest_due + i.charge_due);
+        
+        let loan = match state.loans.get_mut(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        loan.term_months = term_months;
+        loan.interest_rate = interest_rate;
+        loan.monthly_payment = monthly_payment;
+        if loan.status == LoanStatus::Defaulted {
+            loan.status = LoanStatus::Active;
+        }
+        let loan = loan.clone();
+        
+        let kind = LoanEventKind::Restructured { request, capitalized_arrears };
+        record_event(state, &loan_id, caller, kind, Some(previous_terms));
+        loan
+    })
+}
+
+#[query]
+fn get_loan_history(loan_id: String) -> Vec<LoanEvent> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        match state.loans.get(&loan_id) {
+            Some(loan) if can_view(&state, loan, &caller) => {
+                state.history.get(&loan_id).cloned().unwrap_or_default()
+            }
+            _ => Vec::new(),
+        }
+    })
+}
+
+#[query]
+fn get_repayment_schedule(loan_id: String) -> Vec<Installment> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        match state.loans.get(&loan_id) {
+            Some(loan) if can_view(&state, loan, &caller) => {
+                schedule_as_of(&state, loan, time())
+            }
+            _ => Vec::new(),
+        }
+    })
+}
+
+#[query]
+fn get_late_payment_policy() -> LatePaymentPolicy {
+    STATE.with(|state| state.borrow().late_payment_policy.clone())
+}
+
+#[update]
+fn set_late_payment_policy(policy: LatePaymentPolicy) {
+    let caller = ic_cdk::caller();
+    
+    if policy.late_fee_percent < 0.0 || policy.penalty_rate < 0.0 {
+        ic_cdk::trap("Late fee and penalty rate cannot be negative");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        
+        // Applies to installments scheduled from now on; existing installments
+        // keep the terms they were scheduled with
+        state.late_payment_policy = policy;
+    });
+}
+
+#[query]
+fn get_payments(loan_id: String) -> Vec<LoanPayment> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        // Borrowers see their own payments; staff see every loan's
+        if let Some(loan) = state.loans.get(&loan_id) {
+            if !can_view(&state, loan, &caller) {
+                return Vec::new(); // Return empty vector if the caller cannot view the loan
+            }
+        } else {
+            return Vec::new(); // Loan not found
+        }
+        
+        // Return payments for the loan
+        state
+            .payments
+            .get(&loan_id)
+            .cloned()
+            .unwrap_or_default()
+    })
+}
+
+#[query]
+fn calculate_eligibility() -> f64 {
+    let caller = ic_cdk::caller();
+    
+    // In a real application, we would:
+    // 1. Check the caller's savings balance
+    // 2. Check their transaction history
+    // 3. Calculate a credit score based on history
+    // 4. Return the maximum loan amount they qualify for
+    
+    // For this demo, we start from a fixed amount and deduct open borrowing
+    STATE.with(|state| member_eligibility(&state.borrow(), &caller))
+}
+
+#[query]
+fn get_loan_products() -> Vec<LoanProduct> {
+    let loan_types = [
+        LoanType::Personal,
+        LoanType::Business,
+        LoanType::Education,
+        LoanType::Housing,
+        LoanType::Agriculture,
+        LoanType::Medical,
+    ];
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        loan_types.iter().map(|loan_type| product_for(&state, loan_type)).collect()
+    })
+}
+
+#[update]
+fn set_loan_product(product: LoanProduct) {
+    let caller = ic_cdk::caller();
+    
+    let valid_limits = product.interest_rate >= 0.0
+        && product.eligibility_cap_percent >= 0.0
+        && product.min_amount > 0.0
+        && product.min_amount <= product.max_amount;
+    if !valid_limits {
+        ic_cdk::trap("Invalid product rates or amount limits");
+    }
+    if product.allowed_terms.is_empty() || product.allowed_terms.contains(&0) {
+        ic_cdk::trap("Products need at least one non-zero term");
+    }
+    match product.financing_model {
+        FinancingModel::Conventional => {}
+        FinancingModel::QardHasan { administration_fee: charge } | FinancingModel::Murabaha { profit_percent: charge } => {
+            if product.interest_rate != 0.0 {
+                ic_cdk::trap("Interest-free products cannot carry an interest rate");
+            }
+            if !(charge >= 0.0 && charge.is_finite()) {
+                ic_cdk::trap("Administration fee or profit must not be negative");
+            }
+        }
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.products.insert(product.loan_type.clone(), product);
+    });
+}
+
+// Update the interest-rate table in one step, leaving the other product
+// limits as they are. Only the governance canister may call this, once a rate
+// change is voted through.
+#[update]
+fn set_interest_rates(rates: Vec<LoanRate>) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.governance_canister != Some(caller) {
+            ic_cdk::trap("Only the governance canister can change the interest rates");
+        }
+        
+        // Validate every entry before applying any of them
+        let mut products = Vec::with_capacity(rates.len());
+        for rate in rates {
+            if !(rate.interest_rate >= 0.0 && rate.interest_rate.is_finite()) {
+                ic_cdk::trap("Invalid interest rate");
+            }
+            let mut product = product_for(&state, &rate.loan_type);
+            if !matches!(product.financing_model, FinancingModel::Conventional) && rate.interest_rate != 0.0 {
+                ic_cdk::trap("Interest-free products cannot carry an interest rate");
+            }
+            product.interest_rate = rate.interest_rate;
+            products.push(product);
+        }
+        
+        for product in products {
+            state.products.insert(product.loan_type.clone(), product);
+        }
+    });
+}
+
+#[update]
+fn grant_staff_role(principal: Principal, role: StaffRole) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.staff.insert(principal, role);
+    });
+}
+
+#[update]
+fn set_wallet_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.wallet_canister = Some(canister_id);
+    });
+}
+
+#[update]
+fn set_governance_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.governance_canister = Some(canister_id);
+    });
+}
+
+#[update]
+fn set_treasury_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.treasury_canister = Some(canister_id);
+    });
+}
+
+#[query]
+fn get_interest_spread() -> f64 {
+    STATE.with(|state| state.borrow().interest_spread_percent)
+}
+
+// Applies to interest and profit collected from now on
+#[update]
+fn set_interest_spread(percent: f64) {
+    let caller = ic_cdk::caller();
+    
+    if !(0.0..=100.0).contains(&percent) {
+        ic_cdk::trap("Interest spread must be between 0 and 100 percent");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.interest_spread_percent = percent;
+    });
+}
+
+#[update]
+fn revoke_staff_role(principal: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        
+        if principal == caller {
+            ic_cdk::trap("Administrators cannot revoke their own role");
+        }
+        state.staff.remove(&principal);
+    });
+}
+
+#[query]
+fn list_loans(status: Option<LoanStatus>, offset: u64, limit: u64) -> LoanPage {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::Auditor]);
+        
+        let mut loans: Vec<&LoanApplication> = state
+            .loans
+            .values()
+            .filter(|loan| status.as_ref().is_none_or(|s| loan.status == *s))
+            .collect();
+        loans.sort_by_key(|loan| loan_number(&loan.id));
+        
+        let total = loans.len() as u64;
+        let limit = limit.min(MAX_PAGE_SIZE) as usize;
+        
+        LoanPage {
+            loans: loans
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit)
+                .cloned()
+                .collect(),
+            total,
+            offset,
+        }
+    })
+}
+
+#[query]
+fn get_portfolio_summary() -> PortfolioSummary {
+    let caller = ic_cdk::caller();
+    let now = time();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::Auditor]);
+        
+        let mut summary = PortfolioSummary {
+            as_of: now,
+            total_loans: state.loans.len() as u64,
+            disbursed_loans: 0,
+            outstanding_loans: 0,
+            defaulted_loans: 0,
+            total_disbursed: 0.0,
+            outstanding_principal: 0.0,
+            outstanding_by_type: Vec::new(),
+            portfolio_at_risk: PAR_THRESHOLDS_DAYS
+                .iter()
+                .map(|days| PortfolioAtRisk {
+                    min_days_past_due: *days,
+                    loan_count: 0,
+                    outstanding_principal: 0.0,
+                    ratio: 0.0,
+                })
+                .collect(),
+            default_rate: 0.0,
+            interest_income: 0.0,
+            charge_income: 0.0,
+            fee_income: 0.0,
+        };
+        let mut by_type: HashMap<LoanType, LoanTypeExposure> = HashMap::new();
+        
+        for loan in state.loans.values() {
+            for payment in completed_payments(&state, &loan.id) {
+                summary.interest_income += payment.interest_portion;
+                summary.charge_income += payment.charge_portion;
+                summary.fee_income += payment.fees_portion;
+            }
+            
+            if !is_disbursed(loan) {
+                continue;
+            }
+            summary.disbursed_loans += 1;
+            summary.total_disbursed += loan.amount;
+            if matches!(loan.status, LoanStatus::Defaulted | LoanStatus::WrittenOff) {
+                summary.defaulted_loans += 1;
+            }
+            
+            if !is_outstanding(loan) {
+                continue;
+            }
+            let outstanding = outstanding_principal(&state, loan);
+            summary.outstanding_loans += 1;
+            summary.outstanding_principal += outstanding;
+            
+            let exposure = by_type.entry(loan.purpose.clone()).or_insert_with(|| LoanTypeExposure {
+                loan_type: loan.purpose.clone(),
+                loan_count: 0,
+                outstanding_principal: 0.0,
+            });
+            exposure.loan_count += 1;
+            exposure.outstanding_principal += outstanding;
+            
+            let overdue_days = days_past_due(&state, loan, now);
+            for bucket in summary.portfolio_at_risk.iter_mut() {
+                if overdue_days >= bucket.min_days_past_due {
+                    bucket.loan_count += 1;
+                    bucket.outstanding_principal += outstanding;
+                }
+            }
+        }
+        
+        if summary.outstanding_principal > 0.0 {
+            for bucket in summary.portfolio_at_risk.iter_mut() {
+                bucket.ratio = bucket.outstanding_principal / summary.outstanding_principal;
+            }
+        }
+        if summary.disbursed_loans > 0 {
+            summary.default_rate = summary.defaulted_loans as f64 / summary.disbursed_loans as f64;
+        }
+        
+        summary.outstanding_by_type = by_type.into_values().collect();
+        summary
+            .outstanding_by_type
+            .sort_by(|a, b| b.outstanding_principal.total_cmp(&a.outstanding_principal));
+        summary
+    })
+}
+
+#[query]
+fn get_disbursement_report(from: u64, to: u64, period: ReportPeriod) -> Vec<PeriodReport> {
+    let caller = ic_cdk::caller();
+    
+    let period_length = match period {
+        ReportPeriod::Day => NANOS_PER_DAY,
+        ReportPeriod::Week => 7 * NANOS_PER_DAY,
+        ReportPeriod::Month => NANOS_PER_MONTH,
+    };
+    if to <= from {
+        ic_cdk::trap("Report end must be after its start");
+    }
+    let period_count = (to - from).div_ceil(period_length);
+    if period_count > MAX_REPORT_PERIODS {
+        ic_cdk::trap("Report range covers too many periods");
+    }
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::Auditor]);
+        
+        let mut report: Vec<PeriodReport> = (0..period_count)
+            .map(|i| PeriodReport {
+                period_start: from + i * period_length,
+                period_end: (from + (i + 1) * period_length).min(to),
+                loans_disbursed: 0,
+                amount_disbursed: 0.0,
+                interest_income: 0.0,
+            })
+            .collect();
+        let bucket_of = |timestamp: u64| {
+            if timestamp < from || timestamp >= to {
+                None
+            } else {
+                Some(((timestamp - from) / period_length) as usize)
+            }
+        };
+        
+        for loan in state.loans.values().filter(|loan| is_disbursed(loan)) {
+            if let Some(i) = loan.approval_date.and_then(bucket_of) {
+                report[i].loans_disbursed += 1;
+                report[i].amount_disbursed += loan.amount;
+            }
+        }
+        for payment in state.payments.values().flatten() {
+            if !matches!(payment.status, PaymentStatus::Completed) {
+                continue;
+            }
+            if let Some(i) = bucket_of(payment.timestamp) {
+                report[i].interest_income += payment.interest_portion;
+            }
+        }
+        
+        report
+    })
+}
+
+fn wallet_canister() -> Result<Principal, String> {
+    STATE.with(|state| state.borrow().wallet_canister).ok_or_else(|| "The wallet canister is not configured".to_string())
+}
+
+fn call_error((code, message): (RejectionCode, String)) -> String {
+    format!("Call rejected ({:?}): {}", code, message)
+}
+
+async fn credit_wallet(member: Principal, amount: f64, description: &str) -> Result<(), String> {
+    let wallet = wallet_canister()?;
+    ic_cdk::call::<_, (Reserved,)>(wallet, "credit_account", (member, amount, description.to_string()))
+        .await
+        .map(|_| ())
+        .map_err(call_error)
+}
+
+async fn debit_wallet(member: Principal, amount: f64, description: &str) -> Result<(), String> {
+    let wallet = wallet_canister()?;
+    ic_cdk::call::<_, (Reserved,)>(wallet, "debit_account", (member, amount, description.to_string()))
+        .await
+        .map(|_| ())
+        .map_err(call_error)
+}
+
+// Pays a loan out to its borrowers. If a later credit fails, the ones already
+// made are taken back so the disbursement either happens in full or not at all.
+async fn credit_disbursement(loan_id: &str, recipients: &[(Principal, f64)]) -> Result<(), String> {
+    let description = format!("Disbursement of {}", loan_id);
+    for (i, (member, amount)) in recipients.iter().enumerate() {
+        if let Err(reason) = credit_wallet(*member, *amount, &description).await {
+            for (member, amount) in &recipients[..i] {
+                let _ = debit_wallet(*member, *amount, &format!("Reversal of {}", description)).await;
+            }
+            return Err(reason);
+        }
+    }
+    Ok(())
+}
+
+// Moves the contribution out of the member's wallet before any capital is
+// recorded, so the pool only ever lends money that exists
+#[update]
+async fn contribute_to_pool(amount: f64, source: FundingSource) -> PoolTxRecord {
+    let caller = ic_cdk::caller();
+    
+    if !amount.is_finite() || amount <= 0.0 {
+        ic_cdk::trap("Contribution amount must be positive");
+    }
+    
+    if let Err(reason) = debit_wallet(caller, amount, "Lending pool contribution").await {
+        ic_cdk::trap(&reason);
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let contribution = state.pool_contributions.entry(caller).or_insert_with(|| PoolContribution {
+            contributor: caller,
+            savings_capital: 0.0,
+            deposit_capital: 0.0,
+            earnings: 0.0,
+            earnings_claimed: 0.0,
+        });
+        match source {
+            FundingSource::MemberSavings => contribution.savings_capital += amount,
+            FundingSource::DedicatedDeposit => contribution.deposit_capital += amount,
+        }
+        state.pool.total_capital += amount;
+        state.pool.available_liquidity += amount;
+        
+        record_pool_tx(&mut state, PoolTxType::Contribution, amount, Some(caller), Some(source), None)
+    })
+}
+
+// Returns capital to the member's wallet. The capital is taken out of the
+// pool before the wallet call and put back if the call fails.
+#[update]
+async fn withdraw_from_pool(amount: f64, source: FundingSource) -> Result<PoolTxRecord, String> {
+    let caller = ic_cdk::caller();
+    
+    if !amount.is_finite() || amount <= 0.0 {
+        ic_cdk::trap("Withdrawal amount must be positive");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        // Capital lent out to borrowers cannot be withdrawn until it is repaid
+        if amount > state.pool.available_liquidity + AMOUNT_EPSILON {
+            ic_cdk::trap("Insufficient lending pool liquidity for this withdrawal");
+        }
+        let capital = match state.pool_contributions.get_mut(&caller) {
+            Some(contribution) => match source {
+                FundingSource::MemberSavings => &mut contribution.savings_capital,
+                FundingSource::DedicatedDeposit => &mut contribution.deposit_capital,
+            },
+            None => ic_cdk::trap("No pool contribution found for caller"),
+        };
+        if amount > *capital + AMOUNT_EPSILON {
+            ic_cdk::trap("Withdrawal exceeds contributed capital");
+        }
+        
+        *capital = (*capital - amount).max(0.0);
+        state.pool.total_capital -= amount;
+        state.pool.available_liquidity -= amount;
+    });
+    
+    let result = credit_wallet(caller, amount, "Lending pool withdrawal").await;
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if result.is_err() {
+            if let Some(contribution) = state.pool_contributions.get_mut(&caller) {
+                match source {
+                    FundingSource::MemberSavings => contribution.savings_capital += amount,
+                    FundingSource::DedicatedDeposit => contribution.deposit_capital += amount,
+                }
+            }
+            state.pool.total_capital += amount;
+            state.pool.available_liquidity += amount;
+        }
+        result.map(|_| record_pool_tx(&mut state, PoolTxType::Withdrawal, amount, Some(caller), Some(source), None))
+    })
+}
+
+// Pays the caller's distributed earnings into their wallet
+#[update]
+async fn claim_pool_earnings() -> Result<PoolTxRecord, String> {
+    let caller = ic_cdk::caller();
+    
+    let earnings = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match state.pool_contributions.get_mut(&caller) {
+            Some(contribution) if contribution.earnings > 0.0 => {
+                let earnings = contribution.earnings;
+                contribution.earnings = 0.0;
+                earnings
+            }
+            _ => ic_cdk::trap("No pool earnings to claim"),
+        }
+    });
+    
+    let result = credit_wallet(caller, earnings, "Lending pool earnings").await;
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if let Some(contribution) = state.pool_contributions.get_mut(&caller) {
+            match result {
+                Ok(()) => contribution.earnings_claimed += earnings,
+                Err(_) => contribution.earnings += earnings,
+            }
+        }
+        result.map(|_| record_pool_tx(&mut state, PoolTxType::EarningsClaim, earnings, Some(caller), None, None))
+    })
+}
+
+// Hands the fees, penalty income and interest spread collected so far over
+// to the treasury canister, one payment per source. Anyone may trigger it, as
+// it only moves income the treasury is already owed. A source whose transfer
+// fails stays owed and is retried next time.
+#[update]
+async fn transfer_income_to_treasury() -> Result<Vec<PoolTxRecord>, String> {
+    let treasury = STATE
+        .with(|state| state.borrow().treasury_canister)
+        .ok_or_else(|| "The treasury canister is not configured".to_string())?;
+    
+    let mut transfers = Vec::new();
+    for source in TREASURY_INCOME_SOURCES {
+        let amount = STATE.with(|state| state.borrow_mut().treasury_payable.remove(&source).unwrap_or(0.0));
+        if amount <= AMOUNT_EPSILON {
+            continue;
+        }
+        
+        let result = ic_cdk::call::<_, (Reserved,)>(treasury, "receive_income", (source, amount, None::<String>))
+            .await
+            .map_err(call_error);
+        
+        let tx = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            match result {
+                Ok(_) => {
+                    state.pool.treasury_transferred += amount;
+                    Ok(record_pool_tx(&mut state, PoolTxType::TreasuryTransfer, amount, None, None, None))
+                }
+                Err(reason) => {
+                    *state.treasury_payable.entry(source).or_insert(0.0) += amount;
+                    Err(reason)
+                }
+            }
+        })?;
+        transfers.push(tx);
+    }
+    
+    Ok(transfers)
+}
+
+#[query]
+fn get_lending_pool() -> LendingPool {
+    STATE.with(|state| state.borrow().pool.clone())
+}
+
+#[query]
+fn get_pool_contribution() -> Option<PoolContribution> {
+    let caller = ic_cdk::caller();
+    STATE.with(|state| state.borrow().pool_contributions.get(&caller).cloned())
+}
+
+#[query]
+fn get_pool_transactions(offset: u64, limit: u64) -> Vec<PoolTxRecord> {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::Auditor]);
+        
+        state
+            .pool_transactions
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+#[update]
+fn mark_loan_defaulted(loan_id: String) -> LoanApplication {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::LoanOfficer]);
+        
+        let loan = match state.loans.get(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        if !matches!(loan.status, LoanStatus::Approved | LoanStatus::Active) {
+            ic_cdk::trap("Only loans in repayment can be marked as defaulted");
+        }
+        let overdue_days = days_past_due(&state, loan, time());
+        if overdue_days < DEFAULT_AFTER_DAYS {
+            ic_cdk::trap("Loans can only be marked as defaulted after 90 days past due");
+        }
+        
+        let loan = match state.loans.get_mut(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        loan.status = LoanStatus::Defaulted;
+        let loan = loan.clone();
+        
+        let kind = LoanEventKind::Defaulted { days_past_due: overdue_days };
+        record_event(&mut state, &loan_id, caller, kind, None);
+        loan
+    })
+}
+
+#[update]
+fn write_off_loan(loan_id: String, reason: String) -> WriteOff {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        require_role(state, &caller, &[StaffRole::Admin]);
+        let now = time();
+        
+        let loan = match state.loans.get(&loan_id) {
+            Some(loan) => loan,
+            None => ic_cdk::trap("Loan not found"),
+        };
+        if loan.status != LoanStatus::Defaulted {
+            ic_cdk::trap("Only defaulted loans can be written off");
+        }
+        let previous_terms = current_terms(state, loan);
+        let penalty_interest = accrues_penalty_interest(loan);
+        if let Some(loan) = state.loans.get_mut(&loan_id) {
+            loan.status = LoanStatus::WrittenOff;
+        }
+        
+        // Move whatever is still owed, as of today, to the loss account
+        let schedule = state.schedules.entry(loan_id.clone()).or_default();
+        assess_late_charges(schedule, penalty_interest, now);
+        let capitalized = state.capitalized_arrears.remove(&loan_id).unwrap_or_default();
+        let principal: f64 = (schedule.iter().map(|i| i.remaining_principal()).sum::<f64>()
+            - capitalized.interest
+            - capitalized.fees)
+            .max(0.0);
+        let interest: f64 = schedule.iter().map(|i| i.remaining_interest()).sum::<f64>() + capitalized.interest;
+        let charges: f64 = schedule.iter().map(|i| i.remaining_charge()).sum();
+        let fees: f64 = schedule.iter().map(|i| i.remaining_fees()).sum::<f64>() + capitalized.fees;
+        
+        state.loss_account.principal_written_off += principal;
+        state.loss_account.interest_written_off += interest;
+        state.loss_account.charges_written_off += charges;
+        state.loss_account.fees_written_off += fees;
+        
+        // The unrepaid principal is a loss to the pool's contributors
+        state.pool.deployed_capital = (state.pool.deployed_capital - principal).max(0.0);
+        adjust_pool_capital(state, -principal);
+        record_pool_tx(state, PoolTxType::WriteOff, principal, None, None, Some(loan_id.clone()));
+        
+        let write_off = WriteOff {
+            loan_id: loan_id.clone(),
+            written_off_at: now,
+            approved_by: caller,
+            reason: reason.clone(),
+            principal,
+            interest,
+            charges,
+            fees,
+            recovered: 0.0,
+        };
+        state.write_offs.insert(loan_id.clone(), write_off.clone());
+        
+        let kind = LoanEventKind::WrittenOff {
+            reason,
+            principal,
+            interest,
+            charges,
+            fees,
+        };
+        record_event(state, &loan_id, caller, kind, Some(previous_terms));
+        write_off
     })
 }
 
 #[query]
-fn calculate_eligibility() -> f64 {
+fn get_write_off(loan_id: String) -> Option<WriteOff> {
     let caller = ic_cdk::caller();
     
-    // In a real application, we would:
-    // 1. Check the caller's savings balance
-    // 2. Check their transaction history
-    // 3. Calculate a credit score based on history
-    // 4. Return the maximum loan amount they qualify for
+    STATE.with(|state| {
+        let state = state.borrow();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::Auditor]);
+        state.write_offs.get(&loan_id).cloned()
+    })
+}
+
+#[query]
+fn get_provisioning_report() -> ProvisioningReport {
+    let caller = ic_cdk::caller();
+    let now = time();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        require_role(&state, &caller, &[StaffRole::Admin, StaffRole::Auditor]);
+        
+        let rules = &state.provisioning_policy.rules;
+        let mut buckets: Vec<ProvisionBucket> = rules
+            .iter()
+            .map(|rule| ProvisionBucket {
+                min_days_past_due: rule.min_days_past_due,
+                reserve_percent: rule.reserve_percent,
+                loan_count: 0,
+                outstanding_principal: 0.0,
+                required_reserve: 0.0,
+            })
+            .collect();
+        
+        // Each loan is provisioned at the rate of the highest bucket it reaches
+        for loan in state.loans.values().filter(|loan| is_outstanding(loan)) {
+            let overdue_days = days_past_due(&state, loan, now);
+            if let Some(bucket) = buckets.iter_mut().rev().find(|b| overdue_days >= b.min_days_past_due) {
+                let outstanding = outstanding_principal(&state, loan);
+                bucket.loan_count += 1;
+                bucket.outstanding_principal += outstanding;
+                bucket.required_reserve += outstanding * bucket.reserve_percent / 100.0;
+            }
+        }
+        
+        ProvisioningReport {
+            as_of: now,
+            total_required_reserve: buckets.iter().map(|b| b.required_reserve).sum(),
+            buckets,
+            loss_account: state.loss_account.clone(),
+        }
+    })
+}
+
+#[query]
+fn get_provisioning_policy() -> ProvisioningPolicy {
+    STATE.with(|state| state.borrow().provisioning_policy.clone())
+}
+
+#[update]
+fn set_provisioning_policy(mut policy: ProvisioningPolicy) {
+    let caller = ic_cdk::caller();
+    
+    if policy
+        .rules
+        .iter()
+        .any(|rule| !(0.0..=100.0).contains(&rule.reserve_percent))
+    {
+        ic_cdk::trap("Reserve percentages must be between 0 and 100");
+    }
+    policy.rules.sort_by_key(|rule| rule.min_days_past_due);
     
-    // For this demo, we'll just return a fixed amount
-    3000.0
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_role(&state, &caller, &[StaffRole::Admin]);
+        state.provisioning_policy = policy;
+    });
 }
 
 // Required for candid interface generation
@@ -258,3 +2894,1373 @@ candid::export_service!();
 fn export_candid() -> String {
     __export_service()
 }
+
+// Stands in for the system API in unit tests, which run outside a canister:
+// tests set the caller and the clock, and a trap panics with its message
+#[cfg(test)]
+mod ic_cdk {
+    pub use ::ic_cdk::*;
+    use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+    use candid::Principal;
+    use std::cell::{Cell, RefCell};
+    
+    thread_local! {
+        pub static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+        pub static TIME: Cell<u64> = const { Cell::new(0) };
+        // Inter-canister calls made so far: method and candid-encoded arguments
+        pub static CALLS: RefCell<Vec<(String, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+        pub static REJECT_CALLS: Cell<bool> = const { Cell::new(false) };
+    }
+    
+    pub fn caller() -> Principal {
+        CALLER.with(|caller| caller.get())
+    }
+    
+    pub fn trap(message: &str) -> ! {
+        panic!("{}", message)
+    }
+    
+    // Records the call and completes it straight away, replying with an empty
+    // record unless REJECT_CALLS is set
+    pub async fn call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+        _id: Principal,
+        method: &str,
+        args: T,
+    ) -> api::call::CallResult<R> {
+        let encoded = candid::utils::encode_args(args).expect("arguments encode");
+        CALLS.with(|calls| calls.borrow_mut().push((method.to_string(), encoded)));
+        if REJECT_CALLS.with(|reject| reject.get()) {
+            return Err((api::call::RejectionCode::CanisterReject, "Rejected by test".to_string()));
+        }
+        let reply = candid::utils::encode_args(((),)).expect("reply encodes");
+        Ok(candid::utils::decode_args(&reply).expect("reply decodes"))
+    }
+    
+    pub mod api {
+        pub use ::ic_cdk::api::*;
+        
+        pub fn time() -> u64 {
+            super::TIME.with(|time| time.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const START: u64 = 1_700_000_000_000_000_000;
+    const ADMIN: u8 = 1;
+    const AUDITOR: u8 = 2;
+    const FUNDER: u8 = 3;
+    const WALLET: u8 = 4;
+    const GOVERNANCE: u8 = 5;
+    const TREASURY: u8 = 6;
+    const OFFICER: u8 = 7;
+    const MEMBER: u8 = 10;
+    
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+    
+    fn act_as(id: u8) {
+        ic_cdk::CALLER.with(|caller| caller.set(principal(id)));
+    }
+    
+    fn set_time(now: u64) {
+        ic_cdk::TIME.with(|time| time.set(now));
+    }
+    
+    // Drives an update call to completion; the stubbed inter-canister calls
+    // complete on the first poll
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("Call did not complete"),
+        }
+    }
+    
+    fn reject_calls(reject: bool) {
+        ic_cdk::REJECT_CALLS.with(|flag| flag.set(reject));
+    }
+    
+    // Wallet calls made so far as (method, member, amount)
+    fn wallet_calls() -> Vec<(String, Principal, f64)> {
+        ic_cdk::CALLS.with(|calls| {
+            calls
+                .borrow()
+                .iter()
+                .map(|(method, args)| {
+                    let (member, amount, _): (Principal, f64, String) = candid::utils::decode_args(args).expect("wallet arguments");
+                    (method.clone(), member, amount)
+                })
+                .collect()
+        })
+    }
+    
+    // A canister deployed by ADMIN, with AUDITOR on the staff, connected to
+    // the other canisters and the pool funded through the wallet canister
+    fn setup() {
+        set_time(START);
+        act_as(ADMIN);
+        init();
+        grant_staff_role(principal(AUDITOR), StaffRole::Auditor);
+        set_wallet_canister(principal(WALLET));
+        set_governance_canister(principal(GOVERNANCE));
+        set_treasury_canister(principal(TREASURY));
+        fund_pool(10_000.0);
+    }
+    
+    fn fund_pool(amount: f64) {
+        act_as(FUNDER);
+        block_on(contribute_to_pool(amount, FundingSource::DedicatedDeposit));
+    }
+    
+    fn apply(member: u8, amount: f64, term_months: u8) -> String {
+        act_as(member);
+        match apply_for_loan(amount, term_months, LoanType::Personal) {
+            Ok(loan) => loan.id,
+            Err(_) => panic!("Loan application was rejected"),
+        }
+    }
+    
+    fn approve(loan_id: &str) {
+        act_as(ADMIN);
+        block_on(approve_loan(loan_id.to_string())).expect("loan is disbursed");
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller is not authorized for this operation")]
+    fn portfolio_reports_are_limited_to_staff() {
+        setup();
+        act_as(MEMBER);
+        get_portfolio_summary();
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller is not authorized for this operation")]
+    fn auditors_cannot_grant_roles() {
+        setup();
+        act_as(AUDITOR);
+        grant_staff_role(principal(MEMBER), StaffRole::Admin);
+    }
+    
+    #[test]
+    fn portfolio_summary_buckets_loans_by_days_past_due() {
+        setup();
+        let overdue = apply(MEMBER, 1200.0, 12);
+        apply(MEMBER + 1, 500.0, 6);
+        approve(&overdue);
+        
+        // First installment fell due a month after approval, 61 days ago
+        set_time(START + NANOS_PER_MONTH + 61 * NANOS_PER_DAY);
+        act_as(AUDITOR);
+        let summary = get_portfolio_summary();
+        assert_eq!(summary.total_loans, 2);
+        assert_eq!(summary.disbursed_loans, 1);
+        assert_eq!(summary.outstanding_loans, 1);
+        assert_eq!(summary.outstanding_principal, 1200.0);
+        let at_risk: Vec<u64> = summary.portfolio_at_risk.iter().map(|bucket| bucket.loan_count).collect();
+        assert_eq!(at_risk, vec![1, 1, 0]);
+        assert_eq!(summary.portfolio_at_risk[0].ratio, 1.0);
+    }
+    
+    #[test]
+    fn list_loans_filters_by_status_and_pages() {
+        setup();
+        let first = apply(MEMBER, 100.0, 6);
+        let second = apply(MEMBER + 1, 200.0, 6);
+        let third = apply(MEMBER + 2, 300.0, 6);
+        approve(&second);
+        
+        act_as(AUDITOR);
+        let pending = list_loans(Some(LoanStatus::Pending), 0, 10);
+        assert_eq!(pending.total, 2);
+        let ids: Vec<&str> = pending.loans.iter().map(|loan| loan.id.as_str()).collect();
+        assert_eq!(ids, vec![first.as_str(), third.as_str()]);
+        
+        let page = list_loans(None, 1, 1);
+        assert_eq!(page.total, 3);
+        assert_eq!(page.loans.len(), 1);
+        assert_eq!(page.loans[0].id, second);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Report end must be after its start")]
+    fn disbursement_report_rejects_an_empty_range() {
+        setup();
+        act_as(AUDITOR);
+        get_disbursement_report(START, START, ReportPeriod::Day);
+    }
+    
+    #[test]
+    fn disbursement_report_groups_loans_by_period() {
+        setup();
+        let loan = apply(MEMBER, 1000.0, 12);
+        set_time(START + 3 * NANOS_PER_DAY);
+        approve(&loan);
+        
+        act_as(AUDITOR);
+        let report = get_disbursement_report(START, START + 2 * 7 * NANOS_PER_DAY, ReportPeriod::Week);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].loans_disbursed, 1);
+        assert_eq!(report[0].amount_disbursed, 1000.0);
+        assert_eq!(report[1].loans_disbursed, 0);
+    }
+    
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+    
+    // An unpaid installment due `month` months after START, under the
+    // default late payment policy
+    fn installment(month: u64, principal_due: f64, interest_due: f64) -> Installment {
+        let policy = LatePaymentPolicy::default();
+        let due_date = START + month * NANOS_PER_MONTH;
+        let grace_period_end = due_date + policy.grace_period_days * NANOS_PER_DAY;
+        Installment {
+            number: month as u8,
+            due_date,
+            grace_period_end,
+            principal_due,
+            interest_due,
+            charge_due: 0.0,
+            fees_due: 0.0,
+            principal_paid: 0.0,
+            interest_paid: 0.0,
+            charge_paid: 0.0,
+            fees_paid: 0.0,
+            late_fee_charged: false,
+            penalty_accrued_until: grace_period_end,
+            late_fee_percent: policy.late_fee_percent,
+            penalty_rate: policy.penalty_rate,
+            status: InstallmentStatus::Pending,
+        }
+    }
+    
+    #[test]
+    fn allocate_payment_settles_fees_interest_charges_then_principal() {
+        let mut first = installment(1, 100.0, 10.0);
+        first.fees_due = 5.0;
+        first.charge_due = 3.0;
+        let mut schedule = vec![first, installment(2, 100.0, 10.0)];
+        
+        let allocation = allocate_payment(&mut schedule, 12.0);
+        assert_close(allocation.fees, 5.0);
+        assert_close(allocation.interest, 7.0);
+        assert_close(allocation.charges, 0.0);
+        assert_close(allocation.principal, 0.0);
+        
+        let allocation = allocate_payment(&mut schedule, 10.0);
+        assert_close(allocation.interest, 3.0);
+        assert_close(allocation.charges, 3.0);
+        assert_close(allocation.principal, 4.0);
+        assert!(schedule[0].status == InstallmentStatus::Pending);
+    }
+    
+    #[test]
+    fn allocate_payment_clears_the_oldest_installment_first() {
+        let mut schedule = vec![installment(1, 100.0, 10.0), installment(2, 100.0, 10.0)];
+        
+        let allocation = allocate_payment(&mut schedule, 150.0);
+        assert_close(allocation.interest, 20.0);
+        assert_close(allocation.principal, 130.0);
+        assert!(schedule[0].status == InstallmentStatus::Paid);
+        assert_close(schedule[1].interest_paid, 10.0);
+        assert_close(schedule[1].principal_paid, 30.0);
+        assert!(schedule[1].status == InstallmentStatus::Pending);
+    }
+    
+    #[test]
+    fn late_charges_wait_for_the_grace_period() {
+        let mut schedule = vec![installment(1, 100.0, 10.0)];
+        let grace_period_end = schedule[0].grace_period_end;
+        
+        assess_late_charges(&mut schedule, true, grace_period_end);
+        assert_close(schedule[0].fees_due, 0.0);
+        assert!(schedule[0].status == InstallmentStatus::Pending);
+        
+        assess_late_charges(&mut schedule, true, grace_period_end + 1);
+        assert_close(schedule[0].fees_due, 110.0 * 0.02);
+        assert!(schedule[0].status == InstallmentStatus::Overdue);
+    }
+    
+    #[test]
+    fn late_fee_is_charged_once_and_penalty_accrues_daily() {
+        let mut schedule = vec![installment(1, 100.0, 10.0)];
+        let grace_period_end = schedule[0].grace_period_end;
+        let expected = 110.0 * 0.02 + 110.0 * 0.12 / 365.0 * 10.0;
+        
+        assess_late_charges(&mut schedule, true, grace_period_end + 10 * NANOS_PER_DAY);
+        assert_close(schedule[0].fees_due, expected);
+        
+        // Re-assessing on the same day adds nothing; partial days do not accrue
+        assess_late_charges(&mut schedule, true, grace_period_end + 10 * NANOS_PER_DAY + NANOS_PER_DAY / 2);
+        assert_close(schedule[0].fees_due, expected);
+        
+        // Interest-free financing is charged the late fee only
+        let mut schedule = vec![installment(1, 100.0, 10.0)];
+        assess_late_charges(&mut schedule, false, grace_period_end + 10 * NANOS_PER_DAY);
+        assert_close(schedule[0].fees_due, 110.0 * 0.02);
+    }
+    
+    #[test]
+    fn late_charges_skip_paid_installments() {
+        let mut schedule = vec![installment(1, 100.0, 10.0)];
+        allocate_payment(&mut schedule, 110.0);
+        let grace_period_end = schedule[0].grace_period_end;
+        
+        assess_late_charges(&mut schedule, true, grace_period_end + 30 * NANOS_PER_DAY);
+        assert_close(schedule[0].fees_due, 0.0);
+        assert!(schedule[0].status == InstallmentStatus::Paid);
+    }
+    
+    #[test]
+    fn late_payment_settles_charges_before_the_installment() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        // One day past the first installment's grace period
+        set_time(START + NANOS_PER_MONTH + 8 * NANOS_PER_DAY);
+        act_as(MEMBER);
+        let payment = block_on(make_payment(loan_id.clone(), 20.0)).expect("payment is taken");
+        assert_close(payment.fees_portion, 110.0 * 0.02 + 110.0 * 0.12 / 365.0);
+        assert_close(payment.interest_portion, 10.0);
+        assert_close(payment.principal_portion, 10.0 - payment.fees_portion);
+        
+        let schedule = get_repayment_schedule(loan_id);
+        assert!(schedule[0].status == InstallmentStatus::Overdue);
+        assert_eq!(schedule.len(), 12);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Payment exceeds the outstanding balance")]
+    fn payments_cannot_exceed_the_outstanding_balance() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        block_on(make_payment(loan_id, 1320.01)).expect("payment is taken");
+    }
+    
+    // Three monthly installments at 12% a year, with 150 of the first two
+    // installments' principal prepaid, leaving 150 outstanding
+    fn prepaid_schedule() -> Vec<Installment> {
+        let mut schedule = vec![installment(1, 100.0, 1.0), installment(2, 100.0, 1.0), installment(3, 100.0, 1.0)];
+        schedule[0].principal_paid = 100.0;
+        schedule[1].principal_paid = 50.0;
+        schedule
+    }
+    
+    #[test]
+    fn prepayment_can_shorten_the_term() {
+        let mut schedule = prepaid_schedule();
+        
+        reschedule_after_prepayment(&mut schedule, 12.0, 101.0, &PrepaymentMode::ShortenTerm, START);
+        assert_eq!(schedule.len(), 2);
+        assert_eq!(schedule[0].number, 1);
+        assert_eq!(schedule[0].due_date, START + NANOS_PER_MONTH);
+        assert_eq!(schedule[1].due_date, START + 2 * NANOS_PER_MONTH);
+        for installment in &schedule {
+            assert_close(installment.principal_due, 75.0);
+            assert_close(installment.interest_due, 1.5);
+        }
+    }
+    
+    #[test]
+    fn prepayment_can_reduce_the_installment() {
+        let mut schedule = prepaid_schedule();
+        
+        reschedule_after_prepayment(&mut schedule, 12.0, 101.0, &PrepaymentMode::ReduceInstallment, START);
+        assert_eq!(schedule.len(), 3);
+        assert_eq!(schedule[2].number, 3);
+        for installment in &schedule {
+            assert_close(installment.principal_due, 50.0);
+            assert_close(installment.interest_due, 1.5);
+        }
+    }
+    
+    #[test]
+    fn prepayment_keeps_past_installments_and_carries_over_paid_interest() {
+        let mut schedule = prepaid_schedule();
+        allocate_payment(&mut schedule[..1], 1.0);
+        schedule[1].interest_paid = 1.0;
+        
+        reschedule_after_prepayment(&mut schedule, 12.0, 101.0, &PrepaymentMode::ReduceInstallment, START + NANOS_PER_MONTH);
+        assert_eq!(schedule.len(), 3);
+        assert!(schedule[0].status == InstallmentStatus::Paid);
+        assert_eq!(schedule[1].number, 2);
+        assert_close(schedule[1].principal_due, 75.0);
+        assert_close(schedule[1].interest_due, 1.5);
+        assert_close(schedule[1].interest_paid, 1.0);
+    }
+    
+    #[test]
+    fn prepayment_reschedules_the_loan_and_is_recorded_in_its_history() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        let payment = block_on(make_prepayment(loan_id.clone(), 600.0, PrepaymentMode::ReduceInstallment)).expect("payment is taken");
+        assert_close(payment.principal_portion, 600.0);
+        
+        let schedule = get_repayment_schedule(loan_id.clone());
+        assert_eq!(schedule.len(), 12);
+        assert_close(schedule[0].principal_due, 50.0);
+        
+        let history = get_loan_history(loan_id);
+        assert!(matches!(history.last().map(|event| &event.kind), Some(LoanEventKind::Prepayment { .. })));
+    }
+    
+    #[test]
+    fn early_payoff_waives_interest_not_yet_earned() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        set_time(START + NANOS_PER_MONTH / 2);
+        act_as(MEMBER);
+        let quote = get_payoff_quote(loan_id.clone(), START + NANOS_PER_MONTH / 2);
+        assert_close(quote.outstanding_principal, 1200.0);
+        assert!(quote.interest_waived > 0.0);
+        
+        block_on(make_prepayment(loan_id.clone(), quote.total, PrepaymentMode::ShortenTerm)).expect("payment is taken");
+        let history = get_loan_history(loan_id.clone());
+        assert!(matches!(history.last().map(|event| &event.kind), Some(LoanEventKind::EarlyPayoff { .. })));
+        assert!(get_repayment_schedule(loan_id).iter().all(|i| i.status == InstallmentStatus::Paid));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Prepayment exceeds the payoff amount")]
+    fn prepayments_cannot_exceed_the_payoff_amount() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        block_on(make_prepayment(loan_id, 5000.0, PrepaymentMode::ShortenTerm)).expect("payment is taken");
+    }
+    
+    #[test]
+    #[should_panic(expected = "Payoff quotes cannot be issued for a past time")]
+    fn payoff_quotes_cannot_be_backdated() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        set_time(START + NANOS_PER_DAY);
+        act_as(MEMBER);
+        get_payoff_quote(loan_id, START);
+    }
+    
+    // Three monthly installments with the first ten days past its grace period
+    fn schedule_in_arrears() -> (Vec<Installment>, u64) {
+        let mut schedule = vec![installment(1, 100.0, 1.0), installment(2, 100.0, 1.0), installment(3, 100.0, 1.0)];
+        let now = schedule[0].grace_period_end + 10 * NANOS_PER_DAY;
+        assess_late_charges(&mut schedule, true, now);
+        (schedule, now)
+    }
+    
+    fn restructure_request(capitalize_arrears: bool) -> RestructureRequest {
+        RestructureRequest {
+            extend_term_months: 2,
+            payment_holiday_months: 1,
+            capitalize_arrears,
+            new_interest_rate: None,
+            reason: "Hardship".to_string(),
+        }
+    }
+    
+    #[test]
+    fn restructuring_capitalizes_arrears_into_the_extended_schedule() {
+        let (mut schedule, now) = schedule_in_arrears();
+        let policy = LatePaymentPolicy::default();
+        let late_charges = schedule[0].fees_due;
+        
+        let capitalized = restructure_schedule(&mut schedule, &restructure_request(true), 12.0, &policy, now);
+        assert_close(capitalized.interest, 1.0);
+        assert_close(capitalized.fees, late_charges);
+        
+        // Two upcoming installments plus two extra months, after a month's holiday
+        let principal = 300.0 + 1.0 + late_charges;
+        assert_eq!(schedule.len(), 4);
+        assert_eq!(schedule[0].number, 1);
+        assert_eq!(schedule[0].due_date, START + 3 * NANOS_PER_MONTH);
+        assert_eq!(schedule[3].due_date, START + 6 * NANOS_PER_MONTH);
+        assert_close(schedule.iter().map(|i| i.principal_due).sum(), principal);
+        assert_close(schedule.iter().map(|i| i.interest_due).sum(), principal * 0.01 * 5.0);
+        assert!(schedule.iter().all(|i| i.fees_due == 0.0 && i.status == InstallmentStatus::Pending));
+    }
+    
+    #[test]
+    fn restructuring_without_capitalizing_keeps_arrears_due_first() {
+        let (mut schedule, now) = schedule_in_arrears();
+        let policy = LatePaymentPolicy::default();
+        let late_charges = schedule[0].fees_due;
+        
+        let capitalized = restructure_schedule(&mut schedule, &restructure_request(false), 12.0, &policy, now);
+        assert_close(capitalized.interest + capitalized.fees, 0.0);
+        assert_eq!(schedule.len(), 4);
+        assert_close(schedule.iter().map(|i| i.principal_due).sum(), 300.0);
+        assert_close(schedule[0].fees_due, late_charges);
+        assert_close(schedule[0].interest_due, 300.0 * 0.01 * 5.0 / 4.0 + 1.0);
+    }
+    
+    #[test]
+    fn restructuring_keeps_paid_installments() {
+        let (mut schedule, now) = schedule_in_arrears();
+        let overdue = schedule[0].remaining();
+        allocate_payment(&mut schedule, overdue);
+        let policy = LatePaymentPolicy::default();
+        
+        restructure_schedule(&mut schedule, &restructure_request(false), 12.0, &policy, now);
+        assert_eq!(schedule.len(), 5);
+        assert!(schedule[0].status == InstallmentStatus::Paid);
+        assert_eq!(schedule[1].number, 2);
+        assert_close(schedule[1..].iter().map(|i| i.principal_due).sum(), 200.0);
+    }
+    
+    #[test]
+    fn restructuring_a_loan_updates_its_terms_and_history() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(ADMIN);
+        let loan = restructure_loan(loan_id.clone(), restructure_request(false));
+        assert_eq!(loan.term_months, 14);
+        
+        let history = get_loan_history(loan_id);
+        let restructured = history.last().filter(|event| matches!(event.kind, LoanEventKind::Restructured { .. }));
+        let previous_terms = restructured.and_then(|event| event.previous_terms.as_ref());
+        assert_eq!(previous_terms.map(|terms| terms.term_months), Some(12));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller is not authorized for this operation")]
+    fn borrowers_cannot_restructure_their_own_loans() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        restructure_loan(loan_id, restructure_request(true));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Restructuring can only keep or reduce the interest rate")]
+    fn restructuring_cannot_raise_the_interest_rate() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(ADMIN);
+        let mut request = restructure_request(false);
+        request.new_interest_rate = Some(50.0);
+        restructure_loan(loan_id, request);
+    }
+    
+    fn rejection(member: u8, amount: f64, term_months: u8) -> LoanValidationError {
+        act_as(member);
+        match apply_for_loan(amount, term_months, LoanType::Personal) {
+            Ok(_) => panic!("Loan application was accepted"),
+            Err(error) => error,
+        }
+    }
+    
+    #[test]
+    fn applications_outside_the_product_limits_are_rejected() {
+        setup();
+        
+        assert!(matches!(rejection(MEMBER, f64::NAN, 12), LoanValidationError::InvalidAmount));
+        assert!(matches!(
+            rejection(MEMBER, 50.0, 12),
+            LoanValidationError::AmountBelowMinimum { minimum } if minimum == 100.0
+        ));
+        assert!(matches!(
+            rejection(MEMBER, 6_000.0, 12),
+            LoanValidationError::AmountAboveMaximum { maximum } if maximum == 5_000.0
+        ));
+        assert!(matches!(
+            rejection(MEMBER, 1_000.0, 7),
+            LoanValidationError::TermNotAllowed { allowed_terms } if allowed_terms == vec![3, 6, 12, 24]
+        ));
+    }
+    
+    #[test]
+    fn applications_are_limited_by_open_loans_and_eligibility() {
+        setup();
+        assert!(matches!(
+            rejection(MEMBER, 3_500.0, 12),
+            LoanValidationError::ExceedsEligibility { eligible_amount } if eligible_amount == 3_000.0
+        ));
+        
+        // Principal owed on open loans counts against eligibility
+        apply(MEMBER, 1_000.0, 12);
+        assert!(matches!(
+            rejection(MEMBER, 2_500.0, 12),
+            LoanValidationError::ExceedsEligibility { eligible_amount } if eligible_amount == 2_000.0
+        ));
+        
+        apply(MEMBER, 1_000.0, 12);
+        assert!(matches!(
+            rejection(MEMBER, 500.0, 12),
+            LoanValidationError::TooManyConcurrentLoans { limit: 2 }
+        ));
+    }
+    
+    #[test]
+    fn applications_follow_the_configured_product() {
+        setup();
+        act_as(ADMIN);
+        let mut product = default_product(&LoanType::Personal);
+        product.interest_rate = 12.0;
+        product.allowed_terms = vec![9];
+        set_loan_product(product);
+        
+        assert!(matches!(rejection(MEMBER, 1_000.0, 12), LoanValidationError::TermNotAllowed { .. }));
+        act_as(MEMBER);
+        let loan = apply_for_loan(1_000.0, 9, LoanType::Personal);
+        assert!(matches!(loan, Ok(loan) if loan.interest_rate == 12.0));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Products need at least one non-zero term")]
+    fn products_need_a_term() {
+        setup();
+        act_as(ADMIN);
+        let mut product = default_product(&LoanType::Personal);
+        product.allowed_terms = vec![0];
+        set_loan_product(product);
+    }
+    
+    fn allocation(member: u8, amount: f64) -> GroupAllocation {
+        GroupAllocation {
+            member: principal(member),
+            amount,
+        }
+    }
+    
+    fn group_rejection(applicant: u8, allocations: Vec<GroupAllocation>) -> LoanValidationError {
+        act_as(applicant);
+        match apply_for_group_loan("Harvest".to_string(), allocations, 12, LoanType::Personal) {
+            Ok(_) => panic!("Group loan application was accepted"),
+            Err(error) => error,
+        }
+    }
+    
+    // Applied for by the first member; the others have not accepted yet
+    fn request_group_loan(members: &[u8], amount_each: f64) -> String {
+        let allocations = members.iter().map(|member| allocation(*member, amount_each)).collect();
+        act_as(members[0]);
+        match apply_for_group_loan("Harvest".to_string(), allocations, 12, LoanType::Personal) {
+            Ok(loan) => loan.id,
+            Err(_) => panic!("Group loan application was rejected"),
+        }
+    }
+    
+    fn apply_as_group(members: &[u8], amount_each: f64) -> String {
+        let loan_id = request_group_loan(members, amount_each);
+        for member in &members[1..] {
+            act_as(*member);
+            if accept_group_loan(loan_id.clone()).is_err() {
+                panic!("Group loan acceptance was rejected");
+            }
+        }
+        loan_id
+    }
+    
+    #[test]
+    fn group_applications_are_validated() {
+        setup();
+        
+        let invalid = |error: LoanValidationError| match error {
+            LoanValidationError::InvalidGroup { reason } => reason,
+            _ => panic!("Expected an invalid group"),
+        };
+        assert_eq!(
+            invalid(group_rejection(MEMBER, vec![allocation(MEMBER, 500.0)])),
+            "Groups need between 2 and 30 members"
+        );
+        assert_eq!(
+            invalid(group_rejection(MEMBER, vec![allocation(MEMBER + 1, 500.0), allocation(MEMBER + 2, 500.0)])),
+            "The applicant must be a member of the group"
+        );
+        assert_eq!(
+            invalid(group_rejection(MEMBER, vec![allocation(MEMBER, 500.0), allocation(MEMBER, 500.0)])),
+            "Each member can only appear once"
+        );
+        
+        let error = group_rejection(MEMBER, vec![allocation(MEMBER, 500.0), allocation(MEMBER + 1, 4_000.0)]);
+        assert!(matches!(
+            error,
+            LoanValidationError::GroupMemberIneligible { member, error }
+                if member == principal(MEMBER + 1) && matches!(*error, LoanValidationError::ExceedsEligibility { .. })
+        ));
+    }
+    
+    #[test]
+    fn group_loan_shares_count_against_each_members_eligibility() {
+        setup();
+        apply_as_group(&[MEMBER, MEMBER + 1], 600.0);
+        
+        assert!(matches!(
+            rejection(MEMBER + 1, 2_500.0, 12),
+            LoanValidationError::ExceedsEligibility { eligible_amount } if eligible_amount == 2_400.0
+        ));
+    }
+    
+    #[test]
+    fn group_repayment_status_tracks_each_members_arrears() {
+        setup();
+        let loan_id = apply_as_group(&[MEMBER, MEMBER + 1], 600.0);
+        approve(&loan_id);
+        
+        // Any member can pay towards the group's installment
+        set_time(START + NANOS_PER_MONTH + NANOS_PER_DAY);
+        act_as(MEMBER + 1);
+        block_on(make_payment(loan_id.clone(), 55.0)).expect("payment is taken");
+        
+        let status = get_group_repayment_status(loan_id);
+        assert_close(status.due_to_date, 110.0);
+        assert_close(status.arrears, 55.0);
+        let arrears: Vec<f64> = status.members.iter().map(|member| member.arrears).collect();
+        assert_eq!(arrears, vec![55.0, 0.0]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Not a group loan")]
+    fn group_repayment_status_needs_a_group_loan() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        
+        act_as(MEMBER);
+        get_group_repayment_status(loan_id);
+    }
+    
+    #[test]
+    fn disbursements_draw_on_pool_liquidity() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        let pool = get_lending_pool();
+        assert_close(pool.total_capital, 10_000.0);
+        assert_close(pool.available_liquidity, 8_800.0);
+        assert_close(pool.deployed_capital, 1_200.0);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Insufficient lending pool liquidity to fund this loan")]
+    fn loans_cannot_be_approved_beyond_pool_liquidity() {
+        setup();
+        act_as(FUNDER);
+        block_on(withdraw_from_pool(9_500.0, FundingSource::DedicatedDeposit)).expect("capital is returned");
+        
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+    }
+    
+    #[test]
+    fn repayments_return_principal_and_share_interest_with_contributors() {
+        setup();
+        act_as(MEMBER + 1);
+        block_on(contribute_to_pool(10_000.0, FundingSource::MemberSavings));
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        block_on(make_payment(loan_id, 110.0)).expect("payment is taken");
+        let pool = get_lending_pool();
+        assert_close(pool.available_liquidity, 18_900.0);
+        assert_close(pool.deployed_capital, 1_100.0);
+        // The treasury keeps its 10% spread of the interest
+        assert_close(pool.interest_distributed, 9.0);
+        assert_close(pool.treasury_income, 1.0);
+        
+        act_as(FUNDER);
+        let claim = block_on(claim_pool_earnings()).expect("earnings are paid");
+        assert_close(claim.amount, 4.5);
+        assert!(get_pool_contribution().is_some_and(|contribution| contribution.earnings == 0.0));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Insufficient lending pool liquidity for this withdrawal")]
+    fn deployed_capital_cannot_be_withdrawn() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(FUNDER);
+        block_on(withdraw_from_pool(9_000.0, FundingSource::DedicatedDeposit)).expect("capital is returned");
+    }
+    
+    #[test]
+    #[should_panic(expected = "Withdrawal exceeds contributed capital")]
+    fn contributors_cannot_withdraw_more_than_they_provided() {
+        setup();
+        act_as(MEMBER);
+        block_on(contribute_to_pool(100.0, FundingSource::MemberSavings));
+        block_on(withdraw_from_pool(200.0, FundingSource::MemberSavings)).expect("capital is returned");
+    }
+    
+    // A 1200 loan whose first installment is 91 days past due, marked as
+    // defaulted and written off
+    fn written_off_loan() -> String {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        set_time(START + NANOS_PER_MONTH + 91 * NANOS_PER_DAY);
+        act_as(ADMIN);
+        mark_loan_defaulted(loan_id.clone());
+        write_off_loan(loan_id.clone(), "Borrower unreachable".to_string());
+        loan_id
+    }
+    
+    #[test]
+    fn provisioning_reserves_by_days_past_due() {
+        setup();
+        let current = apply(MEMBER, 1000.0, 12);
+        let overdue = apply(MEMBER + 1, 1200.0, 12);
+        approve(&overdue);
+        set_time(START + 5 * NANOS_PER_DAY);
+        approve(&current);
+        
+        // The older loan's first installment is 35 days late, the other's 30
+        set_time(START + NANOS_PER_MONTH + 35 * NANOS_PER_DAY);
+        act_as(AUDITOR);
+        let report = get_provisioning_report();
+        let counts: Vec<u64> = report.buckets.iter().map(|bucket| bucket.loan_count).collect();
+        assert_eq!(counts, vec![0, 2, 0, 0, 0]);
+        assert_close(report.total_required_reserve, 220.0);
+    }
+    
+    #[test]
+    fn write_off_moves_the_balance_to_the_loss_account() {
+        let loan_id = written_off_loan();
+        
+        let write_off = get_write_off(loan_id).expect("write-off recorded");
+        assert_close(write_off.principal, 1200.0);
+        assert_close(write_off.interest, 120.0);
+        assert!(write_off.fees > 0.0);
+        
+        let pool = get_lending_pool();
+        assert_close(pool.total_capital, 8_800.0);
+        assert_close(pool.deployed_capital, 0.0);
+        let report = get_provisioning_report();
+        assert_close(report.loss_account.principal_written_off, 1200.0);
+        assert!(report.buckets.iter().all(|bucket| bucket.loan_count == 0));
+    }
+    
+    #[test]
+    fn payments_on_written_off_loans_are_recoveries() {
+        let loan_id = written_off_loan();
+        
+        act_as(MEMBER);
+        block_on(make_payment(loan_id.clone(), 100.0)).expect("payment is taken");
+        
+        act_as(ADMIN);
+        let write_off = get_write_off(loan_id).expect("write-off recorded");
+        assert_close(write_off.recovered, 100.0);
+        assert_close(get_provisioning_report().loss_account.recoveries, 100.0);
+        assert_close(get_lending_pool().available_liquidity, 8_900.0);
+    }
+    
+    #[test]
+    fn staff_can_read_loan_details_and_payments() {
+        setup();
+        act_as(ADMIN);
+        grant_staff_role(principal(OFFICER), StaffRole::LoanOfficer);
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        approve(&loan_id);
+        act_as(MEMBER);
+        block_on(make_payment(loan_id.clone(), 110.0)).expect("payment is taken");
+        
+        for staff in [AUDITOR, OFFICER] {
+            act_as(staff);
+            assert!(get_loan_details(loan_id.clone()).is_some());
+            assert_eq!(get_payments(loan_id.clone()).len(), 1);
+        }
+        act_as(MEMBER + 1);
+        assert!(get_loan_details(loan_id.clone()).is_none());
+        assert!(get_payments(loan_id).is_empty());
+    }
+    
+    #[test]
+    fn recoveries_beyond_the_written_off_principal_are_income() {
+        let loan_id = written_off_loan();
+        
+        act_as(MEMBER);
+        let payment = block_on(make_payment(loan_id.clone(), 1_250.0)).expect("payment is taken");
+        assert_close(payment.principal_portion, 1_200.0);
+        assert_close(payment.interest_portion, 50.0);
+        
+        // Only the principal restores capital; the interest is shared after the spread
+        let pool = get_lending_pool();
+        assert_close(pool.available_liquidity, 10_000.0);
+        assert_close(pool.total_capital, 10_000.0);
+        assert_close(pool.interest_collected, 50.0);
+        act_as(FUNDER);
+        assert!(get_pool_contribution().is_some_and(|contribution| (contribution.earnings - 45.0).abs() < 1e-9));
+        
+        // A later recovery carries on from the interest
+        act_as(MEMBER);
+        let payment = block_on(make_payment(loan_id, 70.0)).expect("payment is taken");
+        assert_close(payment.principal_portion, 0.0);
+        assert_close(payment.interest_portion, 70.0);
+        assert_close(get_lending_pool().total_capital, 10_000.0);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Payment exceeds the written-off balance")]
+    fn recoveries_cannot_exceed_the_written_off_balance() {
+        let loan_id = written_off_loan();
+        
+        act_as(MEMBER);
+        block_on(make_payment(loan_id, 2_000.0)).expect("payment is taken");
+    }
+    
+    #[test]
+    #[should_panic(expected = "Loans can only be marked as defaulted after 90 days past due")]
+    fn loans_default_only_after_ninety_days() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        set_time(START + NANOS_PER_MONTH + 89 * NANOS_PER_DAY);
+        act_as(ADMIN);
+        mark_loan_defaulted(loan_id);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only defaulted loans can be written off")]
+    fn only_defaulted_loans_are_written_off() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(ADMIN);
+        write_off_loan(loan_id, "Borrower unreachable".to_string());
+    }
+    
+    fn offer_personal_loans_as(financing_model: FinancingModel) {
+        act_as(ADMIN);
+        let mut product = default_product(&LoanType::Personal);
+        product.financing_model = financing_model;
+        product.interest_rate = 0.0;
+        set_loan_product(product);
+    }
+    
+    #[test]
+    fn murabaha_profit_is_shared_with_contributors_instead_of_interest() {
+        setup();
+        offer_personal_loans_as(FinancingModel::Murabaha { profit_percent: 10.0 });
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        let payment = block_on(make_payment(loan_id, 110.0)).expect("payment is taken");
+        assert_close(payment.interest_portion, 0.0);
+        assert_close(payment.charge_portion, 10.0);
+        assert_close(payment.principal_portion, 100.0);
+        
+        let pool = get_lending_pool();
+        assert_close(pool.interest_collected, 0.0);
+        assert_close(pool.profit_collected, 10.0);
+        assert_close(pool.treasury_income, 1.0);
+        act_as(FUNDER);
+        assert!(get_pool_contribution().is_some_and(|contribution| contribution.earnings == 9.0));
+    }
+    
+    #[test]
+    fn qard_hasan_fee_is_fixed_and_not_rebated_on_early_payoff() {
+        setup();
+        offer_personal_loans_as(FinancingModel::QardHasan { administration_fee: 24.0 });
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        let schedule = get_repayment_schedule(loan_id.clone());
+        assert!(schedule.iter().all(|i| i.interest_due == 0.0 && i.charge_due == 2.0));
+        
+        let quote = get_payoff_quote(loan_id, START);
+        assert_close(quote.outstanding_charges, 24.0);
+        assert_close(quote.interest_waived, 0.0);
+        assert_close(quote.total, 1224.0);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Interest-free products cannot carry an interest rate")]
+    fn interest_free_products_cannot_carry_interest() {
+        setup();
+        act_as(ADMIN);
+        let mut product = default_product(&LoanType::Personal);
+        product.financing_model = FinancingModel::QardHasan { administration_fee: 24.0 };
+        set_loan_product(product);
+    }
+    
+    #[test]
+    fn interest_rate_tables_keep_the_other_product_limits() {
+        setup();
+        act_as(GOVERNANCE);
+        set_interest_rates(vec![
+            LoanRate { loan_type: LoanType::Personal, interest_rate: 9.0 },
+            LoanRate { loan_type: LoanType::Business, interest_rate: 7.5 },
+        ]);
+        
+        let products = get_loan_products();
+        let personal = products.iter().find(|p| p.loan_type == LoanType::Personal).expect("personal product");
+        assert_eq!(personal.interest_rate, 9.0);
+        assert_eq!(personal.max_amount, 5_000.0);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Interest-free products cannot carry an interest rate")]
+    fn interest_rate_tables_respect_interest_free_products() {
+        setup();
+        offer_personal_loans_as(FinancingModel::QardHasan { administration_fee: 24.0 });
+        act_as(GOVERNANCE);
+        set_interest_rates(vec![LoanRate { loan_type: LoanType::Personal, interest_rate: 9.0 }]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the governance canister can change the interest rates")]
+    fn interest_rates_change_only_through_governance() {
+        setup();
+        act_as(ADMIN);
+        set_interest_rates(vec![LoanRate { loan_type: LoanType::Personal, interest_rate: 9.0 }]);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller is not authorized for this operation")]
+    fn members_cannot_approve_loans() {
+        setup();
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        act_as(MEMBER);
+        block_on(approve_loan(loan_id)).expect("loan is disbursed");
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only pending loans can be rejected")]
+    fn approved_loans_cannot_be_rejected() {
+        setup();
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        approve(&loan_id);
+        reject_loan(loan_id);
+    }
+    
+    #[test]
+    fn pool_capital_moves_through_member_wallets() {
+        setup();
+        act_as(FUNDER);
+        block_on(withdraw_from_pool(1_000.0, FundingSource::DedicatedDeposit)).expect("capital is returned");
+        
+        let calls = wallet_calls();
+        assert_eq!(calls[0], ("debit_account".to_string(), principal(FUNDER), 10_000.0));
+        assert_eq!(calls[1], ("credit_account".to_string(), principal(FUNDER), 1_000.0));
+        assert_close(get_lending_pool().available_liquidity, 9_000.0);
+    }
+    
+    #[test]
+    fn failed_withdrawals_leave_the_capital_in_the_pool() {
+        setup();
+        reject_calls(true);
+        act_as(FUNDER);
+        let result = block_on(withdraw_from_pool(1_000.0, FundingSource::DedicatedDeposit));
+        assert_eq!(result.err(), Some("Call rejected (CanisterReject): Rejected by test".to_string()));
+        
+        assert_close(get_lending_pool().available_liquidity, 10_000.0);
+        assert!(get_pool_contribution().is_some_and(|contribution| contribution.deposit_capital == 10_000.0));
+    }
+    
+    #[test]
+    fn approval_credits_the_loan_to_the_borrowers_wallet() {
+        setup();
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        approve(&loan_id);
+        
+        let calls = wallet_calls();
+        assert_eq!(calls.last(), Some(&("credit_account".to_string(), principal(MEMBER), 1_200.0)));
+        assert_close(get_lending_pool().available_liquidity, 8_800.0);
+        assert_close(get_lending_pool().deployed_capital, 1_200.0);
+        act_as(AUDITOR);
+        assert!(get_pool_transactions(0, 10)
+            .iter()
+            .any(|tx| matches!(tx.tx_type, PoolTxType::Disbursement) && tx.loan_id.as_deref() == Some(loan_id.as_str())));
+    }
+    
+    #[test]
+    fn group_loans_are_credited_to_each_member() {
+        setup();
+        let loan_id = apply_as_group(&[MEMBER, MEMBER + 1], 600.0);
+        approve(&loan_id);
+        
+        let calls = wallet_calls();
+        assert_eq!(calls[calls.len() - 2..], [
+            ("credit_account".to_string(), principal(MEMBER), 600.0),
+            ("credit_account".to_string(), principal(MEMBER + 1), 600.0),
+        ]);
+    }
+    
+    #[test]
+    fn failed_disbursements_leave_the_loan_pending() {
+        setup();
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        reject_calls(true);
+        act_as(ADMIN);
+        let result = block_on(approve_loan(loan_id.clone()));
+        assert_eq!(result.err(), Some("Call rejected (CanisterReject): Rejected by test".to_string()));
+        
+        act_as(MEMBER);
+        assert!(get_loan_details(loan_id.clone()).is_some_and(|loan| loan.status == LoanStatus::Pending));
+        assert!(get_repayment_schedule(loan_id.clone()).is_empty());
+        assert_close(get_lending_pool().available_liquidity, 10_000.0);
+        assert_close(get_lending_pool().deployed_capital, 0.0);
+        
+        // The approval can be retried once the wallet accepts the credit
+        reject_calls(false);
+        approve(&loan_id);
+        act_as(MEMBER);
+        assert!(get_loan_details(loan_id).is_some_and(|loan| loan.status == LoanStatus::Approved));
+    }
+    
+    #[test]
+    fn payments_are_debited_from_the_payers_wallet() {
+        setup();
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        block_on(make_payment(loan_id.clone(), 110.0)).expect("payment is taken");
+        assert_eq!(wallet_calls().last(), Some(&("debit_account".to_string(), principal(MEMBER), 110.0)));
+        
+        let quote = get_payoff_quote(loan_id.clone(), START);
+        block_on(make_prepayment(loan_id, quote.total, PrepaymentMode::ShortenTerm)).expect("payment is taken");
+        assert_eq!(wallet_calls().last(), Some(&("debit_account".to_string(), principal(MEMBER), quote.total)));
+    }
+    
+    #[test]
+    fn failed_debits_leave_the_loan_and_pool_unchanged() {
+        setup();
+        let loan_id = apply(MEMBER, 1_200.0, 12);
+        approve(&loan_id);
+        let pool = get_lending_pool();
+        
+        reject_calls(true);
+        act_as(MEMBER);
+        let result = block_on(make_payment(loan_id.clone(), 110.0));
+        assert_eq!(result.err(), Some("Call rejected (CanisterReject): Rejected by test".to_string()));
+        
+        assert!(get_payments(loan_id.clone()).is_empty());
+        assert!(get_repayment_schedule(loan_id).iter().all(|i| i.principal_paid == 0.0 && i.interest_paid == 0.0));
+        assert_close(get_lending_pool().available_liquidity, pool.available_liquidity);
+        assert_close(get_lending_pool().interest_collected, 0.0);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Call rejected (CanisterReject): Rejected by test")]
+    fn contributions_need_the_wallet_debit() {
+        setup();
+        reject_calls(true);
+        fund_pool(500.0);
+    }
+    
+    #[test]
+    fn group_members_are_liable_only_once_they_accept() {
+        setup();
+        let loan_id = request_group_loan(&[MEMBER, MEMBER + 1], 600.0);
+        
+        // The invitee can see the request but their limits are untouched
+        act_as(MEMBER + 1);
+        assert!(get_loan_details(loan_id.clone()).is_some());
+        assert_eq!(get_loans().len(), 1);
+        assert!(matches!(
+            rejection(MEMBER + 1, 3_500.0, 12),
+            LoanValidationError::ExceedsEligibility { eligible_amount } if eligible_amount == 3_000.0
+        ));
+        
+        act_as(MEMBER + 1);
+        let accepted = accept_group_loan(loan_id.clone());
+        assert!(accepted.is_ok());
+        assert!(get_loan_history(loan_id)
+            .iter()
+            .any(|event| matches!(event.kind, LoanEventKind::GroupMemberAccepted { member } if member == principal(MEMBER + 1))));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Every group member must accept the loan before it can be approved")]
+    fn group_loans_need_every_acceptance_before_approval() {
+        setup();
+        let loan_id = request_group_loan(&[MEMBER, MEMBER + 1], 600.0);
+        approve(&loan_id);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Group loan already accepted")]
+    fn group_loans_are_accepted_once() {
+        setup();
+        let loan_id = apply_as_group(&[MEMBER, MEMBER + 1], 600.0);
+        act_as(MEMBER);
+        let _ = accept_group_loan(loan_id);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Caller is not a member of this group loan")]
+    fn only_named_members_accept_group_loans() {
+        setup();
+        let loan_id = request_group_loan(&[MEMBER, MEMBER + 1], 600.0);
+        act_as(MEMBER + 2);
+        let _ = accept_group_loan(loan_id);
+    }
+    
+    #[test]
+    fn repaid_capitalized_arrears_are_pool_income_not_capital() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        set_time(START + NANOS_PER_MONTH + 10 * NANOS_PER_DAY);
+        act_as(ADMIN);
+        restructure_loan(loan_id.clone(), restructure_request(true));
+        assert_close(get_lending_pool().deployed_capital, 1_200.0);
+        
+        act_as(MEMBER);
+        let quote = get_payoff_quote(loan_id.clone(), time());
+        assert!(quote.outstanding_principal > 1_200.0);
+        block_on(make_prepayment(loan_id, quote.total, PrepaymentMode::ShortenTerm)).expect("payment is taken");
+        
+        // Only the 1200 lent out returns as capital; the rest is income
+        let pool = get_lending_pool();
+        assert_close(pool.deployed_capital, 0.0);
+        assert_close(pool.available_liquidity, 10_000.0);
+        assert!(pool.interest_distributed > quote.accrued_interest);
+    }
+    
+    #[test]
+    fn late_charges_use_the_rates_the_installment_was_scheduled_with() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(ADMIN);
+        set_late_payment_policy(LatePaymentPolicy {
+            grace_period_days: 5,
+            late_fee_percent: 10.0,
+            penalty_rate: 30.0,
+        });
+        let later = apply(MEMBER + 1, 1200.0, 12);
+        approve(&later);
+        
+        act_as(MEMBER);
+        let schedule = get_repayment_schedule(loan_id);
+        assert_close(schedule[0].late_fee_percent, LatePaymentPolicy::default().late_fee_percent);
+        assert_close(schedule[0].penalty_rate, LatePaymentPolicy::default().penalty_rate);
+        act_as(MEMBER + 1);
+        let schedule = get_repayment_schedule(later);
+        assert_close(schedule[0].late_fee_percent, 10.0);
+        
+        let mut installment = schedule[0].clone();
+        installment.fees_due = 0.0;
+        let grace_period_end = installment.grace_period_end;
+        let mut schedule = vec![installment];
+        assess_late_charges(&mut schedule, false, grace_period_end + 1);
+        assert_close(schedule[0].fees_due, (schedule[0].principal_due + schedule[0].interest_due) * 0.1);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Late fee and penalty rate cannot be negative")]
+    fn late_payment_rates_cannot_be_negative() {
+        setup();
+        act_as(ADMIN);
+        set_late_payment_policy(LatePaymentPolicy {
+            grace_period_days: 5,
+            late_fee_percent: -1.0,
+            penalty_rate: 12.0,
+        });
+    }
+    
+    // Treasury income transfers made so far as (source, amount)
+    fn treasury_transfers() -> Vec<(TreasuryIncomeSource, f64)> {
+        ic_cdk::CALLS.with(|calls| {
+            calls
+                .borrow()
+                .iter()
+                .filter(|(method, _)| method == "receive_income")
+                .map(|(_, args)| {
+                    let (source, amount, _): (TreasuryIncomeSource, f64, Option<String>) =
+                        candid::utils::decode_args(args).expect("treasury arguments");
+                    (source, amount)
+                })
+                .collect()
+        })
+    }
+    
+    #[test]
+    fn late_fees_and_interest_spread_are_transferred_to_the_treasury() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        set_time(START + 45 * NANOS_PER_DAY);
+        act_as(MEMBER);
+        let payment = block_on(make_payment(loan_id, 120.0)).expect("payment is taken");
+        assert!(payment.fees_portion > 0.0);
+        
+        let transfers = block_on(transfer_income_to_treasury()).expect("income is transferred");
+        assert_eq!(transfers.len(), 2);
+        let sent = treasury_transfers();
+        assert!(sent[0].0 == TreasuryIncomeSource::PenaltyIncome);
+        assert_close(sent[0].1, payment.fees_portion);
+        assert!(sent[1].0 == TreasuryIncomeSource::InterestSpread);
+        assert_close(sent[1].1, payment.interest_portion / 10.0);
+        
+        let pool = get_lending_pool();
+        assert_close(pool.treasury_transferred, pool.treasury_income);
+        assert!(block_on(transfer_income_to_treasury()).is_ok_and(|transfers| transfers.is_empty()));
+    }
+    
+    #[test]
+    fn administration_fees_go_to_the_treasury_in_full() {
+        setup();
+        offer_personal_loans_as(FinancingModel::QardHasan { administration_fee: 24.0 });
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        
+        act_as(MEMBER);
+        block_on(make_payment(loan_id, 102.0)).expect("payment is taken");
+        block_on(transfer_income_to_treasury()).expect("income is transferred");
+        let sent = treasury_transfers();
+        assert!(sent.len() == 1 && sent[0].0 == TreasuryIncomeSource::Fees);
+        assert_close(sent[0].1, 2.0);
+    }
+    
+    #[test]
+    fn failed_treasury_transfers_stay_owed() {
+        setup();
+        let loan_id = apply(MEMBER, 1200.0, 12);
+        approve(&loan_id);
+        act_as(MEMBER);
+        block_on(make_payment(loan_id, 110.0)).expect("payment is taken");
+        
+        reject_calls(true);
+        assert!(block_on(transfer_income_to_treasury()).is_err());
+        assert_close(get_lending_pool().treasury_transferred, 0.0);
+        
+        reject_calls(false);
+        let transfers = block_on(transfer_income_to_treasury()).expect("income is transferred");
+        assert_close(transfers[0].amount, 1.0);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Interest spread must be between 0 and 100 percent")]
+    fn interest_spread_is_a_percentage() {
+        setup();
+        act_as(ADMIN);
+        set_interest_spread(120.0);
+    }
+}
@@ -0,0 +1,650 @@
+
+use candid::{CandidType, Deserialize, Principal, Reserved};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::api::time;
+use ic_cdk::export::{
+    candid,
+    serde::Serialize,
+};
+use ic_cdk_macros::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+const MAX_PAGE_SIZE: u64 = 100;
+const AMOUNT_EPSILON: f64 = 1e-6;
+
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TreasuryIncomeSource {
+    Fees,           // administration fees of interest-free loans
+    PenaltyIncome,  // late fees and penalty interest
+    InterestSpread, // the cooperative's share of loan interest and profit
+    Contributions,  // paid in by members from their wallets
+}
+
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum PayoutKind {
+    Budget, // a budget tranche approved by governance
+    Reward, // governance participation rewards
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub enum TreasuryTxKind {
+    Income { source: TreasuryIncomeSource },
+    Payout { kind: PayoutKind, recipient: Principal },
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct TreasuryTx {
+    id: String,
+    kind: TreasuryTxKind,
+    amount: f64,
+    timestamp: u64,
+    reference: Option<String>,
+}
+
+#[derive(CandidType, Clone, Default, Deserialize, Serialize)]
+pub struct Treasury {
+    balance: f64,
+    reserved: f64, // committed to approved budgets not yet paid out
+    total_income: f64,
+    total_spent: f64,
+}
+
+// Funds set aside for an approved budget, drawn down as it is paid out
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct Reservation {
+    reference: String,
+    amount: f64, // still to be paid out
+    created_at: u64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct IncomeTotal {
+    source: TreasuryIncomeSource,
+    amount: f64,
+}
+
+#[derive(CandidType, Clone, Deserialize, Serialize)]
+pub struct TreasuryReport {
+    as_of: u64,
+    treasury: Treasury,
+    available: f64,
+    income_by_source: Vec<IncomeTotal>,
+    reservations: Vec<Reservation>,
+}
+
+#[derive(Default)]
+struct TreasuryStorage {
+    treasury: Treasury,
+    ledger: Vec<TreasuryTx>,
+    reservations: BTreeMap<String, Reservation>, // reference -> reservation
+    admin: Option<Principal>,
+    governance_canister: Option<Principal>, // Reserves and pays out approved budgets
+    loans_canister: Option<Principal>,      // Pays in fees, penalty income and interest spread
+    wallet_canister: Option<Principal>,     // Holds the member balances the treasury pays into
+}
+
+thread_local! {
+    static STATE: RefCell<TreasuryStorage> = RefCell::new(TreasuryStorage::default());
+}
+
+#[init]
+fn init() {
+    let caller = ic_cdk::caller();
+    STATE.with(|state| {
+        state.borrow_mut().admin = Some(caller);
+    });
+}
+
+fn validate_amount(amount: f64) {
+    if !(amount > 0.0 && amount.is_finite()) {
+        ic_cdk::trap("Invalid amount");
+    }
+}
+
+fn require_governance(state: &TreasuryStorage) {
+    if state.governance_canister != Some(ic_cdk::caller()) {
+        ic_cdk::trap("Only the governance canister can spend from the treasury");
+    }
+}
+
+fn available(treasury: &Treasury) -> f64 {
+    treasury.balance - treasury.reserved
+}
+
+fn record_tx(state: &mut TreasuryStorage, kind: TreasuryTxKind, amount: f64, reference: Option<String>) -> TreasuryTx {
+    let tx = TreasuryTx {
+        id: format!("TRS-{}", state.ledger.len()),
+        kind,
+        amount,
+        timestamp: time(),
+        reference,
+    };
+    state.ledger.push(tx.clone());
+    tx
+}
+
+fn wallet_canister() -> Result<Principal, String> {
+    STATE.with(|state| state.borrow().wallet_canister).ok_or_else(|| "The wallet canister is not configured".to_string())
+}
+
+fn call_error((code, message): (RejectionCode, String)) -> String {
+    format!("Call rejected ({:?}): {}", code, message)
+}
+
+// Income the loans canister has collected and hands over to the treasury.
+// The loans canister no longer holds it once it is paid in.
+#[update]
+fn receive_income(source: TreasuryIncomeSource, amount: f64, reference: Option<String>) -> TreasuryTx {
+    let caller = ic_cdk::caller();
+    validate_amount(amount);
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.loans_canister != Some(caller) {
+            ic_cdk::trap("Only the loans canister can pay income into the treasury");
+        }
+        
+        state.treasury.balance += amount;
+        state.treasury.total_income += amount;
+        record_tx(&mut state, TreasuryTxKind::Income { source }, amount, reference)
+    })
+}
+
+// Moves the contribution out of the caller's wallet before it is counted, so
+// the treasury only ever holds money that exists
+#[update]
+async fn contribute(amount: f64) -> TreasuryTx {
+    let caller = ic_cdk::caller();
+    validate_amount(amount);
+    
+    let debited = match wallet_canister() {
+        Ok(wallet) => {
+            let description = "Treasury contribution".to_string();
+            ic_cdk::call::<_, (Reserved,)>(wallet, "debit_account", (caller, amount, description))
+                .await
+                .map_err(call_error)
+        }
+        Err(reason) => Err(reason),
+    };
+    if let Err(reason) = debited {
+        ic_cdk::trap(&reason);
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.treasury.balance += amount;
+        state.treasury.total_income += amount;
+        let kind = TreasuryTxKind::Income { source: TreasuryIncomeSource::Contributions };
+        record_tx(&mut state, kind, amount, Some(caller.to_text()))
+    })
+}
+
+// Sets funds aside for a budget governance has approved, so later payouts
+// cannot be starved by other spending
+#[update]
+fn reserve(reference: String, amount: f64) -> Result<Reservation, String> {
+    validate_amount(amount);
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        require_governance(&state);
+        
+        if state.reservations.contains_key(&reference) {
+            return Err(format!("Funds are already reserved for {}", reference));
+        }
+        let available = available(&state.treasury);
+        if amount > available + AMOUNT_EPSILON {
+            return Err(format!("Treasury has {:.2} available but the budget needs {:.2}", available, amount));
+        }
+        
+        let reservation = Reservation {
+            reference: reference.clone(),
+            amount,
+            created_at: time(),
+        };
+        state.treasury.reserved += amount;
+        state.reservations.insert(reference, reservation.clone());
+        Ok(reservation)
+    })
+}
+
+// Hands part of a reservation back to the available balance. Returns what is
+// still reserved.
+#[update]
+fn release_reservation(reference: String, amount: f64) -> f64 {
+    validate_amount(amount);
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        require_governance(state);
+        
+        let reservation = match state.reservations.get_mut(&reference) {
+            Some(reservation) => reservation,
+            None => ic_cdk::trap("Reservation not found"),
+        };
+        if amount > reservation.amount + AMOUNT_EPSILON {
+            ic_cdk::trap("Release exceeds the reserved amount");
+        }
+        let amount = amount.min(reservation.amount);
+        reservation.amount -= amount;
+        let remaining = reservation.amount;
+        state.treasury.reserved = (state.treasury.reserved - amount).max(0.0);
+        if remaining <= AMOUNT_EPSILON {
+            state.reservations.remove(&reference);
+        }
+        remaining
+    })
+}
+
+// Pays into a member's wallet, from a reservation when `reference` names one
+// and from the available balance otherwise. The amount leaves the treasury
+// before the wallet call and is put back if the call fails.
+#[update]
+async fn pay_out(
+    recipient: Principal,
+    amount: f64,
+    kind: PayoutKind,
+    reference: Option<String>,
+    description: String,
+) -> Result<TreasuryTx, String> {
+    validate_amount(amount);
+    
+    let wallet = wallet_canister()?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        require_governance(state);
+        
+        match &reference {
+            Some(reference) => {
+                let reservation = state
+                    .reservations
+                    .get_mut(reference)
+                    .ok_or_else(|| format!("No funds are reserved for {}", reference))?;
+                if amount > reservation.amount + AMOUNT_EPSILON {
+                    return Err(format!("{} has {:.2} reserved but the payout needs {:.2}", reference, reservation.amount, amount));
+                }
+                reservation.amount = (reservation.amount - amount).max(0.0);
+                state.treasury.reserved = (state.treasury.reserved - amount).max(0.0);
+            }
+            None => {
+                let available = available(&state.treasury);
+                if amount > available + AMOUNT_EPSILON {
+                    return Err(format!("Treasury has {:.2} available but the payout needs {:.2}", available, amount));
+                }
+            }
+        }
+        state.treasury.balance -= amount;
+        Ok(())
+    })?;
+    
+    let method = match kind {
+        PayoutKind::Budget => "credit_account",
+        PayoutKind::Reward => "credit_reward",
+    };
+    let result = ic_cdk::call::<_, (Reserved,)>(wallet, method, (recipient, amount, description))
+        .await
+        .map_err(call_error);
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        
+        if let Err(reason) = result {
+            state.treasury.balance += amount;
+            if let Some(reference) = &reference {
+                if let Some(reservation) = state.reservations.get_mut(reference) {
+                    reservation.amount += amount;
+                }
+                state.treasury.reserved += amount;
+            }
+            return Err(reason);
+        }
+        
+        if let Some(reference) = &reference {
+            if state.reservations.get(reference).is_some_and(|r| r.amount <= AMOUNT_EPSILON) {
+                state.reservations.remove(reference);
+            }
+        }
+        state.treasury.total_spent += amount;
+        Ok(record_tx(state, TreasuryTxKind::Payout { kind, recipient }, amount, reference))
+    })
+}
+
+// Balances, income by source and the funds reserved for approved budgets
+#[query]
+fn get_treasury_report() -> TreasuryReport {
+    STATE.with(|state| {
+        let state = state.borrow();
+        
+        let mut income_by_source: Vec<IncomeTotal> = Vec::new();
+        for tx in &state.ledger {
+            if let TreasuryTxKind::Income { source } = &tx.kind {
+                match income_by_source.iter_mut().find(|i| i.source == *source) {
+                    Some(total) => total.amount += tx.amount,
+                    None => income_by_source.push(IncomeTotal {
+                        source: *source,
+                        amount: tx.amount,
+                    }),
+                }
+            }
+        }
+        
+        TreasuryReport {
+            as_of: time(),
+            treasury: state.treasury.clone(),
+            available: available(&state.treasury),
+            income_by_source,
+            reservations: state.reservations.values().cloned().collect(),
+        }
+    })
+}
+
+#[query]
+fn get_treasury_transactions(offset: u64, limit: u64) -> Vec<TreasuryTx> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .ledger
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+#[update]
+fn set_governance_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the governance canister");
+        }
+        state.governance_canister = Some(canister_id);
+    });
+}
+
+#[update]
+fn set_loans_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the loans canister");
+        }
+        state.loans_canister = Some(canister_id);
+    });
+}
+
+#[update]
+fn set_wallet_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the wallet canister");
+        }
+        state.wallet_canister = Some(canister_id);
+    });
+}
+
+// Required for candid interface generation
+candid::export_service!();
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
+
+// Stands in for the system API in unit tests, which run outside a canister:
+// tests set the caller and the clock, and a trap panics with its message
+#[cfg(test)]
+mod ic_cdk {
+    pub use ::ic_cdk::*;
+    use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+    use candid::Principal;
+    use std::cell::{Cell, RefCell};
+    
+    thread_local! {
+        pub static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+        pub static TIME: Cell<u64> = const { Cell::new(0) };
+        // Inter-canister calls made so far: method and candid-encoded arguments
+        pub static CALLS: RefCell<Vec<(String, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+        pub static REJECT_CALLS: Cell<bool> = const { Cell::new(false) };
+    }
+    
+    pub fn caller() -> Principal {
+        CALLER.with(|caller| caller.get())
+    }
+    
+    pub fn trap(message: &str) -> ! {
+        panic!("{}", message)
+    }
+    
+    // Records the call and completes it straight away, replying with an empty
+    // record unless REJECT_CALLS is set
+    pub async fn call<T: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+        _id: Principal,
+        method: &str,
+        args: T,
+    ) -> api::call::CallResult<R> {
+        let encoded = candid::utils::encode_args(args).expect("arguments encode");
+        CALLS.with(|calls| calls.borrow_mut().push((method.to_string(), encoded)));
+        if REJECT_CALLS.with(|reject| reject.get()) {
+            return Err((api::call::RejectionCode::CanisterReject, "Rejected by test".to_string()));
+        }
+        let reply = candid::utils::encode_args(((),)).expect("reply encodes");
+        Ok(candid::utils::decode_args(&reply).expect("reply decodes"))
+    }
+    
+    pub mod api {
+        pub use ::ic_cdk::api::*;
+        
+        pub fn time() -> u64 {
+            super::TIME.with(|time| time.get())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const START: u64 = 1_700_000_000_000_000_000;
+    const ADMIN: u8 = 1;
+    const GOVERNANCE: u8 = 2;
+    const LOANS: u8 = 3;
+    const WALLET: u8 = 4;
+    const MEMBER: u8 = 10;
+    
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+    
+    fn act_as(id: u8) {
+        ic_cdk::CALLER.with(|caller| caller.set(principal(id)));
+    }
+    
+    // Drives an update call to completion; the stubbed inter-canister calls
+    // complete on the first poll
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("Call did not complete"),
+        }
+    }
+    
+    fn reject_calls(reject: bool) {
+        ic_cdk::REJECT_CALLS.with(|flag| flag.set(reject));
+    }
+    
+    // Wallet calls made so far as (method, member, amount)
+    fn wallet_calls() -> Vec<(String, Principal, f64)> {
+        ic_cdk::CALLS.with(|calls| {
+            calls
+                .borrow()
+                .iter()
+                .map(|(method, args)| {
+                    let (member, amount, _): (Principal, f64, String) = candid::utils::decode_args(args).expect("wallet arguments");
+                    (method.clone(), member, amount)
+                })
+                .collect()
+        })
+    }
+    
+    // A treasury deployed by ADMIN and connected to the other canisters, with
+    // 1000 of fee income paid in by the loans canister
+    fn setup() {
+        ic_cdk::TIME.with(|time| time.set(START));
+        act_as(ADMIN);
+        init();
+        set_governance_canister(principal(GOVERNANCE));
+        set_loans_canister(principal(LOANS));
+        set_wallet_canister(principal(WALLET));
+        
+        act_as(LOANS);
+        receive_income(TreasuryIncomeSource::Fees, 1_000.0, None);
+    }
+    
+    fn pay(amount: f64, kind: PayoutKind, reference: Option<&str>) -> Result<TreasuryTx, String> {
+        act_as(GOVERNANCE);
+        let description = "Payout".to_string();
+        block_on(pay_out(principal(MEMBER), amount, kind, reference.map(String::from), description))
+    }
+    
+    #[test]
+    fn income_is_reported_by_source() {
+        setup();
+        receive_income(TreasuryIncomeSource::InterestSpread, 60.0, None);
+        receive_income(TreasuryIncomeSource::Fees, 10.0, Some("LOAN-1".to_string()));
+        
+        let report = get_treasury_report();
+        assert_eq!(report.treasury.balance, 1_070.0);
+        assert_eq!(report.available, 1_070.0);
+        let fees = report.income_by_source.iter().find(|i| i.source == TreasuryIncomeSource::Fees);
+        assert_eq!(fees.map(|i| i.amount), Some(1_010.0));
+        assert_eq!(get_treasury_transactions(1, 10).len(), 2);
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the loans canister can pay income into the treasury")]
+    fn only_the_loans_canister_pays_in_income() {
+        setup();
+        
+        act_as(ADMIN);
+        receive_income(TreasuryIncomeSource::Fees, 100.0, None);
+    }
+    
+    #[test]
+    fn contributions_are_debited_from_the_member_wallet() {
+        setup();
+        
+        act_as(MEMBER);
+        block_on(contribute(250.0));
+        assert_eq!(wallet_calls(), vec![("debit_account".to_string(), principal(MEMBER), 250.0)]);
+        let report = get_treasury_report();
+        assert_eq!(report.treasury.balance, 1_250.0);
+        let contributions = report.income_by_source.iter().find(|i| i.source == TreasuryIncomeSource::Contributions);
+        assert_eq!(contributions.map(|i| i.amount), Some(250.0));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Call rejected (CanisterReject): Rejected by test")]
+    fn contributions_the_wallet_refuses_are_not_counted() {
+        setup();
+        reject_calls(true);
+        
+        act_as(MEMBER);
+        block_on(contribute(250.0));
+    }
+    
+    #[test]
+    fn reservations_cannot_exceed_the_available_balance() {
+        setup();
+        
+        act_as(GOVERNANCE);
+        assert!(reserve("BUD-0".to_string(), 600.0).is_ok());
+        assert_eq!(
+            reserve("BUD-1".to_string(), 500.0).err().as_deref(),
+            Some("Treasury has 400.00 available but the budget needs 500.00")
+        );
+        assert_eq!(
+            pay(500.0, PayoutKind::Reward, None).err().as_deref(),
+            Some("Treasury has 400.00 available but the payout needs 500.00")
+        );
+    }
+    
+    #[test]
+    #[should_panic(expected = "Only the governance canister can spend from the treasury")]
+    fn only_governance_reserves_funds() {
+        setup();
+        
+        act_as(ADMIN);
+        let _ = reserve("BUD-0".to_string(), 100.0);
+    }
+    
+    #[test]
+    fn payouts_draw_down_their_reservation() {
+        setup();
+        act_as(GOVERNANCE);
+        reserve("BUD-0".to_string(), 500.0).expect("funds are available");
+        
+        pay(300.0, PayoutKind::Budget, Some("BUD-0")).expect("first tranche is paid");
+        assert_eq!(
+            pay(300.0, PayoutKind::Budget, Some("BUD-0")).err().as_deref(),
+            Some("BUD-0 has 200.00 reserved but the payout needs 300.00")
+        );
+        pay(200.0, PayoutKind::Budget, Some("BUD-0")).expect("second tranche is paid");
+        
+        let report = get_treasury_report();
+        assert_eq!(report.treasury.balance, 500.0);
+        assert_eq!(report.treasury.reserved, 0.0);
+        assert_eq!(report.treasury.total_spent, 500.0);
+        assert!(report.reservations.is_empty());
+        assert_eq!(wallet_calls()[0], ("credit_account".to_string(), principal(MEMBER), 300.0));
+    }
+    
+    #[test]
+    fn failed_payouts_are_put_back() {
+        setup();
+        act_as(GOVERNANCE);
+        reserve("BUD-0".to_string(), 500.0).expect("funds are available");
+        reject_calls(true);
+        
+        assert!(pay(300.0, PayoutKind::Budget, Some("BUD-0")).is_err());
+        assert!(pay(100.0, PayoutKind::Reward, None).is_err());
+        let report = get_treasury_report();
+        assert_eq!(report.treasury.balance, 1_000.0);
+        assert_eq!(report.treasury.reserved, 500.0);
+        assert_eq!(report.reservations[0].amount, 500.0);
+        assert_eq!(get_treasury_transactions(0, 10).len(), 1);
+    }
+    
+    #[test]
+    fn rewards_are_credited_as_rewards() {
+        setup();
+        
+        pay(40.0, PayoutKind::Reward, None).expect("reward is paid");
+        assert_eq!(wallet_calls(), vec![("credit_reward".to_string(), principal(MEMBER), 40.0)]);
+        assert_eq!(get_treasury_report().available, 960.0);
+    }
+    
+    #[test]
+    fn released_funds_become_available_again() {
+        setup();
+        act_as(GOVERNANCE);
+        reserve("REWARDS-3".to_string(), 500.0).expect("funds are available");
+        
+        assert_eq!(release_reservation("REWARDS-3".to_string(), 200.0), 300.0);
+        assert_eq!(get_treasury_report().available, 700.0);
+        assert_eq!(release_reservation("REWARDS-3".to_string(), 300.0), 0.0);
+        assert!(get_treasury_report().reservations.is_empty());
+    }
+}
@@ -42,12 +42,29 @@ struct WalletStorage {
     balances: HashMap<Principal, f64>,
     transactions: Vec<TxRecord>,
     next_tx_id: u64,
+    admin: Option<Principal>,
+    governance_canister: Option<Principal>, // Allowed to change the interest rate
+    loans_canister: Option<Principal>, // Moves member funds in and out of the lending pool
+    treasury_canister: Option<Principal>, // Takes in member contributions and pays out budgets and rewards
+    monthly_interest_percent: f64,
 }
 
 thread_local! {
     static STATE: std::cell::RefCell<WalletStorage> = std::cell::RefCell::new(WalletStorage::default());
 }
 
+const DEFAULT_MONTHLY_INTEREST_PERCENT: f64 = 0.5;
+
+#[init]
+fn init() {
+    let caller = ic_cdk::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.admin = Some(caller);
+        state.monthly_interest_percent = DEFAULT_MONTHLY_INTEREST_PERCENT;
+    });
+}
+
 #[query]
 fn get_balance() -> f64 {
     let caller = ic_cdk::caller();
@@ -186,10 +203,12 @@ fn get_transactions() -> Vec<TxRecord> {
 fn calculate_interest() {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
+        let state = &mut *state;
         
-        // Apply 0.5% monthly interest to all balances
+        // Apply the monthly interest rate to all balances
+        let rate = state.monthly_interest_percent / 100.0;
         for (principal, balance) in state.balances.iter_mut() {
-            let interest = *balance * 0.005;
+            let interest = *balance * rate;
             *balance += interest;
             
             // Create interest transaction
@@ -212,6 +231,172 @@ fn calculate_interest() {
     });
 }
 
+fn credit_balance(to: Principal, amount: f64, tx_type: TxType, description: String) -> TxRecord {
+    let caller = ic_cdk::caller();
+    
+    if !(amount > 0.0 && amount.is_finite()) {
+        ic_cdk::trap("Invalid amount");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        
+        let balance = state.balances.entry(to).or_insert(0.0);
+        *balance += amount;
+        
+        let tx_id = state.next_tx_id;
+        state.next_tx_id += 1;
+        
+        let tx = TxRecord {
+            id: format!("TX{}", tx_id),
+            amount,
+            from_principal: caller,
+            to_principal: Some(to),
+            timestamp: time(),
+            tx_type,
+            status: TxStatus::Completed,
+            description: Some(description),
+        };
+        
+        state.transactions.push(tx.clone());
+        tx
+    })
+}
+
+// Pays cooperative funds into a member's wallet: treasury budget tranches
+// released by governance, or capital and earnings leaving the lending pool
+#[update]
+fn credit_account(to: Principal, amount: f64, description: String) -> TxRecord {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let state = state.borrow();
+        if state.treasury_canister != Some(caller) && state.loans_canister != Some(caller) {
+            ic_cdk::trap("Only the treasury or loans canister can credit accounts");
+        }
+    });
+    
+    credit_balance(to, amount, TxType::Transfer, description)
+}
+
+// Pays a governance participation reward claimed as wallet credit, out of
+// the treasury
+#[update]
+fn credit_reward(to: Principal, amount: f64, description: String) -> TxRecord {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        if state.borrow().treasury_canister != Some(caller) {
+            ic_cdk::trap("Only the treasury canister can credit rewards");
+        }
+    });
+    
+    credit_balance(to, amount, TxType::Reward, description)
+}
+
+// Moves a member's savings into the lending pool or the treasury when they
+// contribute to it. Traps if the balance is too low, so the caller records nothing.
+#[update]
+fn debit_account(from: Principal, amount: f64, description: String) -> TxRecord {
+    let caller = ic_cdk::caller();
+    
+    if !(amount > 0.0 && amount.is_finite()) {
+        ic_cdk::trap("Invalid amount");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.loans_canister != Some(caller) && state.treasury_canister != Some(caller) {
+            ic_cdk::trap("Only the loans or treasury canister can debit accounts");
+        }
+        
+        let balance = state.balances.get(&from).cloned().unwrap_or(0.0);
+        if balance < amount {
+            ic_cdk::trap("Insufficient balance");
+        }
+        state.balances.insert(from, balance - amount);
+        
+        let tx_id = state.next_tx_id;
+        state.next_tx_id += 1;
+        
+        let tx = TxRecord {
+            id: format!("TX{}", tx_id),
+            amount,
+            from_principal: from,
+            to_principal: Some(caller),
+            timestamp: time(),
+            tx_type: TxType::Transfer,
+            status: TxStatus::Completed,
+            description: Some(description),
+        };
+        
+        state.transactions.push(tx.clone());
+        tx
+    })
+}
+
+#[query]
+fn get_interest_rate() -> f64 {
+    STATE.with(|state| state.borrow().monthly_interest_percent)
+}
+
+// Only the governance canister may change the rate, once a proposal passes
+#[update]
+fn set_interest_rate(monthly_percent: f64) {
+    let caller = ic_cdk::caller();
+    
+    if !(monthly_percent >= 0.0 && monthly_percent.is_finite()) {
+        ic_cdk::trap("Invalid interest rate");
+    }
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.governance_canister != Some(caller) {
+            ic_cdk::trap("Only the governance canister can change the interest rate");
+        }
+        state.monthly_interest_percent = monthly_percent;
+    });
+}
+
+#[update]
+fn set_governance_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the governance canister");
+        }
+        state.governance_canister = Some(canister_id);
+    });
+}
+
+#[update]
+fn set_loans_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the loans canister");
+        }
+        state.loans_canister = Some(canister_id);
+    });
+}
+
+#[update]
+fn set_treasury_canister(canister_id: Principal) {
+    let caller = ic_cdk::caller();
+    
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.admin != Some(caller) {
+            ic_cdk::trap("Only the administrator can set the treasury canister");
+        }
+        state.treasury_canister = Some(canister_id);
+    });
+}
+
 // Required for candid interface generation
 candid::export_service!();
 #[query(name = "__get_candid_interface_tmp_hack")]
@@ -1,60 +1,427 @@
-
 // This file is generated. Do not modify
 
 import type { Principal } from '@dfinity/principal';
 import type { ActorMethod } from '@dfinity/agent';
 
+export type FinancingModel = { 'Conventional': null } |
+  { 'QardHasan': {
+    'administration_fee': number,
+  } } |
+  { 'Murabaha': {
+    'profit_percent': number,
+  } };
+
+export type FundingSource = { 'DedicatedDeposit': null } |
+  { 'MemberSavings': null };
+
+export interface GroupAllocation {
+  'member': Principal,
+  'amount': number,
+}
+
+export interface GroupRepaymentStatus {
+  'status': LoanStatus,
+  'loan_id': string,
+  'members': Array<MemberRepaymentStatus>,
+  'arrears': number,
+  'paid_to_date': number,
+  'days_past_due': bigint,
+  'due_to_date': number,
+  'group_name': string,
+}
+
+export interface Installment {
+  'status': InstallmentStatus,
+  'fees_due': number,
+  'grace_period_end': bigint,
+  'late_fee_charged': boolean,
+  'principal_paid': number,
+  'charge_paid': number,
+  'penalty_rate': number,
+  'interest_due': number,
+  'penalty_accrued_until': bigint,
+  'number': number,
+  'interest_paid': number,
+  'due_date': bigint,
+  'principal_due': number,
+  'late_fee_percent': number,
+  'charge_due': number,
+  'fees_paid': number,
+}
+
+export type InstallmentStatus = { 'Paid': null } |
+  { 'Overdue': null } |
+  { 'Pending': null };
+
+export interface LatePaymentPolicy {
+  'penalty_rate': number,
+  'grace_period_days': bigint,
+  'late_fee_percent': number,
+}
+
+export interface LendingPool {
+  'profit_distributed': number,
+  'total_capital': number,
+  'interest_distributed': number,
+  'available_liquidity': number,
+  'fee_income': number,
+  'deployed_capital': number,
+  'interest_collected': number,
+  'profit_collected': number,
+  'treasury_income': number,
+  'treasury_transferred': number,
+}
+
 export interface LoanApplication {
   'id': string,
-  'amount': number,
+  'collateral_amount': [] | [number],
   'status': LoanStatus,
-  'credit_score': [] | [number],
-  'term_months': number,
+  'principal': Principal,
+  'financing_charge': number,
   'interest_rate': number,
+  'financing_model': FinancingModel,
+  'term_months': number,
+  'amount': number,
+  'credit_score': [] | [number],
+  'group_name': [] | [string],
+  'purpose': LoanType,
   'approval_date': [] | [bigint],
   'application_date': bigint,
-  'principal': Principal,
-  'purpose': LoanType,
   'monthly_payment': number,
-  'collateral_amount': [] | [number],
 }
 
-export type LoanStatus = { 'PaidOff': null } |
-  { 'Active': null } |
+export interface LoanEvent {
+  'terms': LoanTerms,
+  'actor': Principal,
+  'kind': LoanEventKind,
+  'timestamp': bigint,
+  'previous_terms': [] | [LoanTerms],
+}
+
+export type LoanEventKind = { 'Restructured': {
+    'request': RestructureRequest,
+    'capitalized_arrears': number,
+  } } |
+  { 'Applied': null } |
+  { 'WrittenOff': {
+    'principal': number,
+    'interest': number,
+    'fees': number,
+    'charges': number,
+    'reason': string,
+  } } |
+  { 'GroupMemberAccepted': {
+    'member': Principal,
+  } } |
+  { 'Approved': null } |
+  { 'EarlyPayoff': {
+    'interest_waived': number,
+    'payment_id': string,
+    'amount': number,
+  } } |
   { 'Rejected': null } |
-  { 'Defaulted': null } |
+  { 'Prepayment': {
+    'mode': PrepaymentMode,
+    'payment_id': string,
+    'amount': number,
+  } } |
+  { 'Recovery': {
+    'payment_id': string,
+    'amount': number,
+  } } |
+  { 'Defaulted': {
+    'days_past_due': bigint,
+  } };
+
+export interface LoanPage {
+  'total': bigint,
+  'offset': bigint,
+  'loans': Array<LoanApplication>,
+}
+
+export interface LoanPayment {
+  'id': string,
+  'status': { 'Failed': null } |
+    { 'Completed': null } |
+    { 'Pending': null },
+  'loan_id': string,
+  'principal': Principal,
+  'charge_portion': number,
+  'interest_portion': number,
+  'fees_portion': number,
+  'timestamp': bigint,
+  'principal_portion': number,
+  'amount': number,
+}
+
+export interface LoanProduct {
+  'max_concurrent_loans': number,
+  'eligibility_cap_percent': number,
+  'min_amount': number,
+  'loan_type': LoanType,
+  'interest_rate': number,
+  'financing_model': FinancingModel,
+  'allowed_terms': Array<number>,
+  'max_amount': number,
+}
+
+export interface LoanRate {
+  'loan_type': LoanType,
+  'interest_rate': number,
+}
+
+export type LoanStatus = { 'Active': null } |
+  { 'WrittenOff': null } |
   { 'Approved': null } |
+  { 'PaidOff': null } |
+  { 'Rejected': null } |
+  { 'Defaulted': null } |
   { 'Pending': null };
 
-export type LoanType = { 'Medical': null } |
-  { 'Personal': null } |
-  { 'Housing': null } |
+export interface LoanTerms {
+  'interest_rate': number,
+  'term_months': number,
+  'outstanding_principal': number,
+  'monthly_payment': number,
+}
+
+export type LoanType = { 'Personal': null } |
   { 'Business': null } |
+  { 'Medical': null } |
+  { 'Housing': null } |
   { 'Agriculture': null } |
   { 'Education': null };
 
-export interface LoanPayment {
-  'id': string,
+export interface LoanTypeExposure {
+  'loan_count': bigint,
+  'loan_type': LoanType,
+  'outstanding_principal': number,
+}
+
+export type LoanValidationError = { 'GroupMemberIneligible': {
+    'member': Principal,
+    'error': LoanValidationError,
+  } } |
+  { 'InvalidGroup': {
+    'reason': string,
+  } } |
+  { 'ExceedsEligibility': {
+    'eligible_amount': number,
+  } } |
+  { 'InvalidAmount': null } |
+  { 'AmountBelowMinimum': {
+    'minimum': number,
+  } } |
+  { 'TooManyConcurrentLoans': {
+    'limit': number,
+  } } |
+  { 'TermNotAllowed': {
+    'allowed_terms': Array<number>,
+  } } |
+  { 'AmountAboveMaximum': {
+    'maximum': number,
+  } };
+
+export interface LossAccount {
+  'charges_written_off': number,
+  'principal_written_off': number,
+  'fees_written_off': number,
+  'recoveries': number,
+  'interest_written_off': number,
+}
+
+export interface MemberRepaymentStatus {
+  'member': Principal,
+  'monthly_installment': number,
+  'arrears': number,
+  'paid_to_date': number,
+  'accepted': boolean,
+  'due_to_date': number,
+  'allocated_amount': number,
+}
+
+export interface PayoffQuote {
   'loan_id': string,
-  'amount': number,
-  'status': PaymentStatus,
+  'quoted_at': bigint,
+  'total': number,
+  'outstanding_fees': number,
+  'interest_waived': number,
+  'outstanding_charges': number,
+  'accrued_interest': number,
+  'outstanding_principal': number,
+}
+
+export interface PeriodReport {
+  'period_end': bigint,
+  'interest_income': number,
+  'period_start': bigint,
+  'loans_disbursed': bigint,
+  'amount_disbursed': number,
+}
+
+export interface PoolContribution {
+  'savings_capital': number,
+  'deposit_capital': number,
+  'earnings': number,
+  'earnings_claimed': number,
+  'contributor': Principal,
+}
+
+export interface PoolTxRecord {
+  'id': string,
+  'loan_id': [] | [string],
+  'source': [] | [FundingSource],
   'timestamp': bigint,
-  'principal': Principal,
+  'tx_type': PoolTxType,
+  'amount': number,
+  'contributor': [] | [Principal],
 }
 
-export type PaymentStatus = { 'Completed': null } |
-  { 'Failed': null } |
-  { 'Pending': null };
+export type PoolTxType = { 'Fee': null } |
+  { 'Interest': null } |
+  { 'EarningsClaim': null } |
+  { 'PrincipalRepayment': null } |
+  { 'Recovery': null } |
+  { 'Disbursement': null } |
+  { 'Withdrawal': null } |
+  { 'Contribution': null } |
+  { 'Profit': null } |
+  { 'AdministrationFee': null } |
+  { 'WriteOff': null } |
+  { 'TreasuryTransfer': null };
+
+export interface PortfolioAtRisk {
+  'min_days_past_due': bigint,
+  'loan_count': bigint,
+  'ratio': number,
+  'outstanding_principal': number,
+}
+
+export interface PortfolioSummary {
+  'total_loans': bigint,
+  'outstanding_loans': bigint,
+  'disbursed_loans': bigint,
+  'default_rate': number,
+  'as_of': bigint,
+  'outstanding_by_type': Array<LoanTypeExposure>,
+  'interest_income': number,
+  'fee_income': number,
+  'total_disbursed': number,
+  'portfolio_at_risk': Array<PortfolioAtRisk>,
+  'defaulted_loans': bigint,
+  'charge_income': number,
+  'outstanding_principal': number,
+}
+
+export type PrepaymentMode = { 'ReduceInstallment': null } |
+  { 'ShortenTerm': null };
+
+export interface ProvisionBucket {
+  'min_days_past_due': bigint,
+  'reserve_percent': number,
+  'loan_count': bigint,
+  'required_reserve': number,
+  'outstanding_principal': number,
+}
+
+export interface ProvisioningPolicy {
+  'rules': Array<ProvisioningRule>,
+}
+
+export interface ProvisioningReport {
+  'as_of': bigint,
+  'loss_account': LossAccount,
+  'total_required_reserve': number,
+  'buckets': Array<ProvisionBucket>,
+}
+
+export interface ProvisioningRule {
+  'min_days_past_due': bigint,
+  'reserve_percent': number,
+}
+
+export type ReportPeriod = { 'Day': null } |
+  { 'Week': null } |
+  { 'Month': null };
+
+export interface RestructureRequest {
+  'new_interest_rate': [] | [number],
+  'payment_holiday_months': number,
+  'capitalize_arrears': boolean,
+  'extend_term_months': number,
+  'reason': string,
+}
+
+export type StaffRole = { 'Auditor': null } |
+  { 'Admin': null } |
+  { 'LoanOfficer': null };
+
+export interface WriteOff {
+  'loan_id': string,
+  'principal': number,
+  'interest': number,
+  'recovered': number,
+  'fees': number,
+  'approved_by': Principal,
+  'written_off_at': bigint,
+  'charges': number,
+  'reason': string,
+}
 
 export interface _SERVICE {
-  'apply_for_loan': ActorMethod<[number, number, LoanType], LoanApplication>,
-  'approve_loan': ActorMethod<[string], LoanApplication>,
+  'accept_group_loan': ActorMethod<[string], { 'Ok': LoanApplication } |
+    { 'Err': LoanValidationError }>,
+  'apply_for_group_loan': ActorMethod<[string, Array<GroupAllocation>, number, LoanType], { 'Ok': LoanApplication } |
+    { 'Err': LoanValidationError }>,
+  'apply_for_loan': ActorMethod<[number, number, LoanType], { 'Ok': LoanApplication } |
+    { 'Err': LoanValidationError }>,
+  'approve_loan': ActorMethod<[string], { 'Ok': LoanApplication } |
+    { 'Err': string }>,
   'calculate_eligibility': ActorMethod<[], number>,
+  'claim_pool_earnings': ActorMethod<[], { 'Ok': PoolTxRecord } |
+    { 'Err': string }>,
+  'contribute_to_pool': ActorMethod<[number, FundingSource], PoolTxRecord>,
+  'get_disbursement_report': ActorMethod<[bigint, bigint, ReportPeriod], Array<PeriodReport>>,
+  'get_group_repayment_status': ActorMethod<[string], GroupRepaymentStatus>,
+  'get_interest_spread': ActorMethod<[], number>,
+  'get_late_payment_policy': ActorMethod<[], LatePaymentPolicy>,
+  'get_lending_pool': ActorMethod<[], LendingPool>,
   'get_loan_details': ActorMethod<[string], [] | [LoanApplication]>,
+  'get_loan_history': ActorMethod<[string], Array<LoanEvent>>,
+  'get_loan_products': ActorMethod<[], Array<LoanProduct>>,
   'get_loans': ActorMethod<[], Array<LoanApplication>>,
   'get_payments': ActorMethod<[string], Array<LoanPayment>>,
-  'make_payment': ActorMethod<[string, number], LoanPayment>,
+  'get_payoff_quote': ActorMethod<[string, bigint], PayoffQuote>,
+  'get_pool_contribution': ActorMethod<[], [] | [PoolContribution]>,
+  'get_pool_transactions': ActorMethod<[bigint, bigint], Array<PoolTxRecord>>,
+  'get_portfolio_summary': ActorMethod<[], PortfolioSummary>,
+  'get_provisioning_policy': ActorMethod<[], ProvisioningPolicy>,
+  'get_provisioning_report': ActorMethod<[], ProvisioningReport>,
+  'get_repayment_schedule': ActorMethod<[string], Array<Installment>>,
+  'get_write_off': ActorMethod<[string], [] | [WriteOff]>,
+  'grant_staff_role': ActorMethod<[Principal, StaffRole], undefined>,
+  'list_loans': ActorMethod<[[] | [LoanStatus], bigint, bigint], LoanPage>,
+  'make_payment': ActorMethod<[string, number], { 'Ok': LoanPayment } |
+    { 'Err': string }>,
+  'make_prepayment': ActorMethod<[string, number, PrepaymentMode], { 'Ok': LoanPayment } |
+    { 'Err': string }>,
+  'mark_loan_defaulted': ActorMethod<[string], LoanApplication>,
   'reject_loan': ActorMethod<[string], LoanApplication>,
+  'restructure_loan': ActorMethod<[string, RestructureRequest], LoanApplication>,
+  'revoke_staff_role': ActorMethod<[Principal], undefined>,
+  'set_governance_canister': ActorMethod<[Principal], undefined>,
+  'set_interest_rates': ActorMethod<[Array<LoanRate>], undefined>,
+  'set_interest_spread': ActorMethod<[number], undefined>,
+  'set_late_payment_policy': ActorMethod<[LatePaymentPolicy], undefined>,
+  'set_loan_product': ActorMethod<[LoanProduct], undefined>,
+  'set_provisioning_policy': ActorMethod<[ProvisioningPolicy], undefined>,
+  'set_treasury_canister': ActorMethod<[Principal], undefined>,
+  'set_wallet_canister': ActorMethod<[Principal], undefined>,
+  'transfer_income_to_treasury': ActorMethod<[], { 'Ok': Array<PoolTxRecord> } |
+    { 'Err': string }>,
+  'withdraw_from_pool': ActorMethod<[number, FundingSource], { 'Ok': PoolTxRecord } |
+    { 'Err': string }>,
+  'write_off_loan': ActorMethod<[string, string], WriteOff>,
 }
 
 export declare const idlFactory: ({ IDL }: { IDL: any }) => any;
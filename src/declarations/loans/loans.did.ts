@@ -1,5 +1,5 @@
-
 export const idlFactory = ({ IDL }) => {
+  const LoanValidationError = IDL.Rec();
   const LoanStatus = IDL.Variant({
     'Pending': IDL.Null,
     'Approved': IDL.Null,
@@ -7,6 +7,7 @@ export const idlFactory = ({ IDL }) => {
     'Active': IDL.Null,
     'PaidOff': IDL.Null,
     'Defaulted': IDL.Null,
+    'WrittenOff': IDL.Null,
   });
   
   const LoanType = IDL.Variant({
@@ -18,6 +19,58 @@ export const idlFactory = ({ IDL }) => {
     'Medical': IDL.Null,
   });
   
+  const FinancingModel = IDL.Variant({
+    'Conventional': IDL.Null,
+    'QardHasan': IDL.Record({
+      'administration_fee': IDL.Float64,
+    }),
+    'Murabaha': IDL.Record({
+      'profit_percent': IDL.Float64,
+    }),
+  });
+  
+  const LoanRate = IDL.Record({
+    'loan_type': LoanType,
+    'interest_rate': IDL.Float64,
+  });
+  
+  const LoanProduct = IDL.Record({
+    'loan_type': LoanType,
+    'financing_model': FinancingModel,
+    'interest_rate': IDL.Float64,
+    'min_amount': IDL.Float64,
+    'max_amount': IDL.Float64,
+    'allowed_terms': IDL.Vec(IDL.Nat8),
+    'max_concurrent_loans': IDL.Nat8,
+    'eligibility_cap_percent': IDL.Float64,
+  });
+  
+  LoanValidationError.fill(IDL.Variant({
+    'InvalidAmount': IDL.Null,
+    'AmountBelowMinimum': IDL.Record({
+      'minimum': IDL.Float64,
+    }),
+    'AmountAboveMaximum': IDL.Record({
+      'maximum': IDL.Float64,
+    }),
+    'TermNotAllowed': IDL.Record({
+      'allowed_terms': IDL.Vec(IDL.Nat8),
+    }),
+    'TooManyConcurrentLoans': IDL.Record({
+      'limit': IDL.Nat8,
+    }),
+    'ExceedsEligibility': IDL.Record({
+      'eligible_amount': IDL.Float64,
+    }),
+    'InvalidGroup': IDL.Record({
+      'reason': IDL.Text,
+    }),
+    'GroupMemberIneligible': IDL.Record({
+      'member': IDL.Principal,
+      'error': LoanValidationError,
+    }),
+  }));
+  
   const LoanApplication = IDL.Record({
     'id': IDL.Text,
     'principal': IDL.Principal,
@@ -31,12 +84,35 @@ export const idlFactory = ({ IDL }) => {
     'collateral_amount': IDL.Opt(IDL.Float64),
     'credit_score': IDL.Opt(IDL.Nat16),
     'monthly_payment': IDL.Float64,
+    'financing_model': FinancingModel,
+    'financing_charge': IDL.Float64,
+    'group_name': IDL.Opt(IDL.Text),
   });
   
-  const PaymentStatus = IDL.Variant({
-    'Pending': IDL.Null,
-    'Completed': IDL.Null,
-    'Failed': IDL.Null,
+  const GroupAllocation = IDL.Record({
+    'member': IDL.Principal,
+    'amount': IDL.Float64,
+  });
+  
+  const MemberRepaymentStatus = IDL.Record({
+    'member': IDL.Principal,
+    'accepted': IDL.Bool,
+    'allocated_amount': IDL.Float64,
+    'monthly_installment': IDL.Float64,
+    'due_to_date': IDL.Float64,
+    'paid_to_date': IDL.Float64,
+    'arrears': IDL.Float64,
+  });
+  
+  const GroupRepaymentStatus = IDL.Record({
+    'loan_id': IDL.Text,
+    'group_name': IDL.Text,
+    'status': LoanStatus,
+    'due_to_date': IDL.Float64,
+    'paid_to_date': IDL.Float64,
+    'arrears': IDL.Float64,
+    'days_past_due': IDL.Nat64,
+    'members': IDL.Vec(MemberRepaymentStatus),
   });
   
   const LoanPayment = IDL.Record({
@@ -44,20 +120,346 @@ export const idlFactory = ({ IDL }) => {
     'loan_id': IDL.Text,
     'principal': IDL.Principal,
     'amount': IDL.Float64,
+    'fees_portion': IDL.Float64,
+    'interest_portion': IDL.Float64,
+    'charge_portion': IDL.Float64,
+    'principal_portion': IDL.Float64,
     'timestamp': IDL.Nat64,
-    'status': PaymentStatus,
+    'status': IDL.Variant({
+      'Pending': IDL.Null,
+      'Completed': IDL.Null,
+      'Failed': IDL.Null,
+    }),
+  });
+  
+  const InstallmentStatus = IDL.Variant({
+    'Pending': IDL.Null,
+    'Overdue': IDL.Null,
+    'Paid': IDL.Null,
+  });
+  
+  const Installment = IDL.Record({
+    'number': IDL.Nat8,
+    'due_date': IDL.Nat64,
+    'grace_period_end': IDL.Nat64,
+    'principal_due': IDL.Float64,
+    'interest_due': IDL.Float64,
+    'charge_due': IDL.Float64,
+    'fees_due': IDL.Float64,
+    'principal_paid': IDL.Float64,
+    'interest_paid': IDL.Float64,
+    'charge_paid': IDL.Float64,
+    'fees_paid': IDL.Float64,
+    'late_fee_charged': IDL.Bool,
+    'penalty_accrued_until': IDL.Nat64,
+    'late_fee_percent': IDL.Float64,
+    'penalty_rate': IDL.Float64,
+    'status': InstallmentStatus,
+  });
+  
+  const LatePaymentPolicy = IDL.Record({
+    'grace_period_days': IDL.Nat64,
+    'late_fee_percent': IDL.Float64,
+    'penalty_rate': IDL.Float64,
+  });
+  
+  const ProvisioningRule = IDL.Record({
+    'min_days_past_due': IDL.Nat64,
+    'reserve_percent': IDL.Float64,
+  });
+  
+  const ProvisioningPolicy = IDL.Record({
+    'rules': IDL.Vec(ProvisioningRule),
+  });
+  
+  const ProvisionBucket = IDL.Record({
+    'min_days_past_due': IDL.Nat64,
+    'reserve_percent': IDL.Float64,
+    'loan_count': IDL.Nat64,
+    'outstanding_principal': IDL.Float64,
+    'required_reserve': IDL.Float64,
+  });
+  
+  const LossAccount = IDL.Record({
+    'principal_written_off': IDL.Float64,
+    'interest_written_off': IDL.Float64,
+    'charges_written_off': IDL.Float64,
+    'fees_written_off': IDL.Float64,
+    'recoveries': IDL.Float64,
+  });
+  
+  const ProvisioningReport = IDL.Record({
+    'as_of': IDL.Nat64,
+    'buckets': IDL.Vec(ProvisionBucket),
+    'total_required_reserve': IDL.Float64,
+    'loss_account': LossAccount,
+  });
+  
+  const WriteOff = IDL.Record({
+    'loan_id': IDL.Text,
+    'written_off_at': IDL.Nat64,
+    'approved_by': IDL.Principal,
+    'reason': IDL.Text,
+    'principal': IDL.Float64,
+    'interest': IDL.Float64,
+    'charges': IDL.Float64,
+    'fees': IDL.Float64,
+    'recovered': IDL.Float64,
+  });
+  
+  const PrepaymentMode = IDL.Variant({
+    'ShortenTerm': IDL.Null,
+    'ReduceInstallment': IDL.Null,
+  });
+  
+  const PayoffQuote = IDL.Record({
+    'loan_id': IDL.Text,
+    'quoted_at': IDL.Nat64,
+    'outstanding_principal': IDL.Float64,
+    'accrued_interest': IDL.Float64,
+    'outstanding_charges': IDL.Float64,
+    'outstanding_fees': IDL.Float64,
+    'interest_waived': IDL.Float64,
+    'total': IDL.Float64,
+  });
+  
+  const RestructureRequest = IDL.Record({
+    'extend_term_months': IDL.Nat8,
+    'payment_holiday_months': IDL.Nat8,
+    'capitalize_arrears': IDL.Bool,
+    'new_interest_rate': IDL.Opt(IDL.Float64),
+    'reason': IDL.Text,
+  });
+  
+  const LoanTerms = IDL.Record({
+    'term_months': IDL.Nat8,
+    'interest_rate': IDL.Float64,
+    'monthly_payment': IDL.Float64,
+    'outstanding_principal': IDL.Float64,
+  });
+  
+  const LoanEventKind = IDL.Variant({
+    'Applied': IDL.Null,
+    'GroupMemberAccepted': IDL.Record({
+      'member': IDL.Principal,
+    }),
+    'Approved': IDL.Null,
+    'Rejected': IDL.Null,
+    'Prepayment': IDL.Record({
+      'payment_id': IDL.Text,
+      'amount': IDL.Float64,
+      'mode': PrepaymentMode,
+    }),
+    'EarlyPayoff': IDL.Record({
+      'payment_id': IDL.Text,
+      'amount': IDL.Float64,
+      'interest_waived': IDL.Float64,
+    }),
+    'Restructured': IDL.Record({
+      'request': RestructureRequest,
+      'capitalized_arrears': IDL.Float64,
+    }),
+    'Defaulted': IDL.Record({
+      'days_past_due': IDL.Nat64,
+    }),
+    'WrittenOff': IDL.Record({
+      'reason': IDL.Text,
+      'principal': IDL.Float64,
+      'interest': IDL.Float64,
+      'charges': IDL.Float64,
+      'fees': IDL.Float64,
+    }),
+    'Recovery': IDL.Record({
+      'payment_id': IDL.Text,
+      'amount': IDL.Float64,
+    }),
+  });
+  
+  const LoanEvent = IDL.Record({
+    'timestamp': IDL.Nat64,
+    'actor': IDL.Principal,
+    'kind': LoanEventKind,
+    'previous_terms': IDL.Opt(LoanTerms),
+    'terms': LoanTerms,
+  });
+  
+  const FundingSource = IDL.Variant({
+    'MemberSavings': IDL.Null,
+    'DedicatedDeposit': IDL.Null,
+  });
+  
+  const LendingPool = IDL.Record({
+    'total_capital': IDL.Float64,
+    'available_liquidity': IDL.Float64,
+    'deployed_capital': IDL.Float64,
+    'interest_collected': IDL.Float64,
+    'interest_distributed': IDL.Float64,
+    'profit_collected': IDL.Float64,
+    'profit_distributed': IDL.Float64,
+    'fee_income': IDL.Float64,
+    'treasury_income': IDL.Float64,
+    'treasury_transferred': IDL.Float64,
+  });
+  
+  const PoolContribution = IDL.Record({
+    'contributor': IDL.Principal,
+    'savings_capital': IDL.Float64,
+    'deposit_capital': IDL.Float64,
+    'earnings': IDL.Float64,
+    'earnings_claimed': IDL.Float64,
+  });
+  
+  const PoolTxType = IDL.Variant({
+    'Contribution': IDL.Null,
+    'Withdrawal': IDL.Null,
+    'Disbursement': IDL.Null,
+    'PrincipalRepayment': IDL.Null,
+    'Interest': IDL.Null,
+    'Profit': IDL.Null,
+    'AdministrationFee': IDL.Null,
+    'Fee': IDL.Null,
+    'EarningsClaim': IDL.Null,
+    'WriteOff': IDL.Null,
+    'Recovery': IDL.Null,
+    'TreasuryTransfer': IDL.Null,
+  });
+  
+  const PoolTxRecord = IDL.Record({
+    'id': IDL.Text,
+    'tx_type': PoolTxType,
+    'amount': IDL.Float64,
+    'timestamp': IDL.Nat64,
+    'contributor': IDL.Opt(IDL.Principal),
+    'source': IDL.Opt(FundingSource),
+    'loan_id': IDL.Opt(IDL.Text),
+  });
+  
+  const StaffRole = IDL.Variant({
+    'Admin': IDL.Null,
+    'Auditor': IDL.Null,
+    'LoanOfficer': IDL.Null,
+  });
+  
+  const ReportPeriod = IDL.Variant({
+    'Day': IDL.Null,
+    'Week': IDL.Null,
+    'Month': IDL.Null,
+  });
+  
+  const LoanTypeExposure = IDL.Record({
+    'loan_type': LoanType,
+    'loan_count': IDL.Nat64,
+    'outstanding_principal': IDL.Float64,
+  });
+  
+  const PortfolioAtRisk = IDL.Record({
+    'min_days_past_due': IDL.Nat64,
+    'loan_count': IDL.Nat64,
+    'outstanding_principal': IDL.Float64,
+    'ratio': IDL.Float64,
+  });
+  
+  const PortfolioSummary = IDL.Record({
+    'as_of': IDL.Nat64,
+    'total_loans': IDL.Nat64,
+    'disbursed_loans': IDL.Nat64,
+    'outstanding_loans': IDL.Nat64,
+    'defaulted_loans': IDL.Nat64,
+    'total_disbursed': IDL.Float64,
+    'outstanding_principal': IDL.Float64,
+    'outstanding_by_type': IDL.Vec(LoanTypeExposure),
+    'portfolio_at_risk': IDL.Vec(PortfolioAtRisk),
+    'default_rate': IDL.Float64,
+    'interest_income': IDL.Float64,
+    'charge_income': IDL.Float64,
+    'fee_income': IDL.Float64,
+  });
+  
+  const PeriodReport = IDL.Record({
+    'period_start': IDL.Nat64,
+    'period_end': IDL.Nat64,
+    'loans_disbursed': IDL.Nat64,
+    'amount_disbursed': IDL.Float64,
+    'interest_income': IDL.Float64,
+  });
+  
+  const LoanPage = IDL.Record({
+    'loans': IDL.Vec(LoanApplication),
+    'total': IDL.Nat64,
+    'offset': IDL.Nat64,
   });
   
   return IDL.Service({
-    'apply_for_loan': IDL.Func([IDL.Float64, IDL.Nat8, LoanType], [LoanApplication], []),
+    'apply_for_loan': IDL.Func([IDL.Float64, IDL.Nat8, LoanType], [IDL.Variant({
+      'Ok': LoanApplication,
+      'Err': LoanValidationError,
+    })], []),
+    'apply_for_group_loan': IDL.Func([IDL.Text, IDL.Vec(GroupAllocation), IDL.Nat8, LoanType], [IDL.Variant({
+      'Ok': LoanApplication,
+      'Err': LoanValidationError,
+    })], []),
+    'accept_group_loan': IDL.Func([IDL.Text], [IDL.Variant({
+      'Ok': LoanApplication,
+      'Err': LoanValidationError,
+    })], []),
+    'get_group_repayment_status': IDL.Func([IDL.Text], [GroupRepaymentStatus], ['query']),
     'get_loans': IDL.Func([], [IDL.Vec(LoanApplication)], ['query']),
     'get_loan_details': IDL.Func([IDL.Text], [IDL.Opt(LoanApplication)], ['query']),
-    'approve_loan': IDL.Func([IDL.Text], [LoanApplication], []),
+    'approve_loan': IDL.Func([IDL.Text], [IDL.Variant({
+      'Ok': LoanApplication,
+      'Err': IDL.Text,
+    })], []),
     'reject_loan': IDL.Func([IDL.Text], [LoanApplication], []),
-    'make_payment': IDL.Func([IDL.Text, IDL.Float64], [LoanPayment], []),
+    'make_payment': IDL.Func([IDL.Text, IDL.Float64], [IDL.Variant({
+      'Ok': LoanPayment,
+      'Err': IDL.Text,
+    })], []),
     'get_payments': IDL.Func([IDL.Text], [IDL.Vec(LoanPayment)], ['query']),
+    'get_payoff_quote': IDL.Func([IDL.Text, IDL.Nat64], [PayoffQuote], ['query']),
+    'make_prepayment': IDL.Func([IDL.Text, IDL.Float64, PrepaymentMode], [IDL.Variant({
+      'Ok': LoanPayment,
+      'Err': IDL.Text,
+    })], []),
+    'restructure_loan': IDL.Func([IDL.Text, RestructureRequest], [LoanApplication], []),
+    'get_loan_history': IDL.Func([IDL.Text], [IDL.Vec(LoanEvent)], ['query']),
+    'get_repayment_schedule': IDL.Func([IDL.Text], [IDL.Vec(Installment)], ['query']),
+    'get_late_payment_policy': IDL.Func([], [LatePaymentPolicy], ['query']),
+    'set_late_payment_policy': IDL.Func([LatePaymentPolicy], [], []),
     'calculate_eligibility': IDL.Func([], [IDL.Float64], ['query']),
+    'get_loan_products': IDL.Func([], [IDL.Vec(LoanProduct)], ['query']),
+    'set_loan_product': IDL.Func([LoanProduct], [], []),
+    'set_interest_rates': IDL.Func([IDL.Vec(LoanRate)], [], []),
+    'grant_staff_role': IDL.Func([IDL.Principal, StaffRole], [], []),
+    'revoke_staff_role': IDL.Func([IDL.Principal], [], []),
+    'set_wallet_canister': IDL.Func([IDL.Principal], [], []),
+    'set_governance_canister': IDL.Func([IDL.Principal], [], []),
+    'set_treasury_canister': IDL.Func([IDL.Principal], [], []),
+    'get_interest_spread': IDL.Func([], [IDL.Float64], ['query']),
+    'set_interest_spread': IDL.Func([IDL.Float64], [], []),
+    'list_loans': IDL.Func([IDL.Opt(LoanStatus), IDL.Nat64, IDL.Nat64], [LoanPage], ['query']),
+    'get_portfolio_summary': IDL.Func([], [PortfolioSummary], ['query']),
+    'get_disbursement_report': IDL.Func([IDL.Nat64, IDL.Nat64, ReportPeriod], [IDL.Vec(PeriodReport)], ['query']),
+    'contribute_to_pool': IDL.Func([IDL.Float64, FundingSource], [PoolTxRecord], []),
+    'withdraw_from_pool': IDL.Func([IDL.Float64, FundingSource], [IDL.Variant({
+      'Ok': PoolTxRecord,
+      'Err': IDL.Text,
+    })], []),
+    'claim_pool_earnings': IDL.Func([], [IDL.Variant({
+      'Ok': PoolTxRecord,
+      'Err': IDL.Text,
+    })], []),
+    'transfer_income_to_treasury': IDL.Func([], [IDL.Variant({
+      'Ok': IDL.Vec(PoolTxRecord),
+      'Err': IDL.Text,
+    })], []),
+    'get_lending_pool': IDL.Func([], [LendingPool], ['query']),
+    'get_pool_contribution': IDL.Func([], [IDL.Opt(PoolContribution)], ['query']),
+    'get_pool_transactions': IDL.Func([IDL.Nat64, IDL.Nat64], [IDL.Vec(PoolTxRecord)], ['query']),
+    'mark_loan_defaulted': IDL.Func([IDL.Text], [LoanApplication], []),
+    'write_off_loan': IDL.Func([IDL.Text, IDL.Text], [WriteOff], []),
+    'get_write_off': IDL.Func([IDL.Text], [IDL.Opt(WriteOff)], ['query']),
+    'get_provisioning_report': IDL.Func([], [ProvisioningReport], ['query']),
+    'get_provisioning_policy': IDL.Func([], [ProvisioningPolicy], ['query']),
+    'set_provisioning_policy': IDL.Func([ProvisioningPolicy], [], []),
   });
 };
-
-export const init = ({ IDL }) => { return []; };
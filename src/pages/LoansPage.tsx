@@ -90,6 +90,9 @@ const LoansPage = () => {
         parseInt(loanDuration),
         loanPurpose
       );
+      if (!result) {
+        throw new Error("The loan application was not accepted");
+      }
       
       toast({
         title: "Loan Application Submitted",
@@ -106,7 +109,9 @@ const LoansPage = () => {
       console.error("Error applying for loan:", error);
       toast({
         title: "Application Failed",
-        description: "There was an error submitting your loan application. Please try again.",
+        description: error instanceof Error
+          ? error.message
+          : "There was an error submitting your loan application. Please try again.",
         variant: "destructive",
       });
     } finally {
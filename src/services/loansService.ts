@@ -10,7 +10,8 @@ export enum LoanStatus {
   Rejected = 'Rejected',
   Active = 'Active',
   PaidOff = 'PaidOff',
-  Defaulted = 'Defaulted'
+  Defaulted = 'Defaulted',
+  WrittenOff = 'WrittenOff'
 }
 
 export enum LoanType {
@@ -67,6 +68,22 @@ export interface LoanPayment {
   status: 'pending' | 'completed' | 'failed';
 }
 
+// Turn a loan validation error from the canister into a readable message
+const describeValidationError = (error: any): string => {
+  if ('InvalidAmount' in error) return "The loan amount must be greater than zero";
+  if ('AmountBelowMinimum' in error) return `The minimum amount for this loan is ${error.AmountBelowMinimum.minimum}`;
+  if ('AmountAboveMaximum' in error) return `The maximum amount for this loan is ${error.AmountAboveMaximum.maximum}`;
+  if ('TermNotAllowed' in error) return `The term must be one of ${Array.from(error.TermNotAllowed.allowed_terms).join(', ')} months`;
+  if ('TooManyConcurrentLoans' in error) return `You can have at most ${error.TooManyConcurrentLoans.limit} open loans of this type`;
+  if ('ExceedsEligibility' in error) return `You are eligible for at most ${error.ExceedsEligibility.eligible_amount}`;
+  if ('InvalidGroup' in error) return error.InvalidGroup.reason;
+  if ('GroupMemberIneligible' in error) {
+    const { member, error: memberError } = error.GroupMemberIneligible;
+    return `Group member ${member.toString()} is not eligible: ${describeValidationError(memberError)}`;
+  }
+  return "The loan application is not valid";
+};
+
 // Constants
 const loansCanisterId = import.meta.env.VITE_LOANS_CANISTER_ID || "r7inp-6aaaa-aaaaa-aaabq-cai";
 
@@ -136,10 +153,14 @@ export const useLoansService = () => {
     try {
       setIsLoading(true);
       const api = await getAuthenticatedApi();
-      const result = await api.loansActor.apply_for_loan(amount, term_months, purpose);
+      const response = await api.loansActor.apply_for_loan(amount, term_months, purpose);
       
-      if (!result) return null;
+      if (!response) return null;
+      if ('Err' in response) {
+        throw new Error(describeValidationError(response.Err));
+      }
       
+      const result = response.Ok;
       return {
         id: result.id,
         principal: result.principal.toString(),
@@ -162,8 +183,10 @@ export const useLoansService = () => {
       };
     } catch (err) {
       console.error("Error applying for loan:", err);
-      setError(err instanceof Error ? err : new Error(String(err)));
-      return null;
+      const error = err instanceof Error ? err : new Error(String(err));
+      setError(error);
+      // Rethrown so the page can show why the application was rejected
+      throw error;
     } finally {
       setIsLoading(false);
     }
@@ -215,8 +238,15 @@ export const useLoansService = () => {
     try {
       setIsLoading(true);
       const api = await getAuthenticatedApi();
-      const result = await api.loansActor.make_payment(loanId, amount);
-      return result ? {
+      const response = await api.loansActor.make_payment(loanId, amount);
+      
+      if (!response) return null;
+      if ('Err' in response) {
+        throw new Error(response.Err);
+      }
+      
+      const result = response.Ok;
+      return {
         id: result.id,
         loan_id: result.loan_id,
         principal: result.principal.toString(),
@@ -224,7 +254,7 @@ export const useLoansService = () => {
         timestamp: result.timestamp,
         status: result.status.completed ? 'completed' : 
                 result.status.pending ? 'pending' : 'failed'
-      } : null;
+      };
     } catch (err) {
       console.error("Error making loan payment:", err);
       setError(err instanceof Error ? err : new Error(String(err)));